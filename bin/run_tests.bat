@@ -1,3 +1,3 @@
 set RUST_BACKTRACE=1
 
-cargo test -- --test-threads=1
\ No newline at end of file
+cargo test
\ No newline at end of file
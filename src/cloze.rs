@@ -0,0 +1,203 @@
+use std::error::Error;
+
+use crate::make_error;
+
+// a cloze is text with parts marked for deletion, like
+// "{{c1::Paris}} is the capital of {{c2::France}}". each number becomes its
+// own exercise, with the parts marked with that number hidden and everything
+// else shown. "{{c1::Paris::a city}}" shows "[a city]" in place of the hidden
+// part instead of "[...]".
+
+#[derive(Debug, PartialEq)]
+pub struct ClozeDeletion {
+    pub number: i32,
+    pub description: String,
+    // what was hidden, in the order it appears
+    pub answer: String,
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    Deletion {
+        number: i32,
+        text: &'a str,
+        hint: Option<&'a str>,
+    },
+}
+
+// where a deletion starts and the number it has, if one starts right at the
+// beginning of s
+fn deletion_start(s: &str) -> Option<(i32, usize)> {
+    let rest = s.strip_prefix("{{c")?;
+    let digits = rest.chars().take_while(char::is_ascii_digit).count();
+    let rest = &rest[digits..];
+
+    if digits == 0 || !rest.starts_with("::") {
+        return None;
+    }
+
+    let number = s[3..3 + digits].parse().ok()?;
+
+    Some((number, 3 + digits + 2))
+}
+
+fn parse(text: &str) -> Result<Vec<Segment<'_>>, Box<dyn Error>> {
+    let mut segments = vec![];
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{c") {
+        let (number, content_start) = match deletion_start(&rest[start..]) {
+            Some(deletion) => deletion,
+            None => {
+                // not a deletion after all, so keep it as it is
+                segments.push(Segment::Text(&rest[..start + 3]));
+                rest = &rest[start + 3..];
+                continue;
+            }
+        };
+
+        if start > 0 {
+            segments.push(Segment::Text(&rest[..start]));
+        }
+
+        let content = &rest[start + content_start..];
+        let end = match content.find("}}") {
+            Some(end) => end,
+            None => {
+                return Err(make_error(format!(
+                    "Cloze deletion c{} is missing its closing '}}}}'.",
+                    number
+                )))
+            }
+        };
+
+        if number < 1 {
+            return Err(make_error(
+                "Cloze deletions are numbered starting from c1.".to_string(),
+            ));
+        }
+
+        let (text, hint) = match content[..end].find("::") {
+            Some(i) => (&content[..i], Some(content[i + 2..end].trim())),
+            None => (&content[..end], None),
+        };
+
+        if text.trim().is_empty() {
+            return Err(make_error(format!("Cloze deletion c{} is empty.", number)));
+        }
+
+        segments.push(Segment::Deletion { number, text, hint });
+        rest = &content[end + 2..];
+    }
+
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+
+    Ok(segments)
+}
+
+pub fn has_deletions(text: &str) -> bool {
+    text.match_indices("{{c")
+        .any(|(i, _)| deletion_start(&text[i..]).is_some())
+}
+
+// one entry per deletion number, lowest first
+pub fn expand(text: &str) -> Result<Vec<ClozeDeletion>, Box<dyn Error>> {
+    let segments = parse(text)?;
+
+    let mut numbers = segments
+        .iter()
+        .filter_map(|segment| match segment {
+            Segment::Deletion { number, .. } => Some(*number),
+            Segment::Text(_) => None,
+        })
+        .collect::<Vec<_>>();
+    numbers.sort();
+    numbers.dedup();
+
+    Ok(numbers
+        .into_iter()
+        .map(|hidden_number| {
+            let mut description = String::new();
+            let mut answer = vec![];
+
+            for segment in &segments {
+                match segment {
+                    Segment::Text(text) => description.push_str(text),
+                    Segment::Deletion { number, text, hint } if *number == hidden_number => {
+                        description.push_str(&format!("[{}]", hint.unwrap_or("...")));
+                        answer.push(text.trim());
+                    }
+                    Segment::Deletion { text, .. } => description.push_str(text),
+                }
+            }
+
+            ClozeDeletion {
+                number: hidden_number,
+                description,
+                answer: answer.join(", "),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let deletions =
+            expand("{{c1::Paris}} is the capital of {{c2::France::a country}}.").unwrap();
+
+        assert_eq!(
+            deletions,
+            vec![
+                ClozeDeletion {
+                    number: 1,
+                    description: "[...] is the capital of France.".to_string(),
+                    answer: "Paris".to_string(),
+                },
+                ClozeDeletion {
+                    number: 2,
+                    description: "Paris is the capital of [a country].".to_string(),
+                    answer: "France".to_string(),
+                },
+            ]
+        );
+
+        // the same number twice hides both at once
+        let deletions = expand("{{c2::a}}, {{c1::b}}, {{c2::c}}").unwrap();
+        assert_eq!(deletions.len(), 2);
+        assert_eq!(deletions[0].description, "a, [...], c");
+        assert_eq!(deletions[1].description, "[...], b, [...]");
+        assert_eq!(deletions[1].answer, "a, c");
+
+        assert!(expand("no deletions here").unwrap().is_empty());
+        assert!(expand("{{c}} and {{cat::dog}}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_has_deletions() {
+        assert!(has_deletions("foo {{c1::bar}}"));
+        assert!(has_deletions("{{c12::bar"));
+        assert!(!has_deletions("foo {{bar}}"));
+        assert!(!has_deletions("{{c::bar}}"));
+    }
+
+    #[test]
+    fn test_invalid_clozes() {
+        let error = |s| format!("{}", expand(s).unwrap_err());
+
+        assert_eq!(
+            error("{{c1::Paris is the capital"),
+            "Cloze deletion c1 is missing its closing '}}'."
+        );
+        assert_eq!(error("{{c1:: }} foo"), "Cloze deletion c1 is empty.");
+        assert_eq!(
+            error("{{c0::foo}}"),
+            "Cloze deletions are numbered starting from c1."
+        );
+    }
+}
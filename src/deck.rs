@@ -0,0 +1,141 @@
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use toml::value::{Table, Value};
+
+use crate::{make_error, SchedulingConfig};
+
+// a [decks.<name>] section of config.toml, once [scheduling] has been filled
+// in for whatever it leaves out
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeckConfig {
+    // how long 'review --deck' lasts when no number of minutes is given
+    pub time_box_minutes: Option<i64>,
+    pub scheduling: SchedulingConfig,
+}
+
+#[derive(Deserialize)]
+struct DeckSection {
+    time_box_minutes: Option<i64>,
+    #[serde(default)]
+    scheduling: Table,
+}
+
+// reads every [decks.<name>] section out of the whole config file. a deck's
+// [decks.<name>.scheduling] only needs the settings that differ from
+// [scheduling].
+pub fn deck_configs(config: &Value) -> Result<BTreeMap<String, DeckConfig>, Box<dyn Error>> {
+    let scheduling = config
+        .get("scheduling")
+        .and_then(Value::as_table)
+        .cloned()
+        .unwrap_or_default();
+
+    let sections: BTreeMap<String, DeckSection> = match config.get("decks") {
+        Some(decks) => decks.clone().try_into()?,
+        None => BTreeMap::new(),
+    };
+
+    let mut decks = BTreeMap::new();
+
+    for (name, section) in sections {
+        let mut deck_scheduling = scheduling.clone();
+        deck_scheduling.extend(section.scheduling);
+
+        let deck_scheduling: SchedulingConfig = Value::Table(deck_scheduling)
+            .try_into()
+            .map_err(|e| make_error(format!("Deck {}: {}", name, e)))?;
+        deck_scheduling
+            .validate()
+            .map_err(|e| make_error(format!("Deck {}: {}", name, e)))?;
+
+        if let Some(time_box_minutes) = section.time_box_minutes {
+            if time_box_minutes < 1 {
+                return Err(make_error(format!(
+                    "Deck {}: time_box_minutes must be at least 1, got {}",
+                    name, time_box_minutes
+                )));
+            }
+        }
+
+        decks.insert(
+            name,
+            DeckConfig {
+                time_box_minutes: section.time_box_minutes,
+                scheduling: deck_scheduling,
+            },
+        );
+    }
+
+    Ok(decks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::SchedulerKind;
+
+    fn parse(s: &str) -> Result<BTreeMap<String, DeckConfig>, Box<dyn Error>> {
+        deck_configs(&toml::from_str(s).unwrap())
+    }
+
+    #[test]
+    fn test_deck_configs() {
+        let decks = parse(
+            "[scheduling]
+            max_interval = 180
+            new_per_day = 10
+
+            [decks.math]
+            time_box_minutes = 30
+
+            [decks.math.scheduling]
+            scheduler = \"sm2\"
+            new_per_day = 5
+
+            [decks.rust]",
+        )
+        .unwrap();
+
+        assert_eq!(decks.len(), 2);
+
+        let math = &decks["math"];
+        assert_eq!(math.time_box_minutes, Some(30));
+        assert_eq!(math.scheduling.scheduler, SchedulerKind::Sm2);
+        assert_eq!(math.scheduling.new_per_day, 5);
+        // not set for the deck, so it comes from [scheduling]
+        assert_eq!(math.scheduling.max_interval, 180);
+
+        let rust = &decks["rust"];
+        assert_eq!(rust.time_box_minutes, None);
+        assert_eq!(rust.scheduling.max_interval, 180);
+        assert_eq!(rust.scheduling.new_per_day, 10);
+
+        assert!(parse("live_url = \"foo\"").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_deck_configs() {
+        let error = |s| format!("{}", parse(s).unwrap_err());
+
+        assert_eq!(
+            error(
+                "[decks.math.scheduling]
+                max_interval = 0"
+            ),
+            "Deck math: max_interval must be at least first_interval (1), got 0"
+        );
+        assert_eq!(
+            error(
+                "[decks.math]
+                time_box_minutes = 0"
+            ),
+            "Deck math: time_box_minutes must be at least 1, got 0"
+        );
+        assert!(parse(
+            "[decks.math.scheduling]
+            scheduler = \"leitner\""
+        )
+        .is_err());
+    }
+}
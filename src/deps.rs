@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use crate::Exercise;
+
+// the prerequisite graph, with exercises as nodes and an edge from each
+// exercise to each of its prerequisites. every prerequisite is expected to be
+// resolved to an ID already, the way exercises come out of a store.
+
+fn by_id(exercises: &[Exercise]) -> HashMap<i32, &Exercise> {
+    exercises
+        .iter()
+        .filter_map(|exercise| exercise.id.map(|id| (id, exercise)))
+        .collect()
+}
+
+// the IDs along a cycle, starting and ending with the same one, if there is
+// one. exercises in a cycle would hold each other back forever.
+pub fn find_cycle(exercises: &[Exercise]) -> Option<Vec<i32>> {
+    let exercises_by_id = by_id(exercises);
+
+    // exercises known not to lead to a cycle
+    let mut done = vec![];
+
+    let mut ids = exercises_by_id.keys().cloned().collect::<Vec<_>>();
+    ids.sort();
+
+    for id in ids {
+        let mut path = vec![];
+        if let Some(cycle) = visit(&exercises_by_id, id, &mut path, &mut done) {
+            return Some(cycle);
+        }
+    }
+
+    None
+}
+
+fn visit(
+    exercises_by_id: &HashMap<i32, &Exercise>,
+    id: i32,
+    path: &mut Vec<i32>,
+    done: &mut Vec<i32>,
+) -> Option<Vec<i32>> {
+    if let Some(start) = path.iter().position(|&i| i == id) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(id);
+        return Some(cycle);
+    }
+
+    if done.contains(&id) {
+        return None;
+    }
+
+    path.push(id);
+    if let Some(exercise) = exercises_by_id.get(&id) {
+        for prerequisite_id in exercise.prerequisite_ids() {
+            if let Some(cycle) = visit(exercises_by_id, prerequisite_id, path, done) {
+                return Some(cycle);
+            }
+        }
+    }
+    path.pop();
+
+    done.push(id);
+    None
+}
+
+// everything the exercise builds on, depth first, with how far down each one
+// is. the exercise's own prerequisites are at depth 1.
+pub fn prerequisite_tree(exercises: &[Exercise], id: i32) -> Vec<(usize, i32)> {
+    let exercises_by_id = by_id(exercises);
+
+    let mut tree = vec![];
+    walk(
+        &|id| {
+            exercises_by_id
+                .get(&id)
+                .map_or(vec![], |exercise| exercise.prerequisite_ids().collect())
+        },
+        id,
+        1,
+        &mut vec![id],
+        &mut tree,
+    );
+    tree
+}
+
+// everything that builds on the exercise, the other way around from
+// prerequisite_tree
+pub fn dependent_tree(exercises: &[Exercise], id: i32) -> Vec<(usize, i32)> {
+    let mut tree = vec![];
+    walk(
+        &|id| {
+            let mut dependents = exercises
+                .iter()
+                .filter(|exercise| exercise.prerequisite_ids().any(|i| i == id))
+                .filter_map(|exercise| exercise.id)
+                .collect::<Vec<_>>();
+            dependents.sort();
+            dependents
+        },
+        id,
+        1,
+        &mut vec![id],
+        &mut tree,
+    );
+    tree
+}
+
+fn walk<F>(next: &F, id: i32, depth: usize, path: &mut Vec<i32>, tree: &mut Vec<(usize, i32)>)
+where
+    F: Fn(i32) -> Vec<i32>,
+{
+    for next_id in next(id) {
+        tree.push((depth, next_id));
+
+        // cycles are never saved, but don't loop forever if one is
+        if !path.contains(&next_id) {
+            path.push(next_id);
+            walk(next, next_id, depth + 1, path, tree);
+            path.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Prerequisite;
+
+    fn graph(edges: &[(i32, &[i32])]) -> Vec<Exercise> {
+        edges
+            .iter()
+            .map(|(id, prerequisites)| {
+                let mut exercise = Exercise::new(&format!("foo {}", id), "bar", "baz");
+                exercise.id = Some(*id);
+                exercise.prerequisites = prerequisites
+                    .iter()
+                    .map(|&id| Prerequisite::Id(id))
+                    .collect();
+                exercise
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_find_cycle() {
+        // 1 <- 2 <- 4, 1 <- 3 <- 4
+        let exercises = graph(&[(1, &[]), (2, &[1]), (3, &[1]), (4, &[2, 3])]);
+        assert_eq!(find_cycle(&exercises), None);
+
+        let exercises = graph(&[(1, &[3]), (2, &[1]), (3, &[2]), (4, &[])]);
+        assert_eq!(find_cycle(&exercises), Some(vec![1, 3, 2, 1]));
+
+        let exercises = graph(&[(1, &[1])]);
+        assert_eq!(find_cycle(&exercises), Some(vec![1, 1]));
+    }
+
+    #[test]
+    fn test_trees() {
+        let exercises = graph(&[(1, &[]), (2, &[1]), (3, &[1]), (4, &[2, 3]), (5, &[])]);
+
+        assert_eq!(
+            prerequisite_tree(&exercises, 4),
+            vec![(1, 2), (2, 1), (1, 3), (2, 1)]
+        );
+        assert!(prerequisite_tree(&exercises, 1).is_empty());
+
+        assert_eq!(
+            dependent_tree(&exercises, 1),
+            vec![(1, 2), (2, 4), (1, 3), (2, 4)]
+        );
+        assert!(dependent_tree(&exercises, 5).is_empty());
+
+        // shouldn't happen, but shouldn't hang either
+        let exercises = graph(&[(1, &[2]), (2, &[1])]);
+        assert_eq!(prerequisite_tree(&exercises, 1), vec![(1, 2), (2, 1)]);
+    }
+}
@@ -24,7 +24,11 @@ pub fn debug_log_print(msg: String) {
         if Path::new("debug.log").exists() {
             OpenOptions::new().append(true).open("debug.log").unwrap()
         } else {
-            OpenOptions::new().create(true).open("debug.log").unwrap()
+            OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .open("debug.log")
+                .unwrap()
         }
     };
 
@@ -92,7 +96,7 @@ pub fn horizontal_menu_select(options: &[HorizontalMenuOption]) -> io::Result<Op
     cursor.hide()?;
 
     while !done {
-        draw_horizontal_menu(&options, &cursor, selected_index, max_selected_index)?;
+        draw_horizontal_menu(options, &cursor, selected_index, max_selected_index)?;
 
         // drop into raw mode for input handling
         let _screen = RawScreen::into_raw_mode()?;
@@ -102,70 +106,75 @@ pub fn horizontal_menu_select(options: &[HorizontalMenuOption]) -> io::Result<Op
 
         // read input until a valid key (h/l/left/right/Esc) is entered. disregard other input
         loop {
-            if let Some(key_event) = sync_reader.next() {
-                if let InputEvent::Keyboard(key_press) = key_event {
-                    match key_press {
-                        KeyEvent::Ctrl('a') => {
-                            selected_index = 0;
-                            break;
-                        }
-                        KeyEvent::Ctrl('e') => {
-                            selected_index = max_selected_index;
-                            break;
-                        }
-                        KeyEvent::Char('h')
-                        | KeyEvent::Left
-                        | KeyEvent::Up
-                        | KeyEvent::Ctrl('b') => {
-                            if selected_index >= 1 {
-                                selected_index -= 1;
-                                break;
-                            }
-                        }
-                        KeyEvent::Char('l')
-                        | KeyEvent::Right
-                        | KeyEvent::Down
-                        | KeyEvent::Ctrl('f') => {
-                            if selected_index < max_selected_index {
-                                selected_index += 1;
-                                break;
+            if let Some(InputEvent::Keyboard(key_press)) = sync_reader.next() {
+                match key_press {
+                    KeyEvent::Ctrl('a') => {
+                        selected_index = 0;
+                        break;
+                    }
+                    KeyEvent::Ctrl('e') => {
+                        selected_index = max_selected_index;
+                        break;
+                    }
+                    KeyEvent::Char('h') | KeyEvent::Left | KeyEvent::Up | KeyEvent::Ctrl('b')
+                        if selected_index >= 1 =>
+                    {
+                        selected_index -= 1;
+                        break;
+                    }
+                    KeyEvent::Char('l')
+                    | KeyEvent::Right
+                    | KeyEvent::Down
+                    | KeyEvent::Ctrl('f')
+                        if selected_index < max_selected_index =>
+                    {
+                        selected_index += 1;
+                        break;
+                    }
+                    // already at the end, so these do nothing rather than
+                    // falling through to the cancel and shortcut arms below
+                    KeyEvent::Char('h')
+                    | KeyEvent::Char('l')
+                    | KeyEvent::Left
+                    | KeyEvent::Right
+                    | KeyEvent::Up
+                    | KeyEvent::Down
+                    | KeyEvent::Ctrl('b')
+                    | KeyEvent::Ctrl('f') => {}
+                    KeyEvent::Char('\n') => {
+                        did_select = true;
+                        done = true;
+                        break;
+                    }
+                    KeyEvent::Esc | KeyEvent::Ctrl(_) => {
+                        did_select = false;
+                        done = true;
+                        break;
+                    }
+                    KeyEvent::Char(c) => {
+                        // see if the key stroke matches any of the shortcuts
+                        let mut found_match = false;
+                        for (i, option) in options.iter().enumerate() {
+                            if c == option.shortcut {
+                                selected_index = i;
+                                did_select = true;
+                                done = true;
+                                found_match = true;
+                                // redraw to show result of selection
+                                draw_horizontal_menu(
+                                    options,
+                                    &cursor,
+                                    selected_index,
+                                    max_selected_index,
+                                )?;
+                                break; // this for loop only
                             }
                         }
-                        KeyEvent::Char('\n') => {
-                            did_select = true;
-                            done = true;
-                            break;
-                        }
-                        KeyEvent::Esc | KeyEvent::Ctrl(_) => {
-                            did_select = false;
-                            done = true;
-                            break;
-                        }
-                        KeyEvent::Char(c) => {
-                            // see if the key stroke matches any of the shortcuts
-                            let mut found_match = false;
-                            for (i, option) in options.iter().enumerate() {
-                                if c == option.shortcut {
-                                    selected_index = i;
-                                    did_select = true;
-                                    done = true;
-                                    found_match = true;
-                                    // redraw to show result of selection
-                                    draw_horizontal_menu(
-                                        &options,
-                                        &cursor,
-                                        selected_index,
-                                        max_selected_index,
-                                    )?;
-                                    break; // this for loop only
-                                }
-                            }
-                            if found_match {
-                                break; // loop { above
-                            }
+                        if found_match {
+                            break; // loop { above
                         }
-                        _ => {}
                     }
+                    _ => {}
                 }
             }
         }
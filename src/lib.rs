@@ -1,19 +1,30 @@
-use chrono::{Duration, Local, NaiveDate};
-use postgres::rows::Row;
-use postgres::transaction::Transaction;
-use postgres::types::ToSql;
-use postgres::{Connection, TlsMode};
+use chrono::{Duration, Local, NaiveDate, Utc};
 use serde_derive::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
+use std::fmt;
 use std::fs;
 use std::path::Path;
 
-pub const ONE_DAY: i32 = 1;
-pub const MAX_INTERVAL: i32 = ONE_DAY * 90;
-/* keep this fixed for now */
-pub const EASINESS_FACTOR: i32 = 2;
-
-#[derive(Debug)]
+pub mod cloze;
+pub mod deck;
+pub mod deps;
+pub mod migrations;
+pub mod review;
+pub mod scheduler;
+pub mod store;
+pub mod template;
+
+pub use deck::DeckConfig;
+use migrations::{latest_version, schema_too_new_error, Migration, MIGRATIONS};
+pub use review::{Grade, Outcome, Review};
+pub use scheduler::{
+    DoublingScheduler, FsrsScheduler, ReviewOrder, Scheduler, SchedulingConfig, SchedulingState,
+    Sm2Scheduler,
+};
+pub use store::{MemoryStore, PostgresStore, SqliteStore, Store};
+
+#[derive(Debug, Clone)]
 pub struct Exercise {
     pub id: Option<i32>,
     pub created_at: NaiveDate,
@@ -21,8 +32,51 @@ pub struct Exercise {
     pub description: String,
     pub source: String,
     pub reference_answer: String,
+    // sorted, without duplicates
+    pub tags: Vec<String>,
     pub update_interval: i32,
     pub consecutive_successful_reviews: i32,
+    pub last_grade: Option<Grade>,
+    pub easiness_factor: f64,
+    pub relearning_step: Option<i32>,
+    pub lapses: i32,
+    pub suspended: bool,
+    // None until the exercise has been answered for the first time
+    pub first_reviewed_at: Option<NaiveDate>,
+    // None for exercises imported without --deck
+    pub deck: Option<String>,
+    // exercises this one builds on. always IDs, sorted, once saved.
+    pub prerequisites: Vec<Prerequisite>,
+    // shared by the exercises made from the same YAML entry, which are kept
+    // in step when one of them is updated
+    pub sibling_group: Option<String>,
+    // for exercises made from a cloze, the text with every deletion marked
+    // and the number of the deletion this one hides
+    pub cloze: Option<String>,
+    pub cloze_number: Option<i32>,
+    // definitions of the variables filled in to the description and
+    // reference answer each time it's shown, by name. see the template
+    // module.
+    pub variables: BTreeMap<String, String>,
+}
+
+// in YAML, a number is the ID of an exercise that has already been imported
+// and anything else is the description of an exercise, which may be in the
+// same file
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Prerequisite {
+    Id(i32),
+    Description(String),
+}
+
+impl fmt::Display for Prerequisite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Prerequisite::Id(id) => write!(f, "{}", id),
+            Prerequisite::Description(description) => write!(f, "\"{}\"", description),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -30,29 +84,76 @@ pub struct ExportedExercise {
     pub id: i32,
     pub description: String,
     pub source: String,
+    // left out for clozes
+    #[serde(default)]
     pub reference_answer: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub prerequisites: Vec<Prerequisite>,
+    #[serde(default, deserialize_with = "deserialize_variables")]
+    pub variables: BTreeMap<String, String>,
 }
 
 pub struct ExerciseService {
-    conn: Connection,
+    store: Box<dyn Store>,
+    scheduler: Box<dyn Scheduler>,
+    // fuzz and load balancing happen here rather than in the scheduler since
+    // they need to know what else is due
+    scheduling: SchedulingConfig,
+    // exercises in decks that aren't in here use the scheduler and settings
+    // above
+    decks: BTreeMap<String, DeckScheduler>,
+}
+
+struct DeckScheduler {
+    config: DeckConfig,
+    scheduler: Box<dyn Scheduler>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Backend {
+    #[default]
+    Postgres,
+    Sqlite,
 }
 
+// everything in config.toml
 #[derive(Deserialize)]
-struct DbConfig {
+struct Config {
+    #[serde(default)]
+    backend: Backend,
+    // for sqlite, these are paths to database files rather than URLs
     live_url: String,
-    #[allow(dead_code)]
     test_url: String,
+    #[serde(default)]
+    scheduling: SchedulingConfig,
+    // filled in by parse_config, since decks fall back to [scheduling]
+    #[serde(skip)]
+    decks: BTreeMap<String, DeckConfig>,
 }
 
 #[derive(Debug, Deserialize)]
 struct ImportedExercise {
     pub description: String,
     pub source: String,
+    #[serde(default)]
     pub reference_answer: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub prerequisites: Vec<Prerequisite>,
+    #[serde(default, deserialize_with = "deserialize_variables")]
+    pub variables: BTreeMap<String, String>,
+    // also make an exercise going the other way, from the reference answer
+    // to the description
+    #[serde(default)]
+    pub reverse: bool,
 }
 
-fn make_error(error_string: String) -> Box<dyn Error> {
-    Box::new(std::io::Error::new(std::io::ErrorKind::Other, error_string))
+pub(crate) fn make_error(error_string: String) -> Box<dyn Error> {
+    Box::new(std::io::Error::other(error_string))
 }
 
 fn pad_multiline_string(s: &str) -> String {
@@ -74,22 +175,206 @@ fn yaml_string_is_empty(s: &str) -> bool {
     s.trim().is_empty() || s == "~"
 }
 
+// tags end up in commands and exported YAML, so keep them to characters that
+// need no quoting in either
+fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '/')
+}
+
+fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut tags = tags
+        .iter()
+        .map(|tag| tag.trim().to_string())
+        .collect::<Vec<_>>();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+fn invalid_tag(tags: &[String]) -> Option<&String> {
+    tags.iter().find(|tag| !is_valid_tag(tag.trim()))
+}
+
+fn normalize_prerequisites(prerequisites: &[Prerequisite]) -> Vec<Prerequisite> {
+    let mut normalized: Vec<Prerequisite> = vec![];
+
+    for prerequisite in prerequisites {
+        let prerequisite = match prerequisite {
+            Prerequisite::Id(id) => Prerequisite::Id(*id),
+            Prerequisite::Description(description) => {
+                Prerequisite::Description(description.trim().to_string())
+            }
+        };
+
+        if !normalized.contains(&prerequisite) {
+            normalized.push(prerequisite);
+        }
+    }
+
+    normalized
+}
+
+fn blank_prerequisite(prerequisites: &[Prerequisite]) -> bool {
+    prerequisites.iter().any(|prerequisite| match prerequisite {
+        Prerequisite::Description(description) => yaml_string_is_empty(description),
+        Prerequisite::Id(_) => false,
+    })
+}
+
+// variables are defined with strings like "2..12" or "a * b", but "5" is
+// read as a number
+fn deserialize_variables<'de, D>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let values: BTreeMap<String, serde_yaml::Value> =
+        serde::Deserialize::deserialize(deserializer)?;
+
+    values
+        .into_iter()
+        .map(|(name, value)| match value {
+            serde_yaml::Value::String(source) => Ok((name, source.trim().to_string())),
+            serde_yaml::Value::Number(number) => Ok((name, number.to_string())),
+            _ => Err(serde::de::Error::custom(format!(
+                "variable {} should be a range like 1..10 or an expression",
+                name
+            ))),
+        })
+        .collect()
+}
+
+// tries the variables out once, returning the error without saying which
+// exercise it's about
+fn variables_error(
+    description: &str,
+    reference_answer: &str,
+    variables: &BTreeMap<String, String>,
+) -> Option<String> {
+    if variables.is_empty() {
+        return None;
+    }
+
+    if cloze::has_deletions(description) {
+        return Some("is a cloze, so it can't have variables.".to_string());
+    }
+
+    let rendered = template::instantiate(variables, &mut rand::thread_rng()).and_then(|values| {
+        template::render(description, &values)?;
+        template::render(reference_answer, &values)
+    });
+
+    rendered
+        .err()
+        .map(|e| format!("has invalid variables. {}", e))
+}
+
+fn new_sibling_group() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+// checks the reference answer against whether the description is a cloze,
+// returning the error without saying which exercise it's about
+fn reference_answer_error(description: &str, reference_answer: &str) -> Option<String> {
+    if !cloze::has_deletions(description) {
+        if yaml_string_is_empty(reference_answer) {
+            return Some("has a blank or missing reference answer.".to_string());
+        }
+        return None;
+    }
+
+    if !yaml_string_is_empty(reference_answer) {
+        return Some(
+            "is a cloze, so its reference answers come from its deletions and it can't have one of its own."
+                .to_string(),
+        );
+    }
+
+    cloze::expand(description)
+        .err()
+        .map(|e| format!("has an invalid cloze. {}", e))
+}
+
+// one exercise for each deletion in the cloze, with everything else copied
+// from the given exercise
+fn expand_cloze(exercise: &Exercise, text: &str) -> Result<Vec<Exercise>, Box<dyn Error>> {
+    Ok(cloze::expand(text)?
+        .into_iter()
+        .map(|deletion| {
+            let mut sibling = exercise.clone();
+            sibling.description = deletion.description;
+            sibling.reference_answer = deletion.answer;
+            sibling.cloze = Some(text.to_string());
+            sibling.cloze_number = Some(deletion.number);
+            sibling
+        })
+        .collect())
+}
+
+fn with_tag(exercises: Vec<Exercise>, tag: Option<&str>) -> Vec<Exercise> {
+    match tag {
+        Some(tag) => exercises
+            .into_iter()
+            .filter(|exercise| exercise.has_tag(tag))
+            .collect(),
+        None => exercises,
+    }
+}
+
+// siblings give each other's answers away, so only the one that's been due the
+// longest comes up and the rest wait for another session
+fn first_due_siblings(mut due: Vec<Exercise>) -> Vec<Exercise> {
+    let mut by_due_date = due.iter().collect::<Vec<_>>();
+    by_due_date.sort_by_key(|exercise| (exercise.due_at, exercise.id));
+
+    let mut sibling_groups = HashSet::new();
+    let mut held_back_ids = HashSet::new();
+    for exercise in by_due_date {
+        if let Some(sibling_group) = &exercise.sibling_group {
+            if !sibling_groups.insert(sibling_group) {
+                held_back_ids.insert(exercise.id);
+            }
+        }
+    }
+
+    due.retain(|exercise| !held_back_ids.contains(&exercise.id));
+    due
+}
+
 fn todays_date() -> NaiveDate {
     Local::today().naive_local()
 }
 
-fn read_config_file() -> Result<DbConfig, Box<dyn Error>> {
-    let config_str = std::fs::read_to_string("config.toml")?;
+fn saved_exercise_id(exercise: &Exercise) -> Result<i32, Box<dyn Error>> {
+    exercise
+        .id
+        .ok_or_else(|| make_error("Cannot review an exercise that has not been saved".to_string()))
+}
 
-    match toml::from_str(&config_str) {
-        Ok(toml) => {
-            let config: DbConfig = toml;
-            Ok(config)
-        }
-        Err(e) => Err(Box::new(e)),
+fn open_store(backend: Backend, url: &str) -> Result<Box<dyn Store>, Box<dyn Error>> {
+    match backend {
+        Backend::Postgres => Ok(Box::new(PostgresStore::connect(url)?)),
+        Backend::Sqlite => Ok(Box::new(SqliteStore::open(Path::new(url))?)),
     }
 }
 
+fn parse_config(config_str: &str) -> Result<Config, Box<dyn Error>> {
+    let value: toml::Value = toml::from_str(config_str)?;
+
+    let mut config: Config = value.clone().try_into()?;
+    config.decks = deck::deck_configs(&value)?;
+
+    Ok(config)
+}
+
+fn read_config_file() -> Result<Config, Box<dyn Error>> {
+    let config_str = std::fs::read_to_string("config.toml")?;
+
+    parse_config(&config_str)
+}
+
 impl PartialEq<Exercise> for Exercise {
     fn eq(&self, other: &Exercise) -> bool {
         self.id == other.id
@@ -106,28 +391,39 @@ impl Exercise {
             description: String::from(description),
             source: String::from(source),
             reference_answer: String::from(reference_answer),
+            tags: vec![],
             update_interval: 0,
             consecutive_successful_reviews: 0,
-        }
-    }
-
-    fn new_from_row(row: &Row) -> Exercise {
-        Exercise {
-            id: Some(row.get(0)),
-            created_at: row.get(1),
-            due_at: row.get(2),
-            description: row.get(3),
-            source: row.get(4),
-            reference_answer: row.get(5),
-            update_interval: row.get(6),
-            consecutive_successful_reviews: row.get(6),
+            last_grade: None,
+            easiness_factor: scheduler::sm2::INITIAL_EASINESS_FACTOR,
+            relearning_step: None,
+            lapses: 0,
+            suspended: false,
+            first_reviewed_at: None,
+            deck: None,
+            prerequisites: vec![],
+            sibling_group: None,
+            cloze: None,
+            cloze_number: None,
+            variables: BTreeMap::new(),
         }
     }
 
     pub fn update_with_values(&mut self, updated_exercise: &ExportedExercise) {
-        self.description = updated_exercise.description.clone();
+        // the description and reference answer of a cloze are filled in when
+        // it's saved, since its siblings need to change along with it
+        if cloze::has_deletions(&updated_exercise.description) {
+            self.cloze = Some(updated_exercise.description.clone());
+        } else {
+            // saving works out whether it can leave its siblings
+            self.cloze = None;
+            self.description = updated_exercise.description.clone();
+            self.reference_answer = updated_exercise.reference_answer.clone();
+        }
         self.source = updated_exercise.source.clone();
-        self.reference_answer = updated_exercise.reference_answer.clone();
+        self.tags = updated_exercise.tags.clone();
+        self.prerequisites = updated_exercise.prerequisites.clone();
+        self.variables = updated_exercise.variables.clone();
     }
 
     pub fn yaml_export(&self, path: &Path) -> Result<(), Box<dyn Error>> {
@@ -138,9 +434,15 @@ impl Exercise {
         }
         let exported_exercise = ExportedExercise {
             id: self.id.unwrap(),
-            description: self.description.clone(),
+            description: self
+                .cloze
+                .clone()
+                .unwrap_or_else(|| self.description.clone()),
             source: self.source.clone(),
             reference_answer: self.reference_answer.clone(),
+            tags: self.tags.clone(),
+            prerequisites: self.prerequisites.clone(),
+            variables: self.variables.clone(),
         };
 
         // we could use serde_yaml for this, but it won't print newlines nicely.
@@ -154,418 +456,1188 @@ description: |+
 {}
 source: |+
 {}
-reference_answer: |+
-{}
-",
+{}tags: [{}]
+prerequisites: [{}]
+{}",
             exported_exercise.id,
             pad_multiline_string(&exported_exercise.description),
             pad_multiline_string(&exported_exercise.source),
-            pad_multiline_string(&exported_exercise.reference_answer)
+            // editing the cloze changes the reference answers
+            if self.cloze.is_some() {
+                String::new()
+            } else {
+                format!(
+                    "reference_answer: |+\n{}\n",
+                    pad_multiline_string(&exported_exercise.reference_answer)
+                )
+            },
+            exported_exercise.tags.join(", "),
+            exported_exercise
+                .prerequisites
+                .iter()
+                .map(Prerequisite::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+            if exported_exercise.variables.is_empty() {
+                String::new()
+            } else {
+                // quoted, since a definition like "*a" would mean something
+                // else to YAML
+                let definitions = exported_exercise
+                    .variables
+                    .iter()
+                    .map(|(name, source)| format!("  {}: \"{}\"\n", name, source))
+                    .collect::<String>();
+                format!("variables:\n{}", definitions)
+            }
         );
 
-        match fs::write(path, yaml_string) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(Box::new(e)),
-        }
+        Ok(fs::write(path, yaml_string)?)
     }
 
-    // methods that operate on a single exercise seem reasonale to keep here rather than in ExerciseService
-    fn create(&self, tx: &Transaction) -> Result<u64, Box<dyn Error>> {
-        // exercise was already inserted
-        if self.id.is_some() {
-            return Err(make_error("Cannot insert, has PK".to_string()));
-        }
+    pub fn update(&mut self, service: &ExerciseService) -> Result<u64, Box<dyn Error>> {
+        service.update_exercise(self)
+    }
 
-        // we can let postgres insert some defaults
-        let values: &[&dyn ToSql] = &[
-            &self.created_at,
-            &self.due_at,
-            &self.description,
-            &self.source,
-            &self.reference_answer,
-        ];
+    /* A copy with fresh values for its variables filled in, or just a copy if it has none. */
+    pub fn instantiate(&self) -> Result<Exercise, Box<dyn Error>> {
+        let mut exercise = self.clone();
 
-        // the code doesn't really need the generated values when creating, so I don't feel the need to write the code to fill in data
-        // for fields I don't actually need
-        let query = "insert into exercises(created_at, due_at, description, source, reference_answer) values($1, $2, $3, $4, $5)";
-        match tx.execute(query, values) {
-            Ok(i) => Ok(i),
-            Err(e) => Err(Box::new(e)),
+        if !self.variables.is_empty() {
+            let values = template::instantiate(&self.variables, &mut rand::thread_rng())?;
+            exercise.description = template::render(&self.description, &values)?;
+            exercise.reference_answer = template::render(&self.reference_answer, &values)?;
         }
-    }
 
-    pub fn update(&mut self, service: &ExerciseService) -> Result<u64, Box<dyn Error>> {
-        if self.id.is_none() {
-            return Err(make_error("Cannot insert, has no PK".to_string()));
-        }
+        Ok(exercise)
+    }
 
-        let query = "update exercises set created_at = $1, due_at = $2, description = $3, source = $4,
-        reference_answer = $5, update_interval = $6, consecutive_successful_reviews = $7 where id = $8";
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
 
-        let values: &[&dyn ToSql] = &[
-            &self.created_at,
-            &self.due_at,
-            &self.description,
-            &self.source,
-            &self.reference_answer,
-            &self.update_interval,
-            &self.consecutive_successful_reviews,
-            &self.id.unwrap(),
-        ];
-        match service.conn.execute(query, &values) {
-            Ok(i) => Ok(i),
-            Err(e) => Err(Box::new(e)),
-        }
+    pub fn prerequisite_ids(&self) -> impl Iterator<Item = i32> + '_ {
+        self.prerequisites
+            .iter()
+            .filter_map(|prerequisite| match prerequisite {
+                Prerequisite::Id(id) => Some(*id),
+                Prerequisite::Description(_) => None,
+            })
     }
 
-    pub fn update_repetition_interval(&mut self, correct: bool) {
-        self.due_at = todays_date();
+    /* Whether it was last answered wrong and hasn't been relearned since. */
+    pub fn is_failing(&self) -> bool {
+        self.last_grade.is_some_and(|grade| !grade.is_correct()) || self.relearning_step.is_some()
+    }
 
-        if correct {
-            self.consecutive_successful_reviews += 1;
-            self.update_interval = match self.consecutive_successful_reviews {
-                1 => ONE_DAY,
-                _ => std::cmp::min(MAX_INTERVAL, self.update_interval * EASINESS_FACTOR),
-            };
+    pub fn is_new(&self) -> bool {
+        self.first_reviewed_at.is_none()
+    }
 
-            self.due_at += Duration::days(i64::from(self.update_interval));
-        } else {
-            self.consecutive_successful_reviews = 0;
-            self.update_interval = 0;
+    pub fn scheduling_state(&self) -> SchedulingState {
+        SchedulingState {
+            due_at: self.due_at,
+            update_interval: self.update_interval,
+            consecutive_successful_reviews: self.consecutive_successful_reviews,
+            easiness_factor: self.easiness_factor,
+            relearning_step: self.relearning_step,
         }
     }
+
+    pub fn set_scheduling_state(&mut self, state: SchedulingState) {
+        self.due_at = state.due_at;
+        self.update_interval = state.update_interval;
+        self.consecutive_successful_reviews = state.consecutive_successful_reviews;
+        self.easiness_factor = state.easiness_factor;
+        self.relearning_step = state.relearning_step;
+    }
 }
 
 impl ExerciseService {
+    /* Uses the default DoublingScheduler. */
+    pub fn new(store: Box<dyn Store>) -> ExerciseService {
+        ExerciseService::with_scheduler(store, Box::new(DoublingScheduler::default()))
+    }
+
+    pub fn with_scheduler(store: Box<dyn Store>, scheduler: Box<dyn Scheduler>) -> ExerciseService {
+        ExerciseService {
+            store,
+            scheduler,
+            scheduling: SchedulingConfig::default(),
+            decks: BTreeMap::new(),
+        }
+    }
+
+    /* Uses the scheduler picked in the config, along with its fuzz and load balancing settings. */
+    pub fn with_config(
+        store: Box<dyn Store>,
+        scheduling: SchedulingConfig,
+    ) -> Result<ExerciseService, Box<dyn Error>> {
+        ExerciseService::with_decks(store, scheduling, BTreeMap::new())
+    }
+
+    /* Like with_config, with decks that have their own settings. */
+    pub fn with_decks(
+        store: Box<dyn Store>,
+        scheduling: SchedulingConfig,
+        decks: BTreeMap<String, DeckConfig>,
+    ) -> Result<ExerciseService, Box<dyn Error>> {
+        let mut deck_schedulers = BTreeMap::new();
+        for (name, config) in decks {
+            let scheduler = config.scheduling.open_scheduler()?;
+            deck_schedulers.insert(name, DeckScheduler { config, scheduler });
+        }
+
+        Ok(ExerciseService {
+            store,
+            scheduler: scheduling.open_scheduler()?,
+            scheduling,
+            decks: deck_schedulers,
+        })
+    }
+
     pub fn new_live() -> Result<ExerciseService, Box<dyn Error>> {
         let config = read_config_file()?;
-        let conn = Connection::connect(&config.live_url[..], TlsMode::None)?;
 
-        Ok(ExerciseService { conn })
+        ExerciseService::with_decks(
+            open_store(config.backend, &config.live_url)?,
+            config.scheduling,
+            config.decks,
+        )
     }
 
     /* Helper for testing connections. new_test() is more convenient for actual test setups. */
     pub fn maybe_new_test() -> Result<ExerciseService, Box<dyn Error>> {
         let config = read_config_file()?;
-        let conn = Connection::connect(&config.test_url[..], TlsMode::None)?;
 
-        Ok(ExerciseService { conn })
+        Ok(ExerciseService::new(open_store(
+            config.backend,
+            &config.test_url,
+        )?))
     }
 
     pub fn new_test() -> ExerciseService {
         // it's OK to unwrap here because we're in a test environment and failing here is fine
-        let config = read_config_file().unwrap();
-
-        let conn = Connection::connect(config.test_url, postgres::TlsMode::None).unwrap();
-
-        let service = ExerciseService { conn };
+        let service = ExerciseService::maybe_new_test().unwrap();
 
         /* handle failed test runs that didn't clean up properly */
         service.drop_schema().unwrap();
-        service.bootstrap_schema().unwrap();
+        service.migrate().unwrap();
 
         service
     }
 
-    pub fn bootstrap_schema(&self) -> postgres::Result<u64> {
-        self.conn.execute(
-            "create table if not exists exercises(
-            id serial primary key,
-            created_at date not null default current_date,
-            description text unique not null,
-            source text not null,
-            reference_answer text not null,
-            due_at date not null default current_date,
-            update_interval integer not null default 0,
-            consecutive_successful_reviews integer not null default 0
-        )",
-            &[],
-        )?;
+    /* Not backed by anything, so it's always empty to start with and safe to use from parallel tests. */
+    pub fn new_in_memory() -> ExerciseService {
+        let service = ExerciseService::new(Box::new(MemoryStore::new()));
 
-        self.conn.execute(
-            "create index if not exists exercises_due_at on exercises(due_at)",
-            &[],
-        )
+        // can't fail for an in-memory store
+        service.migrate().unwrap();
+
+        service
     }
 
-    pub fn drop_schema(&self) -> postgres::Result<u64> {
-        self.conn
-            .execute("drop table if exists exercises cascade", &[])
+    pub fn store(&self) -> &dyn Store {
+        self.store.as_ref()
     }
 
-    pub fn schema_is_loaded(&self) -> bool {
-        let query = "SELECT EXISTS (
-            SELECT 1
-            FROM   information_schema.tables
-            WHERE  table_schema = 'public'
-            AND    table_name = 'exercises'
-        )";
+    pub fn schema_version(&self) -> Result<i32, Box<dyn Error>> {
+        self.store.schema_version()
+    }
 
-        match &self.conn.query(query, &[]).unwrap().iter().next() {
-            Some(row) => {
-                let result: bool = row.get(0);
-                result
-            }
-            None => false,
+    /* Applies any migrations the database hasn't seen yet, oldest first, and returns the ones that were applied. */
+    pub fn migrate(&self) -> Result<Vec<&'static Migration>, Box<dyn Error>> {
+        let current_version = self.schema_version()?;
+
+        if current_version > latest_version() {
+            return Err(schema_too_new_error(current_version));
+        }
+
+        let mut applied = vec![];
+
+        for migration in MIGRATIONS.iter().filter(|m| m.version > current_version) {
+            self.store.apply_migration(migration)?;
+            applied.push(migration);
         }
-    }
 
-    fn sql_column_list() -> &'static str {
-        "id, created_at, due_at, description, source, reference_answer, update_interval,
-        consecutive_successful_reviews"
+        Ok(applied)
     }
 
-    pub fn delete_by_pk(&self, pk: i32) -> Result<(), Box<dyn Error>> {
-        let rows_affected = &self
-            .conn
-            .execute("delete from exercises where id = $1", &[&pk])?;
+    /* Makes sure the schema is exactly the one this version of arete expects before anything touches it. */
+    pub fn check_schema(&self) -> Result<(), Box<dyn Error>> {
+        let current_version = self.schema_version()?;
 
-        if *rows_affected == 1 {
-            Ok(())
-        } else {
+        if current_version == 0 {
+            Err(make_error(
+                "Schema is not loaded. Please run migrate.".to_string(),
+            ))
+        } else if current_version < latest_version() {
             Err(make_error(format!(
-                "Expected 1 row to be affected, got {} instead",
-                rows_affected
+                "Database schema is at version {}, but this version of arete needs version {}. Please run migrate.",
+                current_version,
+                latest_version()
             )))
+        } else if current_version > latest_version() {
+            Err(schema_too_new_error(current_version))
+        } else {
+            Ok(())
         }
     }
 
+    pub fn drop_schema(&self) -> Result<(), Box<dyn Error>> {
+        self.store.drop_schema()
+    }
+
+    pub fn delete_by_pk(&self, pk: i32) -> Result<(), Box<dyn Error>> {
+        self.store.delete(pk)
+    }
+
     pub fn get_by_pk(&self, pk: i32) -> Option<Exercise> {
-        let query = format!(
-            "
-        SELECT
-            {}
-        FROM
-            exercises
-        WHERE
-            id = $1
-        ",
-            ExerciseService::sql_column_list()
-        );
+        self.store.get_by_pk(pk)
+    }
 
-        match &self.conn.query(&query, &[&pk]).unwrap().iter().next() {
-            Some(row) => Some(Exercise::new_from_row(&row)),
-            None => None,
+    // the scheduler and settings for exercises in the deck
+    fn deck_scheduler(&self, deck: Option<&str>) -> (&dyn Scheduler, &SchedulingConfig) {
+        match deck.and_then(|deck| self.decks.get(deck)) {
+            Some(deck) => (deck.scheduler.as_ref(), &deck.config.scheduling),
+            None => (self.scheduler.as_ref(), &self.scheduling),
         }
     }
 
-    pub fn get_due(&self) -> Vec<Exercise> {
-        let mut exercises = vec![];
+    fn has_new_exercise_limit(&self) -> bool {
+        self.scheduling.new_per_day > 0
+            || self
+                .decks
+                .values()
+                .any(|deck| deck.config.scheduling.new_per_day > 0)
+    }
 
-        let due_query = format!(
-            "
-        SELECT
-            {}
-        FROM
-            exercises
-        WHERE
-            due_at <= $1
-        ORDER BY
-            due_at desc,
-            id desc",
-            ExerciseService::sql_column_list()
-        );
+    /* The time box for reviewing the deck, if its config has one. */
+    pub fn deck_time_box_minutes(&self, deck: &str) -> Option<i64> {
+        self.decks
+            .get(deck)
+            .and_then(|deck| deck.config.time_box_minutes)
+    }
 
+    // due exercises, except for the ones with a prerequisite that's failing,
+    // which come back once it's been relearned, split into the ones to review
+    // and the new ones held back by each deck's daily limit. every exercise is
+    // only loaded if one of those needs it, and then only once.
+    //
+    // with one_per_sibling_group, siblings are left out before the limit, so
+    // they don't take up new exercises' places.
+    fn split_due(&self, one_per_sibling_group: bool) -> (Vec<Exercise>, Vec<Exercise>) {
         let today = todays_date();
+        let mut due = self.store.get_due(today);
+
+        let has_prerequisites = due
+            .iter()
+            .any(|exercise| !exercise.prerequisites.is_empty());
+        let has_new_exercise_limit = self.has_new_exercise_limit();
+
+        let exercises = if has_prerequisites || has_new_exercise_limit {
+            self.store.get_all_by_due_date_desc()
+        } else {
+            vec![]
+        };
 
-        for row in &self.conn.query(&due_query, &[&today]).unwrap() {
-            exercises.push(Exercise::new_from_row(&row));
+        if has_prerequisites {
+            let failing_ids = exercises
+                .iter()
+                .filter(|exercise| exercise.is_failing())
+                .filter_map(|exercise| exercise.id)
+                .collect::<Vec<_>>();
+
+            due.retain(|exercise| {
+                !exercise
+                    .prerequisite_ids()
+                    .any(|id| failing_ids.contains(&id))
+            });
         }
 
-        exercises
-    }
+        if one_per_sibling_group {
+            due = first_due_siblings(due);
+        }
 
-    pub fn grep(&self, query_string: &str) -> Vec<Exercise> {
-        let mut exercises = vec![];
-
-        let grep_query = format!(
-            "
-        SELECT
-            {}
-        FROM
-            exercises
-        WHERE
-            description like ('%' || $1 || '%')
-            or source like ('%' || $1 || '%')
-            or reference_answer like ('%' || $1 || '%')
-            or id::text like ('%' || $1 || '%')
-        ORDER BY
-            due_at desc,
-            id desc",
-            ExerciseService::sql_column_list()
-        );
+        if !has_new_exercise_limit {
+            return (due, vec![]);
+        }
+
+        let mut introduced_today = HashMap::new();
+        for exercise in exercises {
+            if exercise.first_reviewed_at == Some(today) {
+                *introduced_today.entry(exercise.deck).or_insert(0) += 1;
+            }
+        }
 
-        for row in &self.conn.query(&grep_query, &[&query_string]).unwrap() {
-            exercises.push(Exercise::new_from_row(&row));
+        // introduce new exercises in the order they were imported
+        let mut new_due = due
+            .iter()
+            .filter(|exercise| exercise.is_new())
+            .collect::<Vec<_>>();
+        new_due.sort_by_key(|exercise| exercise.id);
+
+        let mut new_ids = vec![];
+        for exercise in new_due {
+            let new_per_day = self.deck_scheduler(exercise.deck.as_deref()).1.new_per_day;
+            let introduced = introduced_today.entry(exercise.deck.clone()).or_insert(0);
+
+            if new_per_day == 0 || *introduced < new_per_day {
+                *introduced += 1;
+                new_ids.push(exercise.id);
+            }
         }
 
-        exercises
+        due.into_iter()
+            .partition(|exercise| !exercise.is_new() || new_ids.contains(&exercise.id))
     }
 
-    pub fn get_schedule(&self) -> Vec<(NaiveDate, i32)> {
-        let query =
-            "select due_at, count(*)::integer from exercises group by due_at order by due_at";
+    /* Due exercises with the tag if one is given, leaving out ones with failing prerequisites and new ones past each deck's daily limit. */
+    pub fn get_due(&self, tag: Option<&str>) -> Vec<Exercise> {
+        with_tag(self.split_due(false).0, tag)
+    }
 
-        let mut counts = vec![];
+    /* How many new exercises with the tag if one is given are due but held back by the daily limit. */
+    pub fn count_new_waiting(&self, tag: Option<&str>) -> usize {
+        with_tag(self.split_due(false).1, tag).len()
+    }
 
-        for row in &self.conn.query(&query, &[]).unwrap() {
-            counts.push((row.get(0), row.get(1)));
+    /* Due exercises in the order to review them in, or in the deck's order from the config if none is given. With a deck, only that deck's exercises. */
+    pub fn get_due_for_review(
+        &self,
+        order: Option<ReviewOrder>,
+        tag: Option<&str>,
+        deck: Option<&str>,
+    ) -> Vec<Exercise> {
+        let mut due = with_tag(self.split_due(true).0, tag);
+        if let Some(deck) = deck {
+            due.retain(|exercise| exercise.deck.as_deref() == Some(deck));
         }
 
-        counts
+        scheduler::order::order_exercises(
+            due,
+            order.unwrap_or(self.deck_scheduler(deck).1.review_order),
+            &mut rand::thread_rng(),
+        )
     }
 
-    pub fn get_exercise_stats(&self) -> Option<(i32, NaiveDate)> {
-        // need the explicit ::integer cast to let rust-postgres convert the type
-        let query = "select count(*)::integer, min(created_at) from exercises";
-
-        match &self.conn.query(&query, &[]).unwrap().iter().next() {
-            Some(row) => Some((row.get(0), row.get(1))),
-            None => None,
-        }
+    pub fn grep(&self, query_string: &str, tag: Option<&str>) -> Vec<Exercise> {
+        with_tag(self.store.grep(query_string), tag)
     }
 
-    pub fn count_due(&self) -> Option<i32> {
-        let today = todays_date();
-        let query = "select count(*)::integer from exercises where due_at <= $1";
+    // the stores can only summarize every exercise, so to summarize the ones
+    // with a tag, summarize a copy of just those
+    fn tagged_store(&self, tag: &str) -> MemoryStore {
+        MemoryStore::with_exercises(&self.get_all_by_due_date_desc(Some(tag)))
+    }
 
-        match &self.conn.query(&query, &[&today]).unwrap().iter().next() {
-            Some(row) => Some(row.get(0)),
-            None => None,
+    pub fn get_schedule(&self, tag: Option<&str>) -> Vec<(NaiveDate, i32)> {
+        match tag {
+            Some(tag) => self.tagged_store(tag).schedule(),
+            None => self.store.schedule(),
         }
     }
 
-    pub fn get_all_by_due_date_desc(&self) -> Vec<Exercise> {
-        let mut exercises = vec![];
+    pub fn get_exercise_stats(&self, tag: Option<&str>) -> Option<(i32, NaiveDate)> {
+        match tag {
+            Some(tag) => self.tagged_store(tag).stats(),
+            None => self.store.stats(),
+        }
+    }
 
-        let due_query = format!(
-            "
-        SELECT
-            {}
-        FROM
-            exercises
-        ORDER BY
-            due_at desc,
-            id desc",
-            ExerciseService::sql_column_list()
-        );
+    /* (deck, exercises, due exercises) for every deck, including configured decks with no exercises yet. A deck of None holds the exercises that aren't in one. */
+    pub fn get_deck_counts(&self) -> Vec<(Option<String>, i32, i32)> {
+        let mut counts = BTreeMap::new();
 
-        for row in &self.conn.query(&due_query, &[]).unwrap() {
-            exercises.push(Exercise::new_from_row(&row));
+        for deck in self.decks.keys() {
+            counts.insert(Some(deck.clone()), (0, 0));
+        }
+        for exercise in self.store.get_all_by_due_date_desc() {
+            counts.entry(exercise.deck).or_insert((0, 0)).0 += 1;
+        }
+        for exercise in self.get_due(None) {
+            counts.entry(exercise.deck).or_insert((0, 0)).1 += 1;
         }
 
-        exercises
+        counts
+            .into_iter()
+            .map(|(deck, (exercise_cnt, due_cnt))| (deck, exercise_cnt, due_cnt))
+            .collect()
     }
 
-    pub fn save_parsed_exercises(&self, exercises: &[Exercise]) -> Result<(), Box<dyn Error>> {
-        let tx = self.conn.transaction()?;
+    pub fn count_due(&self, tag: Option<&str>) -> Option<i32> {
+        Some(self.get_due(tag).len() as i32)
+    }
 
-        for exercise in exercises {
-            // @Performance we could probably do bulk inserts but for small files it won't matter
-            if let Err(e) = exercise.create(&tx) {
-                // rollback will kick in
-                return Err(e);
-            }
+    pub fn get_all_by_due_date_desc(&self, tag: Option<&str>) -> Vec<Exercise> {
+        with_tag(self.store.get_all_by_due_date_desc(), tag)
+    }
+
+    /* Moves every due date forward by days, then spreads whatever is due over the next spread_days days. Returns what get_schedule will return afterwards; with dry_run, nothing is saved. */
+    pub fn shift_schedule(
+        &self,
+        days: i32,
+        spread_days: i32,
+        dry_run: bool,
+    ) -> Result<Vec<(NaiveDate, i32)>, Box<dyn Error>> {
+        if days < 0 || spread_days < 0 {
+            return Err(make_error(format!(
+                "Can only shift forward, got {} days spread over {} days",
+                days, spread_days
+            )));
         }
-        match tx.commit() {
-            Ok(_) => Ok(()),
-            Err(e) => Err(Box::new(e)),
+
+        let exercises = self.get_all_by_due_date_desc(None);
+
+        // suspended exercises aren't due, so there's nothing to shift
+        let unsuspended = exercises
+            .iter()
+            .filter(|exercise| !exercise.suspended)
+            .cloned()
+            .collect::<Vec<_>>();
+        let shifted =
+            scheduler::shift::shift_due_dates(&unsuspended, days, spread_days, todays_date());
+
+        if !dry_run {
+            self.store.update_all(&shifted)?;
+            return Ok(self.get_schedule(None));
         }
+
+        let preview = exercises
+            .into_iter()
+            .map(|exercise| {
+                shifted
+                    .iter()
+                    .find(|shifted| shifted.id == exercise.id)
+                    .cloned()
+                    .unwrap_or(exercise)
+            })
+            .collect::<Vec<_>>();
+
+        Ok(
+            ExerciseService::new(Box::new(MemoryStore::with_exercises(&preview)))
+                .get_schedule(None),
+        )
     }
-}
 
-pub fn parse_exercises(path: &Path) -> Result<Vec<Exercise>, Box<dyn Error>> {
-    let content = std::fs::read_to_string(&path)?;
+    /* Suspended exercises stay in the database but are never due. */
+    pub fn set_suspended(&self, pk: i32, suspended: bool) -> Result<(), Box<dyn Error>> {
+        let mut exercise = match self.get_by_pk(pk) {
+            Some(exercise) => exercise,
+            None => {
+                return Err(make_error(format!(
+                    "Exercise with ID {} does not exist",
+                    pk
+                )))
+            }
+        };
 
-    match convert_yaml_str_to_exercises(&content) {
-        Ok(exercises) => {
-            for (i, exercise) in exercises.iter().enumerate() {
-                let human_index = i + 1;
-                if yaml_string_is_empty(&exercise.description) {
-                    return Err(make_error(format!(
-                        "Exercise {} has a blank or missing description.",
-                        human_index
-                    )));
-                } else if yaml_string_is_empty(&exercise.source) {
-                    return Err(make_error(format!(
-                        "Exercise {} has a blank or missing source.",
-                        human_index
-                    )));
-                } else if yaml_string_is_empty(&exercise.reference_answer) {
+        exercise.suspended = suspended;
+        self.store.update(&exercise)?;
+
+        Ok(())
+    }
+
+    pub fn is_leech(&self, exercise: &Exercise) -> bool {
+        exercise.lapses
+            >= self
+                .deck_scheduler(exercise.deck.as_deref())
+                .1
+                .leech_threshold
+    }
+
+    /* Exercises that have lapsed at least leech_threshold times, most lapses first. */
+    pub fn get_leeches(&self) -> Vec<Exercise> {
+        let mut leeches = self
+            .store
+            .get_all_by_due_date_desc()
+            .into_iter()
+            .filter(|exercise| self.is_leech(exercise))
+            .collect::<Vec<_>>();
+
+        // stable, so ties stay in due date order
+        leeches.sort_by_key(|exercise| -exercise.lapses);
+
+        leeches
+    }
+
+    // checks the prerequisite graph as it would be with the updated and new
+    // exercises saved, so a circular prerequisite is caught before anything
+    // is. missing descriptions are left for the store to report.
+    fn check_prerequisites(
+        &self,
+        updated: &[Exercise],
+        new: &[Exercise],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut exercises = self.store.get_all_by_due_date_desc();
+        exercises.retain(|exercise| !updated.iter().any(|e| e.id == exercise.id));
+        exercises.extend(updated.iter().cloned());
+
+        // an ID can only refer to an exercise that's already saved. checked
+        // here rather than left for the store, since the store would happily
+        // point it at one of the new exercises if it got that ID.
+        for prerequisite in updated.iter().chain(new).flat_map(|e| &e.prerequisites) {
+            if let Prerequisite::Id(id) = prerequisite {
+                if !exercises.iter().any(|exercise| exercise.id == Some(*id)) {
                     return Err(make_error(format!(
-                        "Exercise {} has a blank or missing reference answer.",
-                        human_index
+                        "Prerequisite {} does not exist",
+                        prerequisite
                     )));
                 }
             }
-            Ok(exercises
-                .iter()
-                .map(|e| {
-                    Exercise::new(
-                        &e.description.trim(),
-                        &e.source.trim(),
-                        &e.reference_answer.trim(),
-                    )
-                })
-                .collect::<Vec<_>>())
         }
-        Err(yaml_err) => Err(Box::new(yaml_err)),
-    }
-}
-
-pub fn parse_updated_exercise(path: &Path) -> Result<ExportedExercise, Box<dyn Error>> {
-    let content = std::fs::read_to_string(&path)?;
 
-    match convert_yaml_str_to_updated_exercise(&content) {
-        Ok(mut exercise) => {
-            if yaml_string_is_empty(&exercise.description) {
-                return Err(make_error(
-                    "Exercise has a blank or missing description.".to_string(),
-                ));
-            } else if yaml_string_is_empty(&exercise.source) {
-                return Err(make_error(
-                    "Exercise has a blank or missing source.".to_string(),
-                ));
-            } else if yaml_string_is_empty(&exercise.reference_answer) {
-                return Err(make_error(
-                    "Exercise has a blank or missing reference answer.".to_string(),
-                ));
+        // new exercises only get their real IDs once they're saved, so they
+        // get negative ones in the meantime that can't be mistaken for those,
+        // still counting up in the order they'll be saved in
+        exercises.extend(new.iter().enumerate().map(|(i, exercise)| {
+            let mut exercise = exercise.clone();
+            exercise.id = Some(i as i32 - new.len() as i32);
+            exercise
+        }));
+
+        let ids_by_description = exercises
+            .iter()
+            .filter_map(|exercise| exercise.id.map(|id| (exercise.description.clone(), id)))
+            .collect::<HashMap<_, _>>();
+        for exercise in exercises.iter_mut() {
+            for prerequisite in exercise.prerequisites.iter_mut() {
+                if let Prerequisite::Description(description) = prerequisite {
+                    if let Some(&id) = ids_by_description.get(description) {
+                        *prerequisite = Prerequisite::Id(id);
+                    }
+                }
             }
-            exercise.description = exercise.description.trim().to_string();
-            exercise.source = exercise.source.trim().to_string();
-            exercise.reference_answer = exercise.reference_answer.trim().to_string();
-            Ok(exercise)
         }
-        Err(yaml_err) => Err(Box::new(yaml_err)),
-    }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        if let Some(cycle) = deps::find_cycle(&exercises) {
+            let descriptions = cycle
+                .iter()
+                .filter_map(|&id| exercises.iter().find(|exercise| exercise.id == Some(id)))
+                .map(|exercise| format!("\"{}\"", exercise.description))
+                .collect::<Vec<_>>();
+
+            return Err(make_error(format!(
+                "Prerequisites can't go around in a circle: {}",
+                descriptions.join(" needs ")
+            )));
+        }
 
-    fn stringify_boxed_error(e: Box<dyn Error>) -> String {
-        format!("{}", e)
+        Ok(())
     }
 
-    #[test]
-    fn test_schema_bootstrapping_dropping_loaded() {
-        assert!(ExerciseService::maybe_new_test().is_ok());
+    pub fn save_parsed_exercises(&self, exercises: &[Exercise]) -> Result<(), Box<dyn Error>> {
+        if exercises
+            .iter()
+            .any(|exercise| !exercise.prerequisites.is_empty())
+        {
+            self.check_prerequisites(&[], exercises)?;
+        }
 
-        let service = ExerciseService::new_test();
+        self.store.save(exercises)
+    }
 
-        assert!(service.schema_is_loaded());
+    /* Saves the exercise along with its siblings: the rest of its cloze, or the other side of a reversed exercise. */
+    pub fn update_exercise(&self, exercise: &Exercise) -> Result<u64, Box<dyn Error>> {
+        if let Some(text) = &exercise.cloze {
+            return self.update_cloze(exercise, text);
+        }
+        if exercise.cloze_number.is_some() {
+            return self.leave_cloze(exercise);
+        }
 
-        service.drop_schema().unwrap();
+        let mut updated = vec![exercise.clone()];
+        updated.extend(self.reversed_siblings(exercise));
 
-        assert!(!service.schema_is_loaded());
+        if !exercise.prerequisites.is_empty() {
+            self.check_prerequisites(&updated, &[])?;
+        }
 
-        service.bootstrap_schema().unwrap();
-        assert!(service.schema_is_loaded());
+        if updated.len() == 1 {
+            return self.store.update(exercise);
+        }
 
-        service.drop_schema().unwrap();
+        self.store.update_all(&updated)?;
+        Ok(1)
+    }
+
+    // the other side of a reversed exercise, with its description and
+    // reference answer swapped back from this one's. everything else about it,
+    // like its scheduling, tags and source, is its own.
+    fn reversed_siblings(&self, exercise: &Exercise) -> Vec<Exercise> {
+        let sibling_group = match &exercise.sibling_group {
+            Some(sibling_group) => sibling_group,
+            None => return vec![],
+        };
+
+        self.store
+            .get_siblings(sibling_group)
+            .into_iter()
+            .filter(|sibling| sibling.cloze.is_none() && sibling.id != exercise.id)
+            .map(|mut sibling| {
+                sibling.description = exercise.reference_answer.clone();
+                sibling.reference_answer = exercise.description.clone();
+                sibling
+            })
+            .collect()
+    }
+
+    // remakes every exercise from the same cloze as this one out of its text,
+    // which may have been edited. each deletion number keeps its scheduling,
+    // new deletions are added and the ones that are gone are deleted, all in
+    // one go.
+    fn update_cloze(&self, exercise: &Exercise, text: &str) -> Result<u64, Box<dyn Error>> {
+        let mut edited = exercise.clone();
+        if edited.cloze_number.is_none() {
+            // an exercise that has only just been made into a cloze
+            edited.cloze_number = cloze::expand(text)?.first().map(|d| d.number);
+        }
+
+        let mut siblings = match &edited.sibling_group {
+            Some(sibling_group) => self
+                .store
+                .get_siblings(sibling_group)
+                .into_iter()
+                .filter(|sibling| sibling.cloze.is_some() && sibling.id != edited.id)
+                .collect(),
+            None => vec![],
+        };
+        siblings.push(edited.clone());
+
+        let mut template = Exercise::new("", &edited.source, "");
+        template.tags = edited.tags.clone();
+        template.prerequisites = edited.prerequisites.clone();
+        template.deck = edited.deck.clone();
+        template.sibling_group = Some(
+            edited
+                .sibling_group
+                .clone()
+                .unwrap_or_else(new_sibling_group),
+        );
+
+        let expanded_exercises = expand_cloze(&template, text)?;
+
+        if !expanded_exercises
+            .iter()
+            .any(|expanded| expanded.cloze_number == edited.cloze_number)
+        {
+            return Err(make_error(format!(
+                "Exercise {} is deletion c{} of its cloze, which the new text doesn't have. Delete the exercise instead.",
+                edited.id.unwrap_or(-1),
+                edited.cloze_number.unwrap_or(-1)
+            )));
+        }
+
+        let mut updated = vec![];
+        let mut new = vec![];
+
+        for expanded in expanded_exercises {
+            match siblings
+                .iter()
+                .find(|sibling| sibling.cloze_number == expanded.cloze_number)
+            {
+                Some(sibling) => {
+                    let mut sibling = sibling.clone();
+                    sibling.description = expanded.description;
+                    sibling.source = expanded.source;
+                    sibling.reference_answer = expanded.reference_answer;
+                    sibling.tags = expanded.tags;
+                    sibling.prerequisites = expanded.prerequisites;
+                    sibling.sibling_group = expanded.sibling_group;
+                    sibling.cloze = expanded.cloze;
+                    updated.push(sibling);
+                }
+                None => new.push(expanded),
+            }
+        }
+
+        let deleted = siblings
+            .iter()
+            .filter(|sibling| !updated.contains(sibling))
+            .filter_map(|sibling| sibling.id)
+            .collect::<Vec<_>>();
+
+        if !edited.prerequisites.is_empty() {
+            self.check_prerequisites(&updated, &new)?;
+        }
+
+        self.store.replace_siblings(&updated, &new, &deleted)?;
+
+        Ok(updated.len() as u64)
+    }
+
+    // an exercise from a cloze that's been edited into an ordinary exercise.
+    // the other exercises from the cloze would make its deletion again the
+    // next time one of them is edited, so it can only leave once it's the
+    // last one.
+    fn leave_cloze(&self, exercise: &Exercise) -> Result<u64, Box<dyn Error>> {
+        if let Some(sibling_group) = &exercise.sibling_group {
+            let sibling_ids = self
+                .store
+                .get_siblings(sibling_group)
+                .iter()
+                .filter(|sibling| sibling.cloze.is_some() && sibling.id != exercise.id)
+                .filter_map(|sibling| sibling.id)
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>();
+
+            if !sibling_ids.is_empty() {
+                return Err(make_error(format!(
+                    "Exercise {} can't stop being a cloze while other exercises are made from the same one ({}). Keep its cloze deletions, or delete the others first.",
+                    exercise.id.unwrap_or(-1),
+                    sibling_ids.join(", ")
+                )));
+            }
+        }
+
+        let mut exercise = exercise.clone();
+        exercise.sibling_group = None;
+        exercise.cloze_number = None;
+
+        self.update_exercise(&exercise)
+    }
+
+    // looks up the exercises in a tree from the deps module
+    fn exercise_tree(&self, tree: Vec<(usize, i32)>) -> Vec<(usize, Exercise)> {
+        let exercises = self.store.get_all_by_due_date_desc();
+
+        tree.into_iter()
+            .filter_map(|(depth, id)| {
+                exercises
+                    .iter()
+                    .find(|exercise| exercise.id == Some(id))
+                    .map(|exercise| (depth, exercise.clone()))
+            })
+            .collect()
+    }
+
+    /* Everything the exercise builds on, depth first, with how many levels down each one is. */
+    pub fn get_prerequisite_tree(&self, pk: i32) -> Vec<(usize, Exercise)> {
+        let tree = deps::prerequisite_tree(&self.store.get_all_by_due_date_desc(), pk);
+        self.exercise_tree(tree)
+    }
+
+    /* Everything that builds on the exercise, depth first, with how many levels up each one is. */
+    pub fn get_dependent_tree(&self, pk: i32) -> Vec<(usize, Exercise)> {
+        let tree = deps::dependent_tree(&self.store.get_all_by_due_date_desc(), pk);
+        self.exercise_tree(tree)
+    }
+
+    /* Reschedules the exercise according to how the review went and saves it along with a record of the review. */
+    pub fn review_exercise(
+        &self,
+        exercise: &mut Exercise,
+        grade: Grade,
+        time_spent: Duration,
+    ) -> Result<Review, Box<dyn Error>> {
+        let exercise_id = saved_exercise_id(exercise)?;
+
+        let interval_before = exercise.update_interval;
+        let history = self.store.get_reviews(exercise_id);
+
+        let lapse = exercise.scheduling_state().is_lapse(grade);
+
+        let (scheduler, scheduling) = self.deck_scheduler(exercise.deck.as_deref());
+
+        let today = todays_date();
+        let mut state = scheduler.next_state(exercise.scheduling_state(), grade, &history, today);
+
+        // relearning steps are meant to be short and exact
+        if grade.is_correct() && state.relearning_step.is_none() {
+            let update_interval = scheduler::balance::adjust_interval(
+                scheduling,
+                state.update_interval,
+                today,
+                |first, last| self.store.schedule_between(first, last, exercise_id),
+                &mut rand::thread_rng(),
+            );
+
+            state.update_interval = update_interval;
+            state.due_at = today + Duration::days(i64::from(update_interval));
+        }
+
+        exercise.set_scheduling_state(state);
+        exercise.last_grade = Some(grade);
+        if exercise.is_new() {
+            exercise.first_reviewed_at = Some(today);
+        }
+
+        if lapse {
+            exercise.lapses += 1;
+
+            if scheduling.suspend_leeches && self.is_leech(exercise) {
+                exercise.suspended = true;
+            }
+        }
+
+        let mut review = Review {
+            id: None,
+            exercise_id,
+            reviewed_at: Utc::now(),
+            outcome: Outcome::Graded(grade),
+            time_spent_seconds: time_spent.num_seconds() as i32,
+            interval_before,
+            interval_after: exercise.update_interval,
+            lapse,
+            relearning_step: exercise.relearning_step,
+        };
+
+        review.id = Some(self.store.save_review(exercise, &review)?);
+
+        Ok(review)
+    }
+
+    /* Records that the exercise was moved to the end of the session without being answered. */
+    pub fn skip_exercise(
+        &self,
+        exercise: &Exercise,
+        time_spent: Duration,
+    ) -> Result<Review, Box<dyn Error>> {
+        self.record_unanswered(exercise, Outcome::Skipped, time_spent)
+    }
+
+    /* Puts the exercise off until tomorrow without changing its interval. */
+    pub fn bury_exercise(
+        &self,
+        exercise: &mut Exercise,
+        time_spent: Duration,
+    ) -> Result<Review, Box<dyn Error>> {
+        saved_exercise_id(exercise)?;
+
+        exercise.due_at = todays_date() + Duration::days(i64::from(scheduler::ONE_DAY));
+
+        self.record_unanswered(exercise, Outcome::Buried, time_spent)
+    }
+
+    fn record_unanswered(
+        &self,
+        exercise: &Exercise,
+        outcome: Outcome,
+        time_spent: Duration,
+    ) -> Result<Review, Box<dyn Error>> {
+        let mut review = Review {
+            id: None,
+            exercise_id: saved_exercise_id(exercise)?,
+            reviewed_at: Utc::now(),
+            outcome,
+            time_spent_seconds: time_spent.num_seconds() as i32,
+            interval_before: exercise.update_interval,
+            interval_after: exercise.update_interval,
+            lapse: false,
+            relearning_step: exercise.relearning_step,
+        };
+
+        review.id = Some(self.store.save_review(exercise, &review)?);
+
+        Ok(review)
+    }
+
+    /* Takes back a review, given the exercise as it was before it. */
+    pub fn undo_review(&self, snapshot: &Exercise, review: &Review) -> Result<(), Box<dyn Error>> {
+        let review_id = match review.id {
+            Some(id) => id,
+            None => {
+                return Err(make_error(
+                    "Cannot undo a review that has not been saved".to_string(),
+                ))
+            }
+        };
+
+        if snapshot.id != Some(review.exercise_id) {
+            return Err(make_error(format!(
+                "Review {} is not a review of exercise {}",
+                review_id,
+                snapshot.id.unwrap_or(-1)
+            )));
+        }
+
+        self.store.undo_review(snapshot, review_id)
+    }
+
+    /* Every review of an exercise, oldest first. */
+    pub fn get_reviews(&self, exercise_id: i32) -> Vec<Review> {
+        self.store.get_reviews(exercise_id)
+    }
+}
+
+pub fn parse_exercises(path: &Path) -> Result<Vec<Exercise>, Box<dyn Error>> {
+    let content = std::fs::read_to_string(path)?;
+
+    match convert_yaml_str_to_exercises(&content) {
+        Ok(exercises) => {
+            for (i, exercise) in exercises.iter().enumerate() {
+                let human_index = i + 1;
+                if yaml_string_is_empty(&exercise.description) {
+                    return Err(make_error(format!(
+                        "Exercise {} has a blank or missing description.",
+                        human_index
+                    )));
+                } else if yaml_string_is_empty(&exercise.source) {
+                    return Err(make_error(format!(
+                        "Exercise {} has a blank or missing source.",
+                        human_index
+                    )));
+                } else if let Some(error) =
+                    reference_answer_error(&exercise.description, &exercise.reference_answer)
+                {
+                    return Err(make_error(format!("Exercise {} {}", human_index, error)));
+                } else if let Some(error) = variables_error(
+                    &exercise.description,
+                    &exercise.reference_answer,
+                    &exercise.variables,
+                ) {
+                    return Err(make_error(format!("Exercise {} {}", human_index, error)));
+                } else if exercise.reverse && cloze::has_deletions(&exercise.description) {
+                    return Err(make_error(format!(
+                        "Exercise {} is a cloze, so it can't be reversed.",
+                        human_index
+                    )));
+                } else if let Some(tag) = invalid_tag(&exercise.tags) {
+                    return Err(make_error(format!(
+                        "Exercise {} has an invalid tag '{}'. Tags can only contain letters, numbers, '-', '_' and '/'.",
+                        human_index, tag
+                    )));
+                } else if blank_prerequisite(&exercise.prerequisites) {
+                    return Err(make_error(format!(
+                        "Exercise {} has a blank prerequisite.",
+                        human_index
+                    )));
+                }
+            }
+            let mut parsed_exercises = vec![];
+
+            for e in exercises {
+                let mut exercise = Exercise::new(
+                    e.description.trim(),
+                    e.source.trim(),
+                    e.reference_answer.trim(),
+                );
+                exercise.tags = normalize_tags(&e.tags);
+                exercise.prerequisites = normalize_prerequisites(&e.prerequisites);
+                exercise.variables = e.variables.clone();
+
+                if cloze::has_deletions(&exercise.description) {
+                    exercise.sibling_group = Some(new_sibling_group());
+                    let text = exercise.description.clone();
+                    parsed_exercises.extend(expand_cloze(&exercise, &text)?);
+                } else if e.reverse {
+                    exercise.sibling_group = Some(new_sibling_group());
+                    let mut reversed = exercise.clone();
+                    reversed.description = exercise.reference_answer.clone();
+                    reversed.reference_answer = exercise.description.clone();
+                    parsed_exercises.push(exercise);
+                    parsed_exercises.push(reversed);
+                } else {
+                    parsed_exercises.push(exercise);
+                }
+            }
+
+            Ok(parsed_exercises)
+        }
+        Err(yaml_err) => Err(Box::new(yaml_err)),
+    }
+}
+
+pub fn parse_updated_exercise(path: &Path) -> Result<ExportedExercise, Box<dyn Error>> {
+    let content = std::fs::read_to_string(path)?;
+
+    match convert_yaml_str_to_updated_exercise(&content) {
+        Ok(mut exercise) => {
+            if yaml_string_is_empty(&exercise.description) {
+                return Err(make_error(
+                    "Exercise has a blank or missing description.".to_string(),
+                ));
+            } else if yaml_string_is_empty(&exercise.source) {
+                return Err(make_error(
+                    "Exercise has a blank or missing source.".to_string(),
+                ));
+            } else if let Some(error) =
+                reference_answer_error(&exercise.description, &exercise.reference_answer)
+            {
+                return Err(make_error(format!("Exercise {}", error)));
+            } else if let Some(error) = variables_error(
+                &exercise.description,
+                &exercise.reference_answer,
+                &exercise.variables,
+            ) {
+                return Err(make_error(format!("Exercise {}", error)));
+            } else if let Some(tag) = invalid_tag(&exercise.tags) {
+                return Err(make_error(format!(
+                    "Exercise has an invalid tag '{}'. Tags can only contain letters, numbers, '-', '_' and '/'.",
+                    tag
+                )));
+            } else if blank_prerequisite(&exercise.prerequisites) {
+                return Err(make_error("Exercise has a blank prerequisite.".to_string()));
+            }
+            exercise.description = exercise.description.trim().to_string();
+            exercise.source = exercise.source.trim().to_string();
+            exercise.reference_answer = exercise.reference_answer.trim().to_string();
+            exercise.tags = normalize_tags(&exercise.tags);
+            exercise.prerequisites = normalize_prerequisites(&exercise.prerequisites);
+            Ok(exercise)
+        }
+        Err(yaml_err) => Err(Box::new(yaml_err)),
+    }
+}
+
+// tests that go through the configured test database drop and recreate its
+// schema, so they have to take turns. everything else uses an in-memory store
+// and can run in parallel.
+#[cfg(test)]
+static TEST_DATABASE: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // service behavior that depends on what the store does. each check gets a
+    // fresh service, and they all run against an in-memory store and then
+    // against the test database.
+    const SERVICE_CHECKS: &[fn(&ExerciseService)] = &[
+        check_review_history,
+        check_undo_review,
+        check_shift_schedule,
+        check_tags,
+        check_decks,
+        check_prerequisites,
+        check_cloze_update,
+        check_siblings,
+        check_variables,
+    ];
+
+    #[test]
+    fn test_service_checks() {
+        for check in SERVICE_CHECKS {
+            check(&ExerciseService::new_in_memory());
+        }
+    }
+
+    #[test]
+    fn test_service_checks_in_test_database() {
+        let _lock = TEST_DATABASE.lock().unwrap_or_else(|e| e.into_inner());
+
+        for check in SERVICE_CHECKS {
+            check(&ExerciseService::new_test());
+        }
+    }
+
+    fn stringify_boxed_error(e: Box<dyn Error>) -> String {
+        format!("{}", e)
+    }
+
+    // the IDs of exercises, in the order they came back in
+    pub(crate) fn ids(exercises: Vec<Exercise>) -> Vec<i32> {
+        exercises
+            .iter()
+            .map(|exercise| exercise.id.unwrap())
+            .collect()
+    }
+
+    // a test exercise with whatever the test cares about changed
+    pub(crate) fn exercise_with(description: &str, change: impl FnOnce(&mut Exercise)) -> Exercise {
+        let mut exercise = Exercise::new(description, "bar", "baz");
+        change(&mut exercise);
+        exercise
+    }
+
+    #[test]
+    fn test_config_parsing() {
+        let config = parse_config(
+            "live_url = \"live.sqlite3\"
+            test_url = \"test.sqlite3\"
+            backend = \"sqlite\"
+
+            [scheduling]
+            max_interval = 180
+            growth_factor = 2.5
+            review_order = \"most_overdue\"
+
+            [decks.math]
+            time_box_minutes = 30
+
+            [decks.math.scheduling]
+            growth_factor = 3.0",
+        )
+        .unwrap();
+
+        assert_eq!(config.backend, Backend::Sqlite);
+        assert_eq!(config.scheduling.max_interval, 180);
+        assert_eq!(config.scheduling.growth_factor, 2.5);
+        assert_eq!(config.scheduling.first_interval, 1);
+        assert_eq!(config.scheduling.review_order, ReviewOrder::MostOverdue);
+        assert_eq!(config.decks["math"].time_box_minutes, Some(30));
+        assert_eq!(config.decks["math"].scheduling.growth_factor, 3.0);
+        assert_eq!(config.decks["math"].scheduling.max_interval, 180);
+
+        // the whole section is optional
+        let config = parse_config(
+            "live_url = \"live.sqlite3\"
+            test_url = \"test.sqlite3\"",
+        )
+        .unwrap();
+
+        assert_eq!(config.backend, Backend::Postgres);
+        assert_eq!(config.scheduling, SchedulingConfig::default());
+        assert!(config.decks.is_empty());
+    }
+
+    #[test]
+    fn test_schema_migrating_dropping() {
+        let _lock = TEST_DATABASE.lock().unwrap_or_else(|e| e.into_inner());
+
+        assert!(ExerciseService::maybe_new_test().is_ok());
+
+        let service = ExerciseService::new_test();
+
+        assert_eq!(service.schema_version().unwrap(), latest_version());
+        assert!(service.check_schema().is_ok());
+
+        service.drop_schema().unwrap();
+
+        assert_eq!(service.schema_version().unwrap(), 0);
+        assert!(service.check_schema().is_err());
+
+        assert_eq!(service.migrate().unwrap().len(), MIGRATIONS.len());
+        assert!(service.check_schema().is_ok());
+
+        // nothing left to do the second time around
+        assert!(service.migrate().unwrap().is_empty());
+
+        service.drop_schema().unwrap();
+
+        assert_eq!(service.schema_version().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_check_schema_versions() {
+        let service = ExerciseService::new(Box::new(MemoryStore::new()));
+
+        assert_eq!(
+            stringify_boxed_error(service.check_schema().unwrap_err()),
+            "Schema is not loaded. Please run migrate."
+        );
+
+        service.store().apply_migration(&MIGRATIONS[0]).unwrap();
+
+        if latest_version() > 1 {
+            assert!(stringify_boxed_error(service.check_schema().unwrap_err())
+                .contains("Please run migrate."));
+        }
+
+        service.migrate().unwrap();
+        assert!(service.check_schema().is_ok());
 
-        assert!(!service.schema_is_loaded());
+        let future_migration = Migration {
+            version: latest_version() + 1,
+            description: "From a newer version of arete",
+            postgres: "",
+            sqlite: "",
+        };
+        service.store().apply_migration(&future_migration).unwrap();
+
+        assert!(stringify_boxed_error(service.check_schema().unwrap_err())
+            .contains("Please upgrade arete."));
+        assert!(service.migrate().is_err());
     }
 
     #[test]
@@ -597,51 +1669,193 @@ mod tests {
     }
 
     #[test]
-    fn test_indentation_preserved() {
+    fn test_tagged_exercises() {
+        let exercises =
+            parse_exercises(&Path::new("sample_files").join("valid").join("tagged.yaml")).unwrap();
+
+        assert_eq!(exercises.len(), 3);
+        assert_eq!(exercises[0].tags, vec!["errors", "rust"]);
+        assert_eq!(
+            exercises[1].tags,
+            vec!["data-structures", "databases/indexes"]
+        );
+        assert!(exercises[2].tags.is_empty());
+    }
+
+    #[test]
+    fn test_exercises_with_prerequisites() {
         let exercises = parse_exercises(
             &Path::new("sample_files")
                 .join("valid")
-                .join("indentation_preserved.yaml"),
+                .join("prerequisites.yaml"),
         )
         .unwrap();
 
-        assert_eq!(exercises.len(), 1);
+        assert_eq!(exercises.len(), 3);
+        assert!(exercises[0].prerequisites.is_empty());
         assert_eq!(
-            exercises[0].description,
-            "Write out psuedocode for the Fisher-Yates shuffle."
+            exercises[1].prerequisites,
+            vec![Prerequisite::Description(
+                "what is a derivative?".to_string()
+            )]
         );
         assert_eq!(
-            exercises[0].source,
-            "Wikipedia (https://en.wikipedia.org/wiki/Fisher%E2%80%93Yates_shuffle)"
+            exercises[2].prerequisites,
+            vec![
+                Prerequisite::Description("what is the chain rule?".to_string()),
+                Prerequisite::Id(12),
+            ]
         );
-        assert_eq!(exercises[0].reference_answer, "for i from 0 to n-2 do\n  j <- random integer such that i <= j < n\n  exchange a[i] and a[j]");
     }
+
     #[test]
-    fn test_valid_longer_example() {
+    fn test_cloze_exercises() {
+        let exercises =
+            parse_exercises(&Path::new("sample_files").join("valid").join("cloze.yaml")).unwrap();
+
+        assert_eq!(exercises.len(), 3);
+
+        assert_eq!(exercises[0].description, "[...] is the capital of France.");
+        assert_eq!(exercises[0].reference_answer, "Paris");
+        assert_eq!(exercises[0].cloze_number, Some(1));
+        assert_eq!(
+            exercises[1].description,
+            "Paris is the capital of [a country]."
+        );
+        assert_eq!(exercises[1].reference_answer, "France");
+        assert_eq!(exercises[1].cloze_number, Some(2));
+
+        for exercise in &exercises[..2] {
+            assert_eq!(exercise.source, "an atlas");
+            assert_eq!(exercise.tags, vec!["geography"]);
+            assert_eq!(
+                exercise.cloze.as_deref(),
+                Some("{{c1::Paris}} is the capital of {{c2::France::a country}}.")
+            );
+        }
+
+        assert!(exercises[0].sibling_group.is_some());
+        assert_eq!(exercises[0].sibling_group, exercises[1].sibling_group);
+
+        assert_eq!(exercises[2].reference_answer, "Rome");
+        assert_eq!(exercises[2].sibling_group, None);
+        assert_eq!(exercises[2].cloze, None);
+    }
+
+    #[test]
+    fn test_reversed_exercises() {
         let exercises = parse_exercises(
             &Path::new("sample_files")
                 .join("valid")
-                .join("thinking_like_a_programmer.yaml"),
+                .join("reversed.yaml"),
         )
         .unwrap();
 
-        assert_eq!(exercises.len(), 1);
-        assert_eq!(exercises[0].description, "A farmer with a fox, a goose, and a sack of corn needs to cross a river.\nThe farmer has a rowboat, but there is room for only the farmer and one\nof his three items. Unfortunately, both the fox and the goose are hungry.\nThe fox cannot be left alone with the goose, or the fox will eat the\ngoose. Likewise, the goose cannot be left alone with the sack of corn, or\nthe goose will eat the corn. How does the farmer get everything across\nthe river?");
-        assert_eq!(exercises[0].source, "Thinking Like A Programmer, p. 3");
-        assert_eq!(exercises[0].reference_answer, "The key is to take things back after moving them. First take the goose\nacross, leaving the fox with the corn: (f c, g). Take the fox across.\nTake the goose back to the corn: (g c, f). Now we\'re home free: take the\ncorn across and leave it with the fox. Go back, get the corn, and bring\nit across.\n\nThe solution here is to swap the fox and the goose once the goose has\nbeen transferred.");
+        assert_eq!(exercises.len(), 3);
+
+        assert_eq!(exercises[0].description, "idempotent");
+        assert_eq!(
+            exercises[0].reference_answer,
+            "gives the same result when applied more than once"
+        );
+        assert_eq!(
+            exercises[1].description,
+            "gives the same result when applied more than once"
+        );
+        assert_eq!(exercises[1].reference_answer, "idempotent");
+        assert_eq!(exercises[1].source, "a math glossary");
+        assert_eq!(exercises[1].tags, vec!["math"]);
+
+        assert!(exercises[0].sibling_group.is_some());
+        assert_eq!(exercises[0].sibling_group, exercises[1].sibling_group);
+        assert_eq!(exercises[2].sibling_group, None);
     }
 
     #[test]
-    fn test_parsing_error_handling() {
-        // test each of the sample files
-
-        {
-            let exercises = parse_exercises(
-                &Path::new("sample_files")
-                    .join("invalid")
-                    .join("completely_invalid.yaml"),
-            );
-            assert!(exercises.is_err());
+    fn test_exercises_with_variables() {
+        let exercises = parse_exercises(
+            &Path::new("sample_files")
+                .join("valid")
+                .join("variables.yaml"),
+        )
+        .unwrap();
+
+        assert_eq!(exercises.len(), 2);
+        assert_eq!(exercises[0].description, "What is {a} * {b}?");
+        assert_eq!(exercises[0].variables["a"], "2..12");
+        assert_eq!(exercises[1].variables.len(), 4);
+        assert_eq!(exercises[1].variables["x"], "-10..10");
+        assert_eq!(exercises[1].variables["c"], "a * x + b");
+
+        for _ in 0..20 {
+            let exercise = exercises[1].instantiate().unwrap();
+            let numbers = exercise
+                .description
+                .split(|c: char| !c.is_ascii_digit() && c != '-')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.parse::<i32>().unwrap())
+                .collect::<Vec<_>>();
+            let x = exercise
+                .reference_answer
+                .trim_start_matches("x = ")
+                .parse::<i32>()
+                .unwrap();
+
+            // a, b and c, in that order
+            assert_eq!(numbers[0] * x + numbers[1], numbers[2]);
+        }
+
+        // nothing to fill in
+        let exercise = Exercise::new("{foo}", "bar", "baz");
+        assert_eq!(exercise.instantiate().unwrap().description, "{foo}");
+    }
+
+    #[test]
+    fn test_indentation_preserved() {
+        let exercises = parse_exercises(
+            &Path::new("sample_files")
+                .join("valid")
+                .join("indentation_preserved.yaml"),
+        )
+        .unwrap();
+
+        assert_eq!(exercises.len(), 1);
+        assert_eq!(
+            exercises[0].description,
+            "Write out psuedocode for the Fisher-Yates shuffle."
+        );
+        assert_eq!(
+            exercises[0].source,
+            "Wikipedia (https://en.wikipedia.org/wiki/Fisher%E2%80%93Yates_shuffle)"
+        );
+        assert_eq!(exercises[0].reference_answer, "for i from 0 to n-2 do\n  j <- random integer such that i <= j < n\n  exchange a[i] and a[j]");
+    }
+    #[test]
+    fn test_valid_longer_example() {
+        let exercises = parse_exercises(
+            &Path::new("sample_files")
+                .join("valid")
+                .join("thinking_like_a_programmer.yaml"),
+        )
+        .unwrap();
+
+        assert_eq!(exercises.len(), 1);
+        assert_eq!(exercises[0].description, "A farmer with a fox, a goose, and a sack of corn needs to cross a river.\nThe farmer has a rowboat, but there is room for only the farmer and one\nof his three items. Unfortunately, both the fox and the goose are hungry.\nThe fox cannot be left alone with the goose, or the fox will eat the\ngoose. Likewise, the goose cannot be left alone with the sack of corn, or\nthe goose will eat the corn. How does the farmer get everything across\nthe river?");
+        assert_eq!(exercises[0].source, "Thinking Like A Programmer, p. 3");
+        assert_eq!(exercises[0].reference_answer, "The key is to take things back after moving them. First take the goose\nacross, leaving the fox with the corn: (f c, g). Take the fox across.\nTake the goose back to the corn: (g c, f). Now we\'re home free: take the\ncorn across and leave it with the fox. Go back, get the corn, and bring\nit across.\n\nThe solution here is to swap the fox and the goose once the goose has\nbeen transferred.");
+    }
+
+    #[test]
+    fn test_parsing_error_handling() {
+        // test each of the sample files
+
+        {
+            let exercises = parse_exercises(
+                &Path::new("sample_files")
+                    .join("invalid")
+                    .join("completely_invalid.yaml"),
+            );
+            assert!(exercises.is_err());
 
             if let Err(e) = exercises {
                 assert_eq!(
@@ -649,7 +1863,7 @@ mod tests {
                     "invalid type: string \"blah\", expected a sequence at line 1 column 1"
                 );
             } else {
-                assert!(false);
+                unreachable!();
             }
         }
 
@@ -664,10 +1878,10 @@ mod tests {
             if let Err(e) = exercises {
                 assert_eq!(
                     stringify_boxed_error(e),
-                    ".[0]: missing field `reference_answer` at line 2 column 14"
+                    "Exercise 1 has a blank or missing reference answer."
                 );
             } else {
-                assert!(false);
+                unreachable!();
             }
         }
 
@@ -685,7 +1899,7 @@ mod tests {
                     ".[1]: missing field `source` at line 6 column 14"
                 );
             } else {
-                assert!(false);
+                unreachable!();
             }
         }
 
@@ -704,7 +1918,7 @@ mod tests {
                     ".[0]: missing field `source` at line 2 column 14"
                 );
             } else {
-                assert!(false);
+                unreachable!();
             }
         }
 
@@ -720,7 +1934,7 @@ mod tests {
                 let err_string = stringify_boxed_error(e);
                 assert_eq!(err_string, "Exercise 1 has a blank or missing description.");
             } else {
-                assert!(false);
+                unreachable!();
             }
         }
 
@@ -736,7 +1950,7 @@ mod tests {
                 let err_string = stringify_boxed_error(e);
                 assert_eq!(err_string, "Exercise 1 has a blank or missing source.");
             } else {
-                assert!(false);
+                unreachable!();
             }
         }
 
@@ -755,7 +1969,118 @@ mod tests {
                     "Exercise 1 has a blank or missing reference answer."
                 );
             } else {
-                assert!(false);
+                unreachable!();
+            }
+        }
+
+        {
+            let exercises = parse_exercises(
+                &Path::new("sample_files")
+                    .join("invalid")
+                    .join("invalid_tag.yaml"),
+            );
+            assert!(exercises.is_err());
+
+            if let Err(e) = exercises {
+                let err_string = stringify_boxed_error(e);
+                assert_eq!(
+                    err_string,
+                    "Exercise 1 has an invalid tag 'not fine'. Tags can only contain letters, numbers, '-', '_' and '/'."
+                );
+            } else {
+                unreachable!();
+            }
+        }
+
+        {
+            let exercises = parse_exercises(
+                &Path::new("sample_files")
+                    .join("invalid")
+                    .join("blank_prerequisite.yaml"),
+            );
+            assert!(exercises.is_err());
+
+            if let Err(e) = exercises {
+                let err_string = stringify_boxed_error(e);
+                assert_eq!(err_string, "Exercise 1 has a blank prerequisite.");
+            } else {
+                unreachable!();
+            }
+        }
+
+        {
+            let exercises = parse_exercises(
+                &Path::new("sample_files")
+                    .join("invalid")
+                    .join("cloze_with_reference_answer.yaml"),
+            );
+            assert!(exercises.is_err());
+
+            if let Err(e) = exercises {
+                let err_string = stringify_boxed_error(e);
+                assert_eq!(
+                    err_string,
+                    "Exercise 1 is a cloze, so its reference answers come from its deletions and it can't have one of its own."
+                );
+            } else {
+                unreachable!();
+            }
+        }
+
+        {
+            let exercises = parse_exercises(
+                &Path::new("sample_files")
+                    .join("invalid")
+                    .join("unclosed_cloze.yaml"),
+            );
+            assert!(exercises.is_err());
+
+            if let Err(e) = exercises {
+                let err_string = stringify_boxed_error(e);
+                assert_eq!(
+                    err_string,
+                    "Exercise 1 has an invalid cloze. Cloze deletion c1 is missing its closing '}}'."
+                );
+            } else {
+                unreachable!();
+            }
+        }
+
+        {
+            let exercises = parse_exercises(
+                &Path::new("sample_files")
+                    .join("invalid")
+                    .join("reversed_cloze.yaml"),
+            );
+            assert!(exercises.is_err());
+
+            if let Err(e) = exercises {
+                let err_string = stringify_boxed_error(e);
+                assert_eq!(
+                    err_string,
+                    "Exercise 1 is a cloze, so it can't be reversed."
+                );
+            } else {
+                unreachable!();
+            }
+        }
+
+        {
+            let exercises = parse_exercises(
+                &Path::new("sample_files")
+                    .join("invalid")
+                    .join("invalid_variables.yaml"),
+            );
+            assert!(exercises.is_err());
+
+            if let Err(e) = exercises {
+                let err_string = stringify_boxed_error(e);
+                assert_eq!(
+                    err_string,
+                    "Exercise 1 has invalid variables. Unknown variable 'c'."
+                );
+            } else {
+                unreachable!();
             }
         }
     }
@@ -766,249 +2091,1359 @@ mod tests {
 here is another
 and one more";
 
-        let expected = "  here is a line
-  here is another
-  and one more"
-            .to_string();
+        let expected = "  here is a line
+  here is another
+  and one more"
+            .to_string();
+
+        assert_eq!(pad_multiline_string(multiline_string), expected);
+    }
+
+    #[test]
+    fn test_grepping_for_exercises() {
+        let exercises = vec![Exercise::new("foo", "bar", "baz some data here")];
+
+        let service = ExerciseService::new_in_memory();
+
+        service
+            .save_parsed_exercises(&exercises)
+            .expect("Saving failed");
+
+        let saved_exercises = service.get_all_by_due_date_desc(None);
+
+        assert_eq!(saved_exercises.len(), 1);
+
+        let saved_exercise = &saved_exercises[0];
+
+        assert_eq!(saved_exercise.id.expect("expected ID"), 1);
+
+        let search_by_description = service.grep("foo", None);
+        assert_eq!(search_by_description.len(), 1);
+        assert_eq!(&search_by_description[0], saved_exercise);
+
+        let search_by_source = service.grep("bar", None);
+        assert_eq!(search_by_source.len(), 1);
+        assert_eq!(&search_by_source[0], saved_exercise);
+
+        let search_by_reference_answer = service.grep("some data", None);
+        assert_eq!(search_by_reference_answer.len(), 1);
+        assert_eq!(&search_by_reference_answer[0], saved_exercise);
+
+        let search_by_id = service.grep("1", None);
+        assert_eq!(search_by_id.len(), 1);
+        assert_eq!(&search_by_id[0], saved_exercise);
+
+        let invalid_query = service.grep("blah", None);
+        assert_eq!(invalid_query.len(), 0);
+    }
+
+    #[test]
+    fn test_export_saved_exercise() {
+        let exercises = vec![Exercise::new("foo", "bar", "baz")];
+
+        let service = ExerciseService::new_in_memory();
+
+        service
+            .save_parsed_exercises(&exercises)
+            .expect("Saving failed");
+
+        let mut saved_exercises = service.get_all_by_due_date_desc(None);
+
+        assert_eq!(saved_exercises.len(), 1);
+
+        let saved_exercise = &mut saved_exercises[0];
+
+        let path = Path::new("id_export_test.yaml");
+
+        if path.exists() {
+            std::fs::remove_file(path).expect("We tried to delete a file that didn't exist?");
+        }
+        saved_exercise.yaml_export(path).expect("Failed to export");
+
+        let data =
+            fs::read_to_string(Path::new("id_export_test.yaml")).expect("Failed to read back in");
+
+        println!("here is data:");
+        println!("{}", data);
+        assert!(data.contains("description: |+\n  foo"));
+        assert!(data.contains("source: |+\n  bar"));
+        assert!(data.contains("id: 1"));
+        assert!(data.contains("reference_answer: |+\n  baz"));
+        assert!(data.contains("tags: []"));
+
+        // test that it overwrites existing files
+
+        saved_exercise.description = "quux".to_string();
+        saved_exercise.source = "quux 2".to_string();
+        saved_exercise.reference_answer = "quux 3".to_string();
+        saved_exercise.tags = vec!["a/b".to_string(), "c".to_string()];
+        saved_exercise.yaml_export(path).expect("Failed to export");
+
+        let data = fs::read_to_string(path).expect("Failed to read back in");
+
+        assert!(data.contains("description: |+\n  quux"));
+        assert!(data.contains("source: |+\n  quux 2"));
+        assert!(data.contains("id: 1"));
+        assert!(data.contains("reference_answer: |+\n  quux 3"));
+        assert!(data.contains("tags: [a/b, c]"));
+
+        // test that it imports correctly
+
+        let parsed_exercise = parse_updated_exercise(path).expect("should not error out");
+
+        saved_exercise.update_with_values(&parsed_exercise);
+        saved_exercise.update(&service).expect("update failed");
+
+        let saved_exercises = service.get_all_by_due_date_desc(None);
+
+        assert_eq!(saved_exercises.len(), 1);
+
+        assert_eq!(&saved_exercises[0].description, "quux");
+        assert_eq!(saved_exercises[0].tags, vec!["a/b", "c"]);
+
+        if path.exists() {
+            std::fs::remove_file(path).expect("We tried to delete a file that didn't exist?");
+        }
+    }
+
+    #[test]
+    fn test_save_parsed_exercises() {
+        let exercises = vec![
+            Exercise::new("foo", "bar", "baz"),
+            Exercise::new("foo 2", "bar 2", "baz 2"),
+        ];
+
+        let service = ExerciseService::new_in_memory();
+
+        service.save_parsed_exercises(&exercises).unwrap();
+
+        let today = todays_date();
+
+        assert_eq!(service.get_exercise_stats(None), Some((2, today)));
+        assert_eq!(service.count_due(None), Some(2));
+
+        let mut saved_exercises = service.get_all_by_due_date_desc(None);
+
+        assert_eq!(saved_exercises.len(), 2);
+
+        assert_eq!(saved_exercises[0].id, Some(2));
+        assert_eq!(saved_exercises[0].created_at, today);
+        assert_eq!(saved_exercises[0].due_at, today);
+        assert_eq!(saved_exercises[0].description, "foo 2");
+        assert_eq!(saved_exercises[0].source, "bar 2");
+        assert_eq!(saved_exercises[0].reference_answer, "baz 2");
+        assert_eq!(saved_exercises[0].update_interval, 0);
+        assert_eq!(saved_exercises[0].consecutive_successful_reviews, 0);
+
+        assert_eq!(service.get_by_pk(2).unwrap(), saved_exercises[0]);
+
+        assert_eq!(saved_exercises[1].id, Some(1));
+        assert_eq!(saved_exercises[1].created_at, today);
+        assert_eq!(saved_exercises[1].due_at, today);
+        assert_eq!(saved_exercises[1].description, "foo");
+        assert_eq!(saved_exercises[1].source, "bar");
+        assert_eq!(saved_exercises[1].reference_answer, "baz");
+        assert_eq!(saved_exercises[1].update_interval, 0);
+        assert_eq!(saved_exercises[1].consecutive_successful_reviews, 0);
+
+        let first_exercise = &mut saved_exercises[0];
+        first_exercise.due_at += Duration::days(1);
+        assert!(first_exercise.update(&service).is_ok());
+
+        let due = service.get_due(None);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0], saved_exercises[1]);
+        assert_eq!(service.count_due(None), Some(1));
+    }
+
+    #[test]
+    fn test_save_parsed_exercise_transaction_handling() {
+        let exercises = vec![
+            Exercise::new("foo", "bar", "baz"),
+            // duplicate description
+            Exercise::new("foo", "bar 2", "baz 2"),
+        ];
+
+        let _lock = TEST_DATABASE.lock().unwrap_or_else(|e| e.into_inner());
+
+        let service = ExerciseService::new_test();
+        let result = service.save_parsed_exercises(&exercises);
+
+        assert!(result.is_err());
+
+        let e = result.unwrap_err();
+
+        let error_string = stringify_boxed_error(e);
+
+        assert_eq!(error_string, "database error: ERROR: duplicate key value violates unique constraint \"exercises_description_key\"");
+    }
+
+    #[test]
+    fn test_in_memory_save_parsed_exercise_transaction_handling() {
+        let service = ExerciseService::new_in_memory();
+
+        service
+            .save_parsed_exercises(&[Exercise::new("foo", "bar", "baz")])
+            .unwrap();
+
+        let result = service.save_parsed_exercises(&[
+            Exercise::new("foo 2", "bar 2", "baz 2"),
+            // duplicate of the exercise already saved
+            Exercise::new("foo", "bar 3", "baz 3"),
+        ]);
+
+        assert_eq!(
+            stringify_boxed_error(result.unwrap_err()),
+            "An exercise with description \"foo\" already exists"
+        );
+
+        // nothing from the failed batch was saved
+        assert_eq!(service.get_all_by_due_date_desc(None).len(), 1);
+        assert!(service.grep("foo 2", None).is_empty());
+
+        let result = service.save_parsed_exercises(&[
+            Exercise::new("foo 2", "bar 2", "baz 2"),
+            // duplicate within the same batch
+            Exercise::new("foo 2", "bar 3", "baz 3"),
+        ]);
+
+        assert!(result.is_err());
+        assert_eq!(service.get_all_by_due_date_desc(None).len(), 1);
+
+        let mut exercise = service.get_by_pk(1).unwrap();
+        service
+            .save_parsed_exercises(&[Exercise::new("foo 2", "bar 2", "baz 2")])
+            .unwrap();
+        exercise.description = "foo 2".to_string();
+        assert!(exercise.update(&service).is_err());
+    }
+
+    fn check_review_history(service: &ExerciseService) {
+        service
+            .save_parsed_exercises(&[
+                Exercise::new("foo", "bar", "baz"),
+                Exercise::new("foo 2", "bar 2", "baz 2"),
+            ])
+            .unwrap();
+
+        let mut exercise = service.get_by_pk(1).unwrap();
+
+        assert!(service.get_reviews(1).is_empty());
+
+        let review = service
+            .review_exercise(&mut exercise, Grade::Good, Duration::seconds(42))
+            .unwrap();
+
+        assert_eq!(review.exercise_id, 1);
+        assert_eq!(review.grade(), Some(Grade::Good));
+        assert_eq!(review.time_spent_seconds, 42);
+        assert_eq!(exercise.last_grade, Some(Grade::Good));
+        assert_eq!(review.interval_before, 0);
+        assert_eq!(review.interval_after, 1);
+
+        // the new schedule was saved along with the review
+        let saved_exercise = service.get_by_pk(1).unwrap();
+        assert_eq!(saved_exercise.update_interval, 1);
+        assert_eq!(saved_exercise.due_at, todays_date() + Duration::days(1));
+        assert_eq!(saved_exercise.first_reviewed_at, Some(todays_date()));
+        assert!(service.get_by_pk(2).unwrap().is_new());
+
+        service
+            .review_exercise(&mut exercise, Grade::Good, Duration::seconds(5))
+            .unwrap();
+        service
+            .review_exercise(&mut exercise, Grade::Again, Duration::seconds(90))
+            .unwrap();
+
+        let reviews = service.get_reviews(1);
+        assert_eq!(reviews.len(), 3);
+        assert_eq!(
+            reviews
+                .iter()
+                .map(|r| (r.outcome, r.interval_before, r.interval_after, r.lapse))
+                .collect::<Vec<_>>(),
+            vec![
+                (Outcome::Graded(Grade::Good), 0, 1, false),
+                (Outcome::Graded(Grade::Good), 1, 2, false),
+                (Outcome::Graded(Grade::Again), 2, 0, true),
+            ]
+        );
+        assert_eq!(reviews[2].time_spent_seconds, 90);
+        assert!(reviews[0].reviewed_at <= reviews[2].reviewed_at);
+
+        assert!(service.get_reviews(2).is_empty());
+
+        let mut unsaved_exercise = Exercise::new("quux", "bar", "baz");
+        assert!(service
+            .review_exercise(&mut unsaved_exercise, Grade::Good, Duration::seconds(1))
+            .is_err());
+    }
+
+    struct EveryThreeDays;
+
+    impl Scheduler for EveryThreeDays {
+        fn next_state(
+            &self,
+            state: SchedulingState,
+            _grade: Grade,
+            history: &[Review],
+            today: NaiveDate,
+        ) -> SchedulingState {
+            // the review being scheduled isn't part of the history yet
+            assert_eq!(history.len() as i32, state.consecutive_successful_reviews);
+
+            state.succeeded(&SchedulingConfig::default(), 3, today)
+        }
+    }
+
+    #[test]
+    fn test_review_with_another_scheduler() {
+        let service =
+            ExerciseService::with_scheduler(Box::new(MemoryStore::new()), Box::new(EveryThreeDays));
+        service.migrate().unwrap();
+        service
+            .save_parsed_exercises(&[Exercise::new("foo", "bar", "baz")])
+            .unwrap();
+
+        let mut exercise = service.get_by_pk(1).unwrap();
+
+        for _ in 0..3 {
+            service
+                .review_exercise(&mut exercise, Grade::Again, Duration::seconds(1))
+                .unwrap();
+        }
+
+        let saved_exercise = service.get_by_pk(1).unwrap();
+        assert_eq!(saved_exercise.update_interval, 3);
+        assert_eq!(saved_exercise.consecutive_successful_reviews, 3);
+        assert_eq!(saved_exercise.due_at, todays_date() + Duration::days(3));
+        assert_eq!(saved_exercise.last_grade, Some(Grade::Again));
+    }
+
+    fn check_undo_review(service: &ExerciseService) {
+        service
+            .save_parsed_exercises(&[
+                Exercise::new("foo", "bar", "baz"),
+                Exercise::new("foo 2", "bar 2", "baz 2"),
+            ])
+            .unwrap();
+
+        let mut exercise = service.get_by_pk(1).unwrap();
+        let first_review = service
+            .review_exercise(&mut exercise, Grade::Good, Duration::seconds(1))
+            .unwrap();
+        assert!(first_review.id.is_some());
+
+        let snapshot = exercise.clone();
+        let review = service
+            .review_exercise(&mut exercise, Grade::Again, Duration::seconds(1))
+            .unwrap();
+        assert_eq!(exercise.lapses, 1);
+
+        service.undo_review(&snapshot, &review).unwrap();
+
+        let restored_exercise = service.get_by_pk(1).unwrap();
+        assert_eq!(restored_exercise.update_interval, 1);
+        assert_eq!(restored_exercise.consecutive_successful_reviews, 1);
+        assert_eq!(restored_exercise.due_at, snapshot.due_at);
+        assert_eq!(restored_exercise.last_grade, Some(Grade::Good));
+        assert_eq!(restored_exercise.lapses, 0);
+        assert_eq!(
+            service
+                .get_reviews(1)
+                .iter()
+                .map(|r| r.id)
+                .collect::<Vec<_>>(),
+            vec![first_review.id]
+        );
+
+        // can't be undone twice, or against another exercise
+        assert!(service.undo_review(&snapshot, &review).is_err());
+        let other_exercise = service.get_by_pk(2).unwrap();
+        assert!(service.undo_review(&other_exercise, &first_review).is_err());
+        assert_eq!(service.get_reviews(1).len(), 1);
+
+        // bury can be undone too
+        let mut exercise = restored_exercise;
+        let snapshot = exercise.clone();
+        let review = service
+            .bury_exercise(&mut exercise, Duration::seconds(1))
+            .unwrap();
+        service.undo_review(&snapshot, &review).unwrap();
+        assert_eq!(service.get_by_pk(1).unwrap().due_at, snapshot.due_at);
+    }
+
+    #[test]
+    fn test_review_relearning_steps() {
+        let service = ExerciseService::with_config(
+            Box::new(MemoryStore::new()),
+            SchedulingConfig {
+                lapse_factor: 0.5,
+                relearning_steps: vec![1, 2],
+                ..SchedulingConfig::default()
+            },
+        )
+        .unwrap();
+        service.migrate().unwrap();
+
+        service
+            .save_parsed_exercises(&[Exercise::new("foo", "bar", "baz")])
+            .unwrap();
+        let mut exercise = service.get_by_pk(1).unwrap();
+
+        for _ in 0..4 {
+            service
+                .review_exercise(&mut exercise, Grade::Good, Duration::seconds(1))
+                .unwrap();
+        }
+        assert_eq!(exercise.update_interval, 8);
+
+        let today = todays_date();
+        let grades = [
+            Grade::Again,
+            Grade::Good,
+            Grade::Again,
+            Grade::Good,
+            Grade::Good,
+        ];
+        let reviews = grades
+            .iter()
+            .map(|grade| {
+                service
+                    .review_exercise(&mut exercise, *grade, Duration::seconds(1))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        // failing again while relearning isn't another lapse, and the
+        // interval is only cut once
+        assert_eq!(
+            reviews
+                .iter()
+                .map(|r| (r.lapse, r.relearning_step, r.interval_after))
+                .collect::<Vec<_>>(),
+            vec![
+                (true, Some(0), 4),
+                (false, Some(1), 4),
+                (false, Some(0), 4),
+                (false, Some(1), 4),
+                (false, None, 4),
+            ]
+        );
+        assert_eq!(exercise.consecutive_successful_reviews, 1);
+        assert_eq!(exercise.due_at, today + Duration::days(4));
+
+        let saved_exercise = service.get_by_pk(1).unwrap();
+        assert_eq!(saved_exercise.relearning_step, None);
+        assert_eq!(service.get_reviews(1)[4].relearning_step, Some(0));
+    }
+
+    #[test]
+    fn test_skipping_and_burying() {
+        let service = ExerciseService::new_in_memory();
+        service
+            .save_parsed_exercises(&[Exercise::new("foo", "bar", "baz")])
+            .unwrap();
+
+        let today = todays_date();
+        let mut exercise = service.get_by_pk(1).unwrap();
+        for _ in 0..3 {
+            service
+                .review_exercise(&mut exercise, Grade::Good, Duration::seconds(1))
+                .unwrap();
+        }
+        exercise.due_at = today;
+        service.update_exercise(&exercise).unwrap();
+
+        let review = service
+            .skip_exercise(&exercise, Duration::seconds(3))
+            .unwrap();
+        assert_eq!(review.outcome, Outcome::Skipped);
+        assert_eq!(review.grade(), None);
+        assert_eq!(service.get_by_pk(1).unwrap().due_at, today);
+
+        let review = service
+            .bury_exercise(&mut exercise, Duration::seconds(4))
+            .unwrap();
+        assert_eq!(review.outcome, Outcome::Buried);
+        assert_eq!((review.interval_before, review.interval_after), (4, 4));
+
+        // only the due date moves
+        let saved_exercise = service.get_by_pk(1).unwrap();
+        assert_eq!(saved_exercise.due_at, today + Duration::days(1));
+        assert_eq!(saved_exercise.update_interval, 4);
+        assert_eq!(saved_exercise.consecutive_successful_reviews, 3);
+        assert_eq!(saved_exercise.last_grade, Some(Grade::Good));
+        assert!(service.get_due(None).is_empty());
+
+        let outcomes = service
+            .get_reviews(1)
+            .iter()
+            .map(|r| r.outcome)
+            .collect::<Vec<_>>();
+        assert_eq!(outcomes.len(), 5);
+        assert_eq!(outcomes[3..], [Outcome::Skipped, Outcome::Buried]);
+
+        // the next answer carries on from where the exercise was
+        service
+            .review_exercise(&mut exercise, Grade::Good, Duration::seconds(1))
+            .unwrap();
+        assert_eq!(exercise.update_interval, 8);
+        assert_eq!(exercise.consecutive_successful_reviews, 4);
+
+        let mut unsaved_exercise = Exercise::new("quux", "bar", "baz");
+        assert!(service
+            .bury_exercise(&mut unsaved_exercise, Duration::seconds(1))
+            .is_err());
+        assert_eq!(unsaved_exercise.due_at, today);
+    }
+
+    fn check_shift_schedule(service: &ExerciseService) {
+        let exercises = (0..6)
+            .map(|i| Exercise::new(&format!("foo {}", i), "bar", "baz"))
+            .collect::<Vec<_>>();
+        service.save_parsed_exercises(&exercises).unwrap();
+
+        let today = todays_date();
+        let day = |n| today + Duration::days(n);
+
+        // four overdue, one due later and one suspended
+        for mut exercise in service.get_all_by_due_date_desc(None) {
+            let id = exercise.id.unwrap();
+            exercise.due_at = if id <= 4 {
+                day(-i64::from(id))
+            } else {
+                day(10)
+            };
+            exercise.suspended = id == 6;
+            service.update_exercise(&exercise).unwrap();
+        }
+        let schedule = service.get_schedule(None);
+
+        let preview = service.shift_schedule(3, 0, true).unwrap();
+        assert_eq!(
+            preview,
+            vec![
+                (day(-1), 1),
+                (day(0), 1),
+                (day(1), 1),
+                (day(2), 1),
+                (day(13), 1)
+            ]
+        );
+
+        // nothing was saved
+        assert_eq!(service.get_schedule(None), schedule);
+
+        let preview = service.shift_schedule(0, 2, true).unwrap();
+        assert_eq!(preview, vec![(day(0), 2), (day(1), 2), (day(10), 1)]);
+        assert_eq!(service.shift_schedule(0, 2, false).unwrap(), preview);
+        assert_eq!(service.get_schedule(None), preview);
+        assert_eq!(service.count_due(None), Some(2));
+
+        // the most overdue go first
+        assert_eq!(service.get_by_pk(4).unwrap().due_at, day(0));
+        assert_eq!(service.get_by_pk(1).unwrap().due_at, day(1));
+        assert_eq!(service.get_by_pk(6).unwrap().due_at, day(10));
+
+        assert!(service.shift_schedule(-1, 0, true).is_err());
+    }
+
+    #[test]
+    fn test_get_due_for_review() {
+        let service = ExerciseService::with_config(
+            Box::new(MemoryStore::new()),
+            SchedulingConfig {
+                review_order: ReviewOrder::MostOverdue,
+                ..SchedulingConfig::default()
+            },
+        )
+        .unwrap();
+        service.migrate().unwrap();
+
+        let exercises = (1..=3)
+            .map(|i| Exercise::new(&format!("foo {}", i), "bar", "baz"))
+            .collect::<Vec<_>>();
+        service.save_parsed_exercises(&exercises).unwrap();
+
+        let mut exercise = service.get_by_pk(2).unwrap();
+        exercise.due_at = todays_date() - Duration::days(3);
+        service.update_exercise(&exercise).unwrap();
 
-        assert_eq!(pad_multiline_string(&multiline_string), expected);
+        assert_eq!(ids(service.get_due(None)), vec![3, 1, 2]);
+        assert_eq!(
+            ids(service.get_due_for_review(None, None, None)),
+            vec![2, 1, 3]
+        );
+        assert_eq!(
+            ids(service.get_due_for_review(Some(ReviewOrder::DueDate), None, None)),
+            vec![3, 1, 2]
+        );
     }
 
     #[test]
-    fn test_grepping_for_exercises() {
-        let exercises = vec![Exercise::new("foo", "bar", "baz some data here")];
+    fn test_new_exercise_limit() {
+        let service = ExerciseService::with_config(
+            Box::new(MemoryStore::new()),
+            SchedulingConfig {
+                new_per_day: 2,
+                ..SchedulingConfig::default()
+            },
+        )
+        .unwrap();
+        service.migrate().unwrap();
 
-        let service = ExerciseService::new_test();
+        let exercises = (1..=5)
+            .map(|i| Exercise::new(&format!("foo {}", i), "bar", "baz"))
+            .collect::<Vec<_>>();
+        service.save_parsed_exercises(&exercises).unwrap();
+
+        let due_ids = || ids(service.get_due(None));
+
+        // the first ones imported go first
+        assert_eq!(due_ids(), vec![2, 1]);
+        assert_eq!(service.count_due(None), Some(2));
+        assert_eq!(service.count_new_waiting(None), 3);
 
+        let mut exercise = service.get_by_pk(1).unwrap();
         service
-            .save_parsed_exercises(&exercises)
-            .expect("Saving failed");
+            .review_exercise(&mut exercise, Grade::Good, Duration::seconds(1))
+            .unwrap();
+        assert!(!exercise.is_new());
 
-        let saved_exercises = service.get_all_by_due_date_desc();
+        // only one new exercise left for today
+        assert_eq!(due_ids(), vec![2]);
+        assert_eq!(service.count_new_waiting(None), 3);
 
-        assert_eq!(saved_exercises.len(), 1);
+        let mut exercise = service.get_by_pk(2).unwrap();
+        service
+            .review_exercise(&mut exercise, Grade::Again, Duration::seconds(1))
+            .unwrap();
 
-        let saved_exercise = &saved_exercises[0];
+        // failed exercises aren't new any more, so they don't count
+        assert_eq!(due_ids(), vec![2]);
+        assert_eq!(service.count_due(None), Some(1));
+        assert_eq!(service.count_new_waiting(None), 3);
 
-        assert_eq!(saved_exercise.id.expect("expected ID"), 1);
+        // without a limit, everything is due
+        let service = ExerciseService::new_in_memory();
+        service.save_parsed_exercises(&exercises).unwrap();
+        assert_eq!(service.get_due(None).len(), 5);
+        assert_eq!(service.count_new_waiting(None), 0);
+    }
 
-        let search_by_description = service.grep("foo");
-        assert_eq!(search_by_description.len(), 1);
-        assert_eq!(&search_by_description[0], saved_exercise);
+    fn check_tags(service: &ExerciseService) {
+        service
+            .save_parsed_exercises(&[
+                exercise_with("foo 1", |exercise| {
+                    exercise.tags = vec!["a".to_string(), "b".to_string()]
+                }),
+                exercise_with("foo 2", |exercise| exercise.tags = vec!["b".to_string()]),
+                Exercise::new("foo 3", "bar", "baz"),
+            ])
+            .unwrap();
 
-        let search_by_source = service.grep("bar");
-        assert_eq!(search_by_source.len(), 1);
-        assert_eq!(&search_by_source[0], saved_exercise);
+        assert_eq!(ids(service.get_all_by_due_date_desc(Some("b"))), vec![2, 1]);
+        assert_eq!(ids(service.get_due(Some("a"))), vec![1]);
+        assert_eq!(ids(service.grep("foo", Some("a"))), vec![1]);
+        assert_eq!(
+            ids(service.get_due_for_review(None, Some("b"), None)),
+            vec![2, 1]
+        );
+        assert!(service.get_due(Some("c")).is_empty());
+        assert_eq!(service.count_due(Some("b")), Some(2));
+        assert_eq!(service.count_due(None), Some(3));
+        assert_eq!(
+            service.get_exercise_stats(Some("a")),
+            Some((1, todays_date()))
+        );
+        assert_eq!(service.get_exercise_stats(Some("c")), None);
 
-        let search_by_reference_answer = service.grep("some data");
-        assert_eq!(search_by_reference_answer.len(), 1);
-        assert_eq!(&search_by_reference_answer[0], saved_exercise);
+        // tags survive reviews, and updating an exercise replaces them
+        let mut exercise = service.get_by_pk(1).unwrap();
+        service
+            .review_exercise(&mut exercise, Grade::Good, Duration::seconds(1))
+            .unwrap();
+        assert_eq!(service.get_by_pk(1).unwrap().tags, vec!["a", "b"]);
+        assert_eq!(
+            service.get_schedule(Some("b")),
+            vec![(todays_date(), 1), (todays_date() + Duration::days(1), 1)]
+        );
 
-        let search_by_id = service.grep("1");
-        assert_eq!(search_by_id.len(), 1);
-        assert_eq!(&search_by_id[0], saved_exercise);
+        exercise.tags = vec!["c".to_string()];
+        service.update_exercise(&exercise).unwrap();
+        assert_eq!(ids(service.get_all_by_due_date_desc(Some("b"))), vec![2]);
+        assert!(service.get_all_by_due_date_desc(Some("a")).is_empty());
 
-        let invalid_query = service.grep("blah");
-        assert_eq!(invalid_query.len(), 0);
+        service.delete_by_pk(1).unwrap();
+        assert!(service.get_all_by_due_date_desc(Some("c")).is_empty());
     }
 
-    #[test]
-    fn test_export_saved_exercise() {
-        let exercises = vec![Exercise::new("foo", "bar", "baz")];
+    fn check_decks(service: &ExerciseService) {
+        service
+            .save_parsed_exercises(&[
+                exercise_with("foo 1", |exercise| exercise.deck = Some("math".to_string())),
+                exercise_with("foo 2", |exercise| exercise.deck = Some("rust".to_string())),
+                Exercise::new("foo 3", "bar", "baz"),
+                exercise_with("foo 4", |exercise| exercise.deck = Some("math".to_string())),
+            ])
+            .unwrap();
 
-        let service = ExerciseService::new_test();
+        assert_eq!(
+            ids(service.get_due_for_review(None, None, Some("math"))),
+            vec![4, 1]
+        );
+        assert_eq!(ids(service.get_due_for_review(None, None, None)).len(), 4);
+        assert!(service
+            .get_due_for_review(None, None, Some("nope"))
+            .is_empty());
 
+        let mut exercise = service.get_by_pk(1).unwrap();
         service
-            .save_parsed_exercises(&exercises)
-            .expect("Saving failed");
+            .review_exercise(&mut exercise, Grade::Good, Duration::seconds(1))
+            .unwrap();
+        assert_eq!(service.get_by_pk(1).unwrap().deck.as_deref(), Some("math"));
 
-        let mut saved_exercises = service.get_all_by_due_date_desc();
+        assert_eq!(
+            service.get_deck_counts(),
+            vec![
+                (None, 1, 1),
+                (Some("math".to_string()), 2, 1),
+                (Some("rust".to_string()), 1, 1),
+            ]
+        );
+    }
 
-        assert_eq!(saved_exercises.len(), 1);
+    fn check_prerequisites(service: &ExerciseService) {
+        service
+            .save_parsed_exercises(&[
+                Exercise::new("foo 1", "bar", "baz"),
+                exercise_with("foo 2", |exercise| {
+                    exercise.prerequisites = vec![Prerequisite::Description("foo 1".to_string())]
+                }),
+            ])
+            .unwrap();
+        // an ID can only refer to an exercise that's already been saved
+        service
+            .save_parsed_exercises(&[
+                exercise_with("foo 3", |exercise| {
+                    exercise.prerequisites = vec![Prerequisite::Id(2)]
+                }),
+                Exercise::new("foo 4", "bar", "baz"),
+            ])
+            .unwrap();
 
-        let saved_exercise = &mut saved_exercises[0];
+        let due_ids = || {
+            let mut due = ids(service.get_due(None));
+            due.sort();
+            due
+        };
 
-        let path = Path::new("id_export_test.yaml");
+        assert_eq!(due_ids(), vec![1, 2, 3, 4]);
 
-        if path.exists() {
-            std::fs::remove_file(&path).expect("We tried to delete a file that didn't exist?");
-        }
-        saved_exercise.yaml_export(&path).expect("Failed to export");
+        // only the exercise that directly builds on the failing one waits
+        let mut exercise = service.get_by_pk(1).unwrap();
+        service
+            .review_exercise(&mut exercise, Grade::Again, Duration::seconds(1))
+            .unwrap();
+        assert_eq!(due_ids(), vec![1, 3, 4]);
+        assert_eq!(service.count_due(None), Some(3));
 
-        let data =
-            fs::read_to_string(Path::new("id_export_test.yaml")).expect("Failed to read back in");
+        service
+            .review_exercise(&mut exercise, Grade::Good, Duration::seconds(1))
+            .unwrap();
+        assert_eq!(due_ids(), vec![2, 3, 4]);
 
-        println!("here is data:");
-        println!("{}", data);
-        assert!(data.contains("description: |+\n  foo"));
-        assert!(data.contains("source: |+\n  bar"));
-        assert!(data.contains("id: 1"));
-        assert!(data.contains("reference_answer: |+\n  baz"));
+        // new exercises can only go around in a circle among themselves
+        let description = |description: &str| Prerequisite::Description(description.to_string());
+        assert_eq!(
+            format!(
+                "{}",
+                service
+                    .save_parsed_exercises(&[
+                        exercise_with("foo 5", |exercise| {
+                            exercise.prerequisites =
+                                vec![description("foo 1"), description("foo 6")]
+                        }),
+                        exercise_with("foo 6", |exercise| {
+                            exercise.prerequisites = vec![description("foo 5")]
+                        }),
+                    ])
+                    .unwrap_err()
+            ),
+            "Prerequisites can't go around in a circle: \"foo 5\" needs \"foo 6\" needs \"foo 5\""
+        );
+        assert!(service.get_by_pk(5).is_none());
 
-        // test that it overwrites existing files
+        // 6 is the ID "foo 6" would get, but that doesn't make it a circle
+        assert_eq!(
+            format!(
+                "{}",
+                service
+                    .save_parsed_exercises(&[
+                        exercise_with("foo 5", |exercise| {
+                            exercise.prerequisites = vec![Prerequisite::Id(6)]
+                        }),
+                        exercise_with("foo 6", |exercise| {
+                            exercise.prerequisites = vec![description("foo 5")]
+                        }),
+                    ])
+                    .unwrap_err()
+            ),
+            "Prerequisite 6 does not exist"
+        );
+        assert!(service.get_by_pk(5).is_none());
 
-        saved_exercise.description = "quux".to_string();
-        saved_exercise.source = "quux 2".to_string();
-        saved_exercise.reference_answer = "quux 3".to_string();
-        saved_exercise.yaml_export(&path).expect("Failed to export");
+        let mut exercise = service.get_by_pk(1).unwrap();
+        exercise.prerequisites = vec![Prerequisite::Id(3)];
+        assert_eq!(
+            format!("{}", service.update_exercise(&exercise).unwrap_err()),
+            "Prerequisites can't go around in a circle: \"foo 1\" needs \"foo 3\" needs \"foo 2\" needs \"foo 1\""
+        );
+        assert!(service.get_by_pk(1).unwrap().prerequisites.is_empty());
 
-        let data = fs::read_to_string(&path).expect("Failed to read back in");
+        let tree_ids = |tree: Vec<(usize, Exercise)>| {
+            tree.iter()
+                .map(|(depth, exercise)| (*depth, exercise.id.unwrap()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            tree_ids(service.get_prerequisite_tree(3)),
+            vec![(1, 2), (2, 1)]
+        );
+        assert_eq!(
+            tree_ids(service.get_dependent_tree(1)),
+            vec![(1, 2), (2, 3)]
+        );
+        assert!(service.get_dependent_tree(4).is_empty());
+    }
 
-        assert!(data.contains("description: |+\n  quux"));
-        assert!(data.contains("source: |+\n  quux 2"));
-        assert!(data.contains("id: 1"));
-        assert!(data.contains("reference_answer: |+\n  quux 3"));
+    fn check_cloze_update(service: &ExerciseService) {
+        let mut exercise = Exercise::new("", "an atlas", "");
+        exercise.sibling_group = Some("foo".to_string());
+        exercise.deck = Some("geography".to_string());
+        let exercises =
+            expand_cloze(&exercise, "{{c1::Paris}} is the capital of {{c2::France}}.").unwrap();
+        service
+            .save_parsed_exercises(&[exercises, vec![Exercise::new("foo", "bar", "baz")]].concat())
+            .unwrap();
 
-        // test that it imports correctly
+        let mut exercise = service.get_by_pk(1).unwrap();
+        service
+            .review_exercise(&mut exercise, Grade::Good, Duration::seconds(1))
+            .unwrap();
 
-        let parsed_exercise = parse_updated_exercise(&path).expect("should not error out");
+        // c1 stays, c2 goes and c3 is new
+        exercise.update_with_values(&ExportedExercise {
+            id: 1,
+            description: "{{c1::Paris}} is the capital and largest city of {{c3::France}}."
+                .to_string(),
+            source: "a better atlas".to_string(),
+            reference_answer: "".to_string(),
+            tags: vec!["cities".to_string()],
+            prerequisites: vec![Prerequisite::Id(3)],
+            variables: BTreeMap::new(),
+        });
+        assert_eq!(service.update_exercise(&exercise).unwrap(), 1);
+
+        let exercise = service.get_by_pk(1).unwrap();
+        assert_eq!(
+            exercise.description,
+            "[...] is the capital and largest city of France."
+        );
+        assert_eq!(exercise.reference_answer, "Paris");
+        assert_eq!(exercise.source, "a better atlas");
+        assert_eq!(exercise.tags, vec!["cities"]);
+        assert_eq!(exercise.prerequisites, vec![Prerequisite::Id(3)]);
+        // still scheduled as before
+        assert_eq!(exercise.update_interval, 1);
 
-        saved_exercise.update_with_values(&parsed_exercise);
-        saved_exercise.update(&service).expect("update failed");
+        assert!(service.get_by_pk(2).is_none());
 
-        let saved_exercises = service.get_all_by_due_date_desc();
+        let new_exercise = service.get_by_pk(4).unwrap();
+        assert_eq!(
+            new_exercise.description,
+            "Paris is the capital and largest city of [...]."
+        );
+        assert_eq!(new_exercise.reference_answer, "France");
+        assert_eq!(new_exercise.source, "a better atlas");
+        assert_eq!(new_exercise.deck.as_deref(), Some("geography"));
+        assert_eq!(new_exercise.sibling_group.as_deref(), Some("foo"));
+        assert_eq!(new_exercise.cloze_number, Some(3));
+        assert_eq!(new_exercise.update_interval, 0);
+
+        // an exercise can't take out its own deletion
+        let mut exercise = service.get_by_pk(4).unwrap();
+        exercise.update_with_values(&ExportedExercise {
+            id: 4,
+            description: "{{c1::Paris}} is the capital of France.".to_string(),
+            source: "a better atlas".to_string(),
+            reference_answer: "".to_string(),
+            tags: vec![],
+            prerequisites: vec![],
+            variables: BTreeMap::new(),
+        });
+        assert_eq!(
+            format!("{}", service.update_exercise(&exercise).unwrap_err()),
+            "Exercise 4 is deletion c3 of its cloze, which the new text doesn't have. Delete the exercise instead."
+        );
+        assert!(service.get_by_pk(4).is_some());
+        assert_eq!(service.get_by_pk(1).unwrap().tags, vec!["cities"]);
+
+        // or leave the cloze while the other exercises from it are around
+        let mut exercise = service.get_by_pk(4).unwrap();
+        let plain_text = ExportedExercise {
+            id: 4,
+            description: "What is the capital of France?".to_string(),
+            source: "a better atlas".to_string(),
+            reference_answer: "Paris".to_string(),
+            tags: vec![],
+            prerequisites: vec![],
+            variables: BTreeMap::new(),
+        };
+        exercise.update_with_values(&plain_text);
+        assert_eq!(
+            format!("{}", service.update_exercise(&exercise).unwrap_err()),
+            "Exercise 4 can't stop being a cloze while other exercises are made from the same one (1). Keep its cloze deletions, or delete the others first."
+        );
+        let unchanged = service.get_by_pk(4).unwrap();
+        assert!(unchanged.cloze.is_some());
+        assert_eq!(unchanged.sibling_group.as_deref(), Some("foo"));
+
+        // once it's the last one, taking the deletions out makes it an
+        // ordinary exercise again
+        service.delete_by_pk(1).unwrap();
+        assert_eq!(service.update_exercise(&exercise).unwrap(), 1);
+
+        let exercise = service.get_by_pk(4).unwrap();
+        assert_eq!(exercise.reference_answer, "Paris");
+        assert_eq!(exercise.sibling_group, None);
+        assert_eq!(exercise.cloze, None);
+        assert_eq!(exercise.cloze_number, None);
+    }
 
-        assert_eq!(saved_exercises.len(), 1);
+    fn check_siblings(service: &ExerciseService) {
+        let sibling = |description: &str, reference_answer: &str, sibling_group: &str| {
+            let mut exercise = Exercise::new(description, "bar", reference_answer);
+            exercise.sibling_group = Some(sibling_group.to_string());
+            exercise
+        };
 
-        assert_eq!(&saved_exercises[0].description, "quux");
+        service
+            .save_parsed_exercises(&[
+                sibling("foo", "baz", "a"),
+                sibling("baz", "foo", "a"),
+                sibling("foo 2", "baz 2", "b"),
+                sibling("foo 3", "baz 3", "c"),
+                sibling("baz 3", "foo 3", "c"),
+                Exercise::new("foo 4", "bar", "baz 4"),
+            ])
+            .unwrap();
 
-        if path.exists() {
-            std::fs::remove_file(&path).expect("We tried to delete a file that didn't exist?");
+        let session_groups = || {
+            let mut groups = service
+                .get_due_for_review(None, None, None)
+                .iter()
+                .map(|exercise| exercise.sibling_group.clone())
+                .collect::<Vec<_>>();
+            groups.sort();
+            groups
+        };
+
+        assert_eq!(service.get_due(None).len(), 6);
+        assert_eq!(
+            session_groups(),
+            vec![
+                None,
+                Some("a".to_string()),
+                Some("b".to_string()),
+                Some("c".to_string())
+            ]
+        );
+
+        // the other side comes up once the first one is done, and each side
+        // is scheduled on its own
+        for mut exercise in service.get_due_for_review(None, None, None) {
+            service
+                .review_exercise(&mut exercise, Grade::Good, Duration::seconds(1))
+                .unwrap();
         }
+        assert_eq!(
+            session_groups(),
+            vec![Some("a".to_string()), Some("c".to_string())]
+        );
+        assert_eq!(
+            service
+                .get_all_by_due_date_desc(None)
+                .iter()
+                .filter(|exercise| exercise.update_interval == 1)
+                .count(),
+            4
+        );
+
+        // changing one side's description and reference answer changes the
+        // other's, and nothing else
+        let mut exercise = service.get_by_pk(1).unwrap();
+        exercise.description = "quux".to_string();
+        exercise.source = "quux 2".to_string();
+        exercise.tags = vec!["a".to_string()];
+        service.update_exercise(&exercise).unwrap();
+
+        let reversed = service.get_by_pk(2).unwrap();
+        assert_eq!(reversed.description, "baz");
+        assert_eq!(reversed.reference_answer, "quux");
+        assert_eq!(reversed.source, "bar");
+        assert!(reversed.tags.is_empty());
+        assert_eq!(service.get_by_pk(4).unwrap().description, "foo 3");
     }
 
     #[test]
-    fn test_save_parsed_exercises() {
-        let exercises = vec![
-            Exercise::new("foo", "bar", "baz"),
-            Exercise::new("foo 2", "bar 2", "baz 2"),
-        ];
+    fn test_siblings_new_exercise_limit() {
+        let service = ExerciseService::with_config(
+            Box::new(MemoryStore::new()),
+            SchedulingConfig {
+                new_per_day: 2,
+                ..SchedulingConfig::default()
+            },
+        )
+        .unwrap();
+        service.migrate().unwrap();
 
-        let service = ExerciseService::new_test();
+        let sibling = |description: &str, reference_answer: &str| {
+            let mut exercise = Exercise::new(description, "bar", reference_answer);
+            exercise.sibling_group = Some("a".to_string());
+            exercise
+        };
+        service
+            .save_parsed_exercises(&[
+                sibling("foo", "baz"),
+                sibling("baz", "foo"),
+                Exercise::new("foo 2", "bar", "baz 2"),
+            ])
+            .unwrap();
 
-        service.save_parsed_exercises(&exercises).unwrap();
+        // the held back side doesn't take up a new exercise's place
+        let mut due = ids(service.get_due_for_review(None, None, None));
+        due.sort();
+        assert_eq!(due, vec![1, 3]);
+    }
 
-        let today = todays_date();
+    fn check_variables(service: &ExerciseService) {
+        let mut exercise = Exercise::new("What is {a} + {a}?", "bar", "{2 * a}");
+        exercise
+            .variables
+            .insert("a".to_string(), "5..5".to_string());
+        service.save_parsed_exercises(&[exercise]).unwrap();
 
-        assert_eq!(service.get_exercise_stats(), Some((2, today)));
-        assert_eq!(service.count_due(), Some(2));
+        let mut exercise = service.get_by_pk(1).unwrap();
+        assert_eq!(
+            exercise.instantiate().unwrap().description,
+            "What is 5 + 5?"
+        );
+        assert_eq!(exercise.instantiate().unwrap().reference_answer, "10");
 
-        let mut saved_exercises = service.get_all_by_due_date_desc();
+        // reviewing doesn't save the filled in values
+        service
+            .review_exercise(&mut exercise, Grade::Good, Duration::seconds(1))
+            .unwrap();
+        let exercise = service.get_by_pk(1).unwrap();
+        assert_eq!(exercise.description, "What is {a} + {a}?");
+        assert_eq!(exercise.variables.len(), 1);
+    }
 
-        assert_eq!(saved_exercises.len(), 2);
+    #[test]
+    fn test_export_variables() {
+        let mut exercise = Exercise::new("What is {a} * {b}?", "bar", "{a * b}");
+        exercise
+            .variables
+            .insert("a".to_string(), "2..12".to_string());
+        exercise.variables.insert("b".to_string(), "a".to_string());
 
-        assert_eq!(saved_exercises[0].id, Some(2));
-        assert_eq!(saved_exercises[0].created_at, today);
-        assert_eq!(saved_exercises[0].due_at, today);
-        assert_eq!(saved_exercises[0].description, "foo 2");
-        assert_eq!(saved_exercises[0].source, "bar 2");
-        assert_eq!(saved_exercises[0].reference_answer, "baz 2");
-        assert_eq!(saved_exercises[0].update_interval, 0);
-        assert_eq!(saved_exercises[0].consecutive_successful_reviews, 0);
+        let service = ExerciseService::new_in_memory();
+        service.save_parsed_exercises(&[exercise]).unwrap();
 
-        assert_eq!(service.get_by_pk(2).unwrap(), saved_exercises[0]);
+        let path = Path::new("variables_export_test.yaml");
+        service
+            .get_by_pk(1)
+            .unwrap()
+            .yaml_export(path)
+            .expect("Failed to export");
 
-        assert_eq!(saved_exercises[1].id, Some(1));
-        assert_eq!(saved_exercises[1].created_at, today);
-        assert_eq!(saved_exercises[1].due_at, today);
-        assert_eq!(saved_exercises[1].description, "foo");
-        assert_eq!(saved_exercises[1].source, "bar");
-        assert_eq!(saved_exercises[1].reference_answer, "baz");
-        assert_eq!(saved_exercises[1].update_interval, 0);
-        assert_eq!(saved_exercises[1].consecutive_successful_reviews, 0);
+        let data = fs::read_to_string(path).expect("Failed to read back in");
+        assert!(data.contains("variables:\n  a: \"2..12\"\n  b: \"a\"\n"));
 
-        let first_exercise = &mut saved_exercises[0];
-        first_exercise.due_at += Duration::days(1);
-        assert!(first_exercise.update(&service).is_ok());
+        let parsed_exercise = parse_updated_exercise(path).expect("should not error out");
+        fs::remove_file(path).unwrap();
 
-        let due = service.get_due();
-        assert_eq!(due.len(), 1);
-        assert_eq!(due[0], saved_exercises[1]);
-        assert_eq!(service.count_due(), Some(1));
+        assert_eq!(parsed_exercise.variables["a"], "2..12");
+        assert_eq!(parsed_exercise.variables["b"], "a");
     }
 
     #[test]
-    fn test_save_parsed_exercise_transaction_handling() {
-        let exercises = vec![
-            Exercise::new("foo", "bar", "baz"),
-            // duplicate description
-            Exercise::new("foo", "bar 2", "baz 2"),
-        ];
+    fn test_export_cloze() {
+        let mut exercise = Exercise::new("", "bar", "");
+        exercise.sibling_group = Some("foo".to_string());
 
-        let service = ExerciseService::new_test();
-        let result = service.save_parsed_exercises(&exercises);
+        let service = ExerciseService::new_in_memory();
+        service
+            .save_parsed_exercises(&expand_cloze(&exercise, "{{c1::foo}} {{c2::bar}}").unwrap())
+            .unwrap();
 
-        assert!(result.is_err());
+        let path = Path::new("cloze_export_test.yaml");
+        service
+            .get_by_pk(2)
+            .unwrap()
+            .yaml_export(path)
+            .expect("Failed to export");
 
-        let e = result.unwrap_err();
+        let data = fs::read_to_string(path).expect("Failed to read back in");
+        assert!(data.contains("description: |+\n  {{c1::foo}} {{c2::bar}}"));
+        assert!(!data.contains("reference_answer"));
 
-        let error_string = stringify_boxed_error(e);
+        fs::write(path, data.replace("{{c2::bar}}", "{{c2::baz}}")).unwrap();
+        let parsed_exercise = parse_updated_exercise(path).expect("should not error out");
+        fs::remove_file(path).unwrap();
 
-        assert_eq!(error_string, "database error: ERROR: duplicate key value violates unique constraint \"exercises_description_key\"");
+        let mut exercise = service.get_by_pk(2).unwrap();
+        exercise.update_with_values(&parsed_exercise);
+        exercise.update(&service).expect("update failed");
+
+        assert_eq!(service.get_by_pk(1).unwrap().description, "[...] baz");
+        assert_eq!(service.get_by_pk(2).unwrap().reference_answer, "baz");
     }
 
-    // test interval updating
     #[test]
-    fn test_exercise_update_interval_calculations() {
-        let mut exercise = Exercise::new("", "", "");
+    fn test_deck_settings() {
+        let mut decks = BTreeMap::new();
+        decks.insert(
+            "math".to_string(),
+            DeckConfig {
+                time_box_minutes: Some(30),
+                scheduling: SchedulingConfig {
+                    first_interval: 3,
+                    new_per_day: 1,
+                    leech_threshold: 1,
+                    ..SchedulingConfig::default()
+                },
+            },
+        );
+        decks.insert(
+            "empty".to_string(),
+            DeckConfig {
+                time_box_minutes: None,
+                scheduling: SchedulingConfig::default(),
+            },
+        );
 
-        let today = Local::today().naive_local();
+        let service = ExerciseService::with_decks(
+            Box::new(MemoryStore::new()),
+            SchedulingConfig {
+                new_per_day: 2,
+                ..SchedulingConfig::default()
+            },
+            decks,
+        )
+        .unwrap();
+        service.migrate().unwrap();
 
-        assert_eq!(exercise.due_at, today);
-        assert_eq!(exercise.consecutive_successful_reviews, 0);
-        assert_eq!(exercise.update_interval, 0);
+        let exercises = (1..=6)
+            .map(|i| {
+                let mut exercise = Exercise::new(&format!("foo {}", i), "bar", "baz");
+                if i <= 3 {
+                    exercise.deck = Some("math".to_string());
+                }
+                exercise
+            })
+            .collect::<Vec<_>>();
+        service.save_parsed_exercises(&exercises).unwrap();
 
-        exercise.update_repetition_interval(true);
-        assert_eq!(exercise.due_at, today + Duration::days(1));
-        assert_eq!(exercise.consecutive_successful_reviews, 1);
+        assert_eq!(service.deck_time_box_minutes("math"), Some(30));
+        assert_eq!(service.deck_time_box_minutes("empty"), None);
+        assert_eq!(service.deck_time_box_minutes("rust"), None);
+
+        // each deck has its own limit on new exercises
+        let due_ids =
+            |deck| ids(service.get_due_for_review(Some(ReviewOrder::DueDate), None, deck));
+        assert_eq!(due_ids(None), vec![5, 4, 1]);
+        assert_eq!(due_ids(Some("math")), vec![1]);
+        assert_eq!(service.count_new_waiting(None), 3);
+
+        // and its own scheduling settings
+        let mut exercise = service.get_by_pk(1).unwrap();
+        service
+            .review_exercise(&mut exercise, Grade::Good, Duration::seconds(1))
+            .unwrap();
+        assert_eq!(exercise.update_interval, 3);
+        assert!(due_ids(Some("math")).is_empty());
+
+        let mut exercise = service.get_by_pk(4).unwrap();
+        service
+            .review_exercise(&mut exercise, Grade::Good, Duration::seconds(1))
+            .unwrap();
         assert_eq!(exercise.update_interval, 1);
 
-        exercise.update_repetition_interval(true);
-        assert_eq!(exercise.due_at, today + Duration::days(2));
-        assert_eq!(exercise.consecutive_successful_reviews, 2);
-        assert_eq!(exercise.update_interval, 2);
+        let mut leech = service.get_by_pk(2).unwrap();
+        leech.lapses = 1;
+        assert!(service.is_leech(&leech));
+        leech.deck = None;
+        assert!(!service.is_leech(&leech));
 
-        exercise.update_repetition_interval(true);
-        assert_eq!(exercise.due_at, today + Duration::days(4));
-        assert_eq!(exercise.consecutive_successful_reviews, 3);
-        assert_eq!(exercise.update_interval, 4);
+        assert_eq!(
+            service.get_deck_counts(),
+            vec![
+                (None, 3, 1),
+                (Some("empty".to_string()), 0, 0),
+                (Some("math".to_string()), 3, 0),
+            ]
+        );
+    }
 
-        exercise.update_repetition_interval(true);
-        assert_eq!(exercise.due_at, today + Duration::days(8));
-        assert_eq!(exercise.consecutive_successful_reviews, 4);
-        assert_eq!(exercise.update_interval, 8);
+    #[test]
+    fn test_suspending_exercises() {
+        let service = ExerciseService::new_in_memory();
+        service
+            .save_parsed_exercises(&[
+                Exercise::new("foo", "bar", "baz"),
+                Exercise::new("foo 2", "bar 2", "baz 2"),
+            ])
+            .unwrap();
 
-        exercise.update_repetition_interval(true);
-        assert_eq!(exercise.due_at, today + Duration::days(16));
-        assert_eq!(exercise.consecutive_successful_reviews, 5);
-        assert_eq!(exercise.update_interval, 16);
+        service.set_suspended(1, true).unwrap();
+        assert!(service.get_by_pk(1).unwrap().suspended);
+        assert_eq!(service.get_due(None).len(), 1);
+        assert_eq!(service.count_due(None), Some(1));
+        assert_eq!(service.get_schedule(None), vec![(todays_date(), 1)]);
 
-        exercise.update_repetition_interval(true);
-        assert_eq!(exercise.due_at, today + Duration::days(32));
-        assert_eq!(exercise.consecutive_successful_reviews, 6);
-        assert_eq!(exercise.update_interval, 32);
+        // still there, just not due
+        assert_eq!(service.get_all_by_due_date_desc(None).len(), 2);
+        assert_eq!(service.grep("foo", None).len(), 2);
 
-        exercise.update_repetition_interval(true);
-        assert_eq!(exercise.due_at, today + Duration::days(64));
-        assert_eq!(exercise.consecutive_successful_reviews, 7);
-        assert_eq!(exercise.update_interval, 64);
+        service.set_suspended(1, false).unwrap();
+        assert!(!service.get_by_pk(1).unwrap().suspended);
+        assert_eq!(service.count_due(None), Some(2));
 
-        for i in 1..100 {
-            exercise.update_repetition_interval(true);
-            assert_eq!(exercise.due_at, today + Duration::days(90));
-            assert_eq!(exercise.consecutive_successful_reviews, 7 + i);
-            assert_eq!(exercise.update_interval, 90);
+        assert!(service.set_suspended(3, true).is_err());
+
+        // only the flag is saved, without touching the exercise's siblings
+        let mut exercise = Exercise::new("foo 3", "bar 3", "baz 3");
+        exercise.sibling_group = Some("a".to_string());
+        let mut reversed = Exercise::new("baz 3", "bar 4", "foo 3");
+        reversed.sibling_group = Some("a".to_string());
+        service
+            .save_parsed_exercises(&[exercise, reversed])
+            .unwrap();
+
+        service.set_suspended(3, true).unwrap();
+        assert!(service.get_by_pk(3).unwrap().suspended);
+        let reversed = service.get_by_pk(4).unwrap();
+        assert!(!reversed.suspended);
+        assert_eq!(reversed.source, "bar 4");
+    }
+
+    #[test]
+    fn test_leeches() {
+        let leech_service = |suspend_leeches| {
+            let service = ExerciseService::with_config(
+                Box::new(MemoryStore::new()),
+                SchedulingConfig {
+                    leech_threshold: 2,
+                    suspend_leeches,
+                    ..SchedulingConfig::default()
+                },
+            )
+            .unwrap();
+            service.migrate().unwrap();
+            service
+                .save_parsed_exercises(&[
+                    Exercise::new("foo", "bar", "baz"),
+                    Exercise::new("foo 2", "bar 2", "baz 2"),
+                ])
+                .unwrap();
+            service
+        };
+
+        let lapse = |service: &ExerciseService, exercise: &mut Exercise| {
+            for grade in &[Grade::Good, Grade::Again, Grade::Again] {
+                service
+                    .review_exercise(exercise, *grade, Duration::seconds(1))
+                    .unwrap();
+            }
+        };
+
+        let service = leech_service(false);
+        let mut exercise = service.get_by_pk(1).unwrap();
+
+        // failing twice in a row is only one lapse
+        lapse(&service, &mut exercise);
+        assert_eq!(exercise.lapses, 1);
+        assert!(!service.is_leech(&exercise));
+        assert!(service.get_leeches().is_empty());
+
+        lapse(&service, &mut exercise);
+        assert_eq!(exercise.lapses, 2);
+        assert!(service.is_leech(&exercise));
+        assert!(!exercise.suspended);
+
+        let leeches = service.get_leeches();
+        assert_eq!(leeches.len(), 1);
+        assert_eq!(leeches[0].id, Some(1));
+        assert_eq!(leeches[0].lapses, 2);
+        assert_eq!(service.count_due(None), Some(2));
+
+        let service = leech_service(true);
+        let mut exercise = service.get_by_pk(1).unwrap();
+        lapse(&service, &mut exercise);
+        assert!(!exercise.suspended);
+        lapse(&service, &mut exercise);
+        assert!(exercise.suspended);
+
+        // still listed, but no longer due
+        assert_eq!(service.get_leeches().len(), 1);
+        assert_eq!(service.count_due(None), Some(1));
+        assert_eq!(service.get_due(None)[0].id, Some(2));
+        assert_eq!(service.get_schedule(None), vec![(todays_date(), 1)]);
+    }
+
+    #[test]
+    fn test_review_load_balancing() {
+        let service = ExerciseService::with_config(
+            Box::new(MemoryStore::new()),
+            SchedulingConfig {
+                load_balance_days: 3,
+                ..SchedulingConfig::default()
+            },
+        )
+        .unwrap();
+        service.migrate().unwrap();
+
+        let exercises = (0..20)
+            .map(|i| Exercise::new(&format!("foo {}", i), "bar", "baz"))
+            .collect::<Vec<_>>();
+        service.save_parsed_exercises(&exercises).unwrap();
+
+        // all imported together and reviewed the same way so far
+        for mut exercise in service.get_all_by_due_date_desc(None) {
+            exercise.update_interval = 8;
+            exercise.consecutive_successful_reviews = 3;
+            service.update_exercise(&exercise).unwrap();
         }
 
-        exercise.update_repetition_interval(false);
-        assert_eq!(exercise.due_at, today);
-        assert_eq!(exercise.consecutive_successful_reviews, 0);
-        assert_eq!(exercise.update_interval, 0);
+        for mut exercise in service.get_all_by_due_date_desc(None) {
+            service
+                .review_exercise(&mut exercise, Grade::Good, Duration::seconds(1))
+                .unwrap();
+        }
 
-        exercise.update_repetition_interval(true);
-        assert_eq!(exercise.due_at, today + Duration::days(1));
-        assert_eq!(exercise.consecutive_successful_reviews, 1);
-        assert_eq!(exercise.update_interval, 1);
+        let today = todays_date();
+        let schedule = service.get_schedule(None);
+
+        // spread over the week around the 16 days doubling asks for
+        assert_eq!(schedule.len(), 7);
+        for (due_at, count) in schedule {
+            assert!(due_at >= today + Duration::days(13));
+            assert!(due_at <= today + Duration::days(19));
+            assert!(count <= 3);
+        }
     }
 
     // test a simulated review update process end to end (update an exercise's fields, check that they get saved in database)
@@ -1016,10 +3451,10 @@ and one more";
     fn test_review_crud_update_process() {
         let exercise = Exercise::new("foo", "bar", "baz");
 
-        let service = ExerciseService::new_test();
-        service.save_parsed_exercises(&vec![exercise]).unwrap();
+        let service = ExerciseService::new_in_memory();
+        service.save_parsed_exercises(&[exercise]).unwrap();
 
-        let mut saved_exercises = service.get_all_by_due_date_desc();
+        let mut saved_exercises = service.get_all_by_due_date_desc(None);
 
         assert_eq!(saved_exercises.len(), 1);
 
@@ -1036,7 +3471,13 @@ and one more";
         assert_eq!(saved_exercise.update_interval, 0);
         assert_eq!(saved_exercise.consecutive_successful_reviews, 0);
 
-        saved_exercise.update_repetition_interval(true);
+        let state = DoublingScheduler::default().next_state(
+            saved_exercise.scheduling_state(),
+            Grade::Good,
+            &[],
+            today,
+        );
+        saved_exercise.set_scheduling_state(state);
 
         let tomorrow = today + Duration::days(1);
 
@@ -1051,7 +3492,7 @@ and one more";
 
         saved_exercise.update(&service).unwrap();
 
-        let saved_exercises = service.get_all_by_due_date_desc();
+        let saved_exercises = service.get_all_by_due_date_desc(None);
 
         assert_eq!(saved_exercises.len(), 1);
 
@@ -1068,11 +3509,9 @@ and one more";
 
         let other_exercise = Exercise::new("quux", "bar", "baz");
 
-        service
-            .save_parsed_exercises(&vec![other_exercise])
-            .unwrap();
+        service.save_parsed_exercises(&[other_exercise]).unwrap();
 
-        let schedule = service.get_schedule();
+        let schedule = service.get_schedule(None);
 
         assert_eq!(schedule.len(), 2);
         assert_eq!(schedule[0], (today, 1));
@@ -1080,10 +3519,9 @@ and one more";
 
         assert!(service.delete_by_pk(saved_exercise.id.unwrap()).is_ok());
 
-        let saved_exercises = service.get_all_by_due_date_desc();
+        let saved_exercises = service.get_all_by_due_date_desc(None);
         assert_eq!(saved_exercises.len(), 1);
 
         assert!(service.delete_by_pk(1234).is_err());
     }
-
 }
@@ -1,5 +1,7 @@
+use chrono::{DateTime, Utc};
 use clap::{App, Arg, SubCommand};
 use crossterm::{terminal, Attribute, ClearType};
+use std::collections::VecDeque;
 use std::path::Path;
 
 use arete::*;
@@ -16,6 +18,26 @@ fn usage(app: &mut App) {
     println!();
 }
 
+// connects to the live database and makes sure its schema is the one this
+// version of arete expects. everything except migrate and drop_schema should
+// go through here.
+fn start_live_service() -> Option<ExerciseService> {
+    let service = match ExerciseService::new_live() {
+        Ok(service) => service,
+        Err(e) => {
+            eprintln!("Error starting up: {}", e);
+            return None;
+        }
+    };
+
+    if let Err(e) = service.check_schema() {
+        eprintln!("{}", e);
+        return None;
+    }
+
+    Some(service)
+}
+
 fn delete_command(pk: i32) {
     eprintln!(
         "Really delete exercise {}? Type 'delete' without quotes to continue",
@@ -36,32 +58,90 @@ fn delete_command(pk: i32) {
         return;
     }
 
-    let service = ExerciseService::new_live();
+    let service = match start_live_service() {
+        Some(service) => service,
+        None => return,
+    };
 
-    if let Err(e) = service {
-        eprintln!("Error starting up: {}", e);
-        return;
+    match service.delete_by_pk(pk) {
+        Ok(_) => println!("Exercise {} has been deleted.", pk),
+        Err(e) => eprintln!("Error: {}", e),
     }
+}
 
-    let service = service.unwrap();
+fn suspend_command(pk: i32, suspended: bool) {
+    let service = match start_live_service() {
+        Some(service) => service,
+        None => return,
+    };
 
-    match service.delete_by_pk(pk) {
-        Ok(_) => println!("Exercise {} has been deleted.", pk),
+    match service.set_suspended(pk, suspended) {
+        Ok(_) if suspended => println!("Exercise {} has been suspended.", pk),
+        Ok(_) => println!("Exercise {} has been unsuspended.", pk),
         Err(e) => eprintln!("Error: {}", e),
     }
 }
 
-fn edit_command(pk: i32, path: &Path) {
-    let service = ExerciseService::new_live();
+// the first line of the description, for lists where whole exercises would
+// be too much
+fn exercise_summary(exercise: &Exercise) -> String {
+    format!(
+        "{}: {}",
+        exercise.id.unwrap_or(-1),
+        exercise.description.lines().next().unwrap_or("")
+    )
+}
 
-    if let Err(e) = service {
-        eprintln!("Error starting up: {}", e);
-        return;
+fn print_exercise_tree(label: &str, tree: &[(usize, Exercise)]) {
+    println!("{}:", label);
+
+    if tree.is_empty() {
+        println!("  none");
     }
 
-    match service.unwrap().get_by_pk(pk) {
+    for (depth, exercise) in tree {
+        println!(
+            "{}{}{}",
+            "  ".repeat(*depth),
+            exercise_summary(exercise),
+            if exercise.is_failing() {
+                " (failing)"
+            } else {
+                ""
+            }
+        );
+    }
+}
+
+fn deps_command(pk: i32) {
+    let service = match start_live_service() {
+        Some(service) => service,
+        None => return,
+    };
+
+    let exercise = match service.get_by_pk(pk) {
+        Some(exercise) => exercise,
+        None => {
+            eprintln!("Exercise {} does not exist", pk);
+            return;
+        }
+    };
+
+    println!("{}\n", exercise_summary(&exercise));
+    print_exercise_tree("Prerequisites", &service.get_prerequisite_tree(pk));
+    println!();
+    print_exercise_tree("Needed by", &service.get_dependent_tree(pk));
+}
+
+fn edit_command(pk: i32, path: &Path) {
+    let service = match start_live_service() {
+        Some(service) => service,
+        None => return,
+    };
+
+    match service.get_by_pk(pk) {
         Some(exercise) => {
-            if let Err(e) = exercise.yaml_export(&path) {
+            if let Err(e) = exercise.yaml_export(path) {
                 eprintln!("Error while exporting: {}", e);
             }
         }
@@ -72,16 +152,12 @@ fn edit_command(pk: i32, path: &Path) {
 }
 
 fn update_exercise_from_path(path: &Path) {
-    match parse_updated_exercise(&path) {
+    match parse_updated_exercise(path) {
         Ok(updated_exercise) => {
-            let service = ExerciseService::new_live();
-
-            if let Err(e) = service {
-                eprintln!("Error starting up: {}", e);
-                return;
-            }
-
-            let service = service.unwrap();
+            let service = match start_live_service() {
+                Some(service) => service,
+                None => return,
+            };
 
             match service.get_by_pk(updated_exercise.id) {
                 Some(mut exercise) => {
@@ -104,7 +180,7 @@ fn update_exercise_from_path(path: &Path) {
     }
 }
 
-fn bootstrap_schema_command() {
+fn migrate_command() {
     let service = ExerciseService::new_live();
 
     if let Err(e) = service {
@@ -114,12 +190,25 @@ fn bootstrap_schema_command() {
 
     let service = service.unwrap();
 
-    if let Err(e) = service.bootstrap_schema() {
-        eprintln!("Error bootstrapping database: {}", e);
-        return;
+    match service.migrate() {
+        Ok(applied) => {
+            for migration in applied {
+                println!(
+                    "Applied migration {}: {}",
+                    migration.version, migration.description
+                );
+            }
+        }
+        Err(e) => {
+            eprintln!("Error migrating database: {}", e);
+            return;
+        }
     }
 
-    println!("Database schema bootstrapped.");
+    match service.schema_version() {
+        Ok(version) => println!("Database schema is up to date (version {}).", version),
+        Err(e) => eprintln!("Error reading schema version: {}", e),
+    }
 }
 
 fn drop_schema_command() {
@@ -165,30 +254,55 @@ fn print_labeled_field(label: &str, s: &str) {
 
 fn print_full_exercise(exercise: &Exercise) {
     print_labeled_field("Description", &exercise.description);
-    if exercise.id.is_some() {
-        println!("ID:\n  {}", &exercise.id.unwrap());
+    if let Some(id) = exercise.id {
+        println!("ID:\n  {}", id);
     }
     print_labeled_field("Source", &exercise.source);
+    if let Some(deck) = &exercise.deck {
+        print_labeled_field("Deck", deck);
+    }
+    if !exercise.tags.is_empty() {
+        print_labeled_field("Tags", &exercise.tags.join(", "));
+    }
+    if !exercise.prerequisites.is_empty() {
+        let prerequisites = exercise
+            .prerequisites
+            .iter()
+            .map(Prerequisite::to_string)
+            .collect::<Vec<_>>();
+        print_labeled_field("Prerequisites", &prerequisites.join(", "));
+    }
+    if !exercise.variables.is_empty() {
+        let variables = exercise
+            .variables
+            .iter()
+            .map(|(name, source)| format!("{}: {}", name, source))
+            .collect::<Vec<_>>();
+        print_labeled_field("Variables", &variables.join("\n"));
+    }
     print_labeled_field("Reference", &exercise.reference_answer);
 }
 
+fn print_no_exercises_loaded(tag: Option<&str>) {
+    match tag {
+        Some(tag) => println!("No exercises are tagged '{}'.", tag),
+        None => println!("No exercises are loaded."),
+    }
+}
+
 fn print_partial_exercise(exercise: &Exercise) {
     print_labeled_field("Description", &exercise.description);
     print_labeled_field("Source", &exercise.source);
 }
 
-fn grep_command(query: &str) {
-    let service = ExerciseService::new_live();
-
-    if let Err(e) = service {
-        eprintln!("Error starting up: {}", e);
-        return;
-    }
-
-    let service = service.unwrap();
+fn grep_command(query: &str, tag: Option<&str>) {
+    let service = match start_live_service() {
+        Some(service) => service,
+        None => return,
+    };
 
     println!("Searching for '{}': ", &query);
-    let results = service.grep(query);
+    let results = service.grep(query, tag);
 
     if results.is_empty() {
         println!("No results found.");
@@ -201,9 +315,13 @@ fn grep_command(query: &str) {
     }
 }
 
-fn import_command(path: &str, dry_run: bool) {
+fn import_command(path: &str, deck: Option<&str>, dry_run: bool) {
     match parse_exercises(Path::new(path)) {
-        Ok(exercises) => {
+        Ok(mut exercises) => {
+            for exercise in exercises.iter_mut() {
+                exercise.deck = deck.map(str::to_string);
+            }
+
             if dry_run {
                 println!("Here are the exercises that would be imported:\n");
             } else {
@@ -216,6 +334,31 @@ fn import_command(path: &str, dry_run: bool) {
             }
 
             if dry_run {
+                // saving to a throwaway store catches problems the parser
+                // can't, like the same description appearing twice.
+                // prerequisites outside the file can only be checked on import.
+                let descriptions = exercises
+                    .iter()
+                    .map(|exercise| exercise.description.clone())
+                    .collect::<Vec<_>>();
+                let mut scratch_exercises = exercises.clone();
+                for exercise in scratch_exercises.iter_mut() {
+                    exercise
+                        .prerequisites
+                        .retain(|prerequisite| match prerequisite {
+                            Prerequisite::Description(description) => {
+                                descriptions.contains(description)
+                            }
+                            Prerequisite::Id(_) => false,
+                        });
+                }
+
+                let scratch_service = ExerciseService::new_in_memory();
+                if let Err(e) = scratch_service.save_parsed_exercises(&scratch_exercises) {
+                    eprintln!("These exercises could not be imported: {}", e);
+                    return;
+                }
+
                 println!("\nExiting since this is a dry run.");
                 return;
             }
@@ -237,19 +380,10 @@ fn import_command(path: &str, dry_run: bool) {
 
             /* No need to connect to the database unless actually necessary */
 
-            let service = ExerciseService::new_live();
-
-            if let Err(e) = service {
-                eprintln!("Error starting up: {}", e);
-                return;
-            }
-
-            let service = service.unwrap();
-
-            if !service.schema_is_loaded() {
-                eprintln!("Schema is not loaded. Please run bootstrap_schema.");
-                return;
-            }
+            let service = match start_live_service() {
+                Some(service) => service,
+                None => return,
+            };
 
             if let Err(e) = service.save_parsed_exercises(&exercises) {
                 eprintln!("Error saving exercises: {}", e);
@@ -265,25 +399,16 @@ fn import_command(path: &str, dry_run: bool) {
     }
 }
 
-fn schedule_command() {
-    let service = ExerciseService::new_live();
-
-    if let Err(e) = service {
-        eprintln!("Error starting up: {}", e);
-        return;
-    }
-
-    let service = service.unwrap();
-
-    if !service.schema_is_loaded() {
-        eprintln!("Schema is not loaded. Please run bootstrap_schema.");
-        return;
-    }
+fn schedule_command(tag: Option<&str>) {
+    let service = match start_live_service() {
+        Some(service) => service,
+        None => return,
+    };
 
-    let schedule = service.get_schedule();
+    let schedule = service.get_schedule(tag);
 
     if schedule.is_empty() {
-        println!("No exercises are loaded.");
+        print_no_exercises_loaded(tag);
     }
 
     for (date, count) in schedule {
@@ -291,25 +416,96 @@ fn schedule_command() {
     }
 }
 
-fn count_command() {
-    let service = ExerciseService::new_live();
+fn shift_command(days: i32, spread_days: i32, dry_run: bool) {
+    let service = match start_live_service() {
+        Some(service) => service,
+        None => return,
+    };
 
-    if let Err(e) = service {
-        eprintln!("Error starting up: {}", e);
+    let preview = match service.shift_schedule(days, spread_days, true) {
+        Ok(preview) => preview,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return;
+        }
+    };
+
+    if preview == service.get_schedule(None) {
+        println!("Nothing would change.");
         return;
     }
 
-    let service = service.unwrap();
+    println!("Here is when exercises would be due afterwards:\n");
+    for (date, count) in preview {
+        println!("{}: {}", date, count);
+    }
 
-    if !service.schema_is_loaded() {
-        eprintln!("Schema is not loaded. Please run bootstrap_schema.");
+    if dry_run {
+        println!("\nExiting since this is a dry run.");
         return;
     }
 
-    let stats = service.get_exercise_stats();
+    println!("\nShift due dates? [y/N]");
+    let mut buffer = String::new();
+    if std::io::stdin().read_line(&mut buffer).is_err() {
+        eprintln!("Invalid response");
+        return;
+    }
+
+    let trimmed_input = buffer.trim();
+    if trimmed_input != "y" {
+        eprintln!(
+            "Got response \"{}\" but needed \"y\" to proceed. No data was saved.",
+            trimmed_input
+        );
+        return;
+    }
+
+    match service.shift_schedule(days, spread_days, false) {
+        Ok(_) => println!("Due dates have been shifted."),
+        Err(e) => eprintln!("Error shifting due dates: {}", e),
+    }
+}
+
+// options that are followed by a value
+const VALUE_FLAGS: &[&str] = &["--days", "--spread", "--order", "--tag", "--deck"];
+
+// the value after --flag, for subcommands that take options. see the comment
+// in main about why clap can't do this for us.
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+// the arguments after the subcommand that aren't options or their values
+fn positional_args(args: &[String]) -> Vec<&str> {
+    args.iter()
+        .enumerate()
+        .skip(2)
+        .filter(|(i, arg)| !arg.starts_with("--") && !VALUE_FLAGS.contains(&args[i - 1].as_str()))
+        .map(|(_, arg)| arg.as_str())
+        .collect()
+}
+
+fn tag_arg() -> Arg<'static, 'static> {
+    Arg::with_name("tag")
+        .long("tag")
+        .help("Only include exercises with this tag.")
+        .takes_value(true)
+}
+
+fn count_command(tag: Option<&str>) {
+    let service = match start_live_service() {
+        Some(service) => service,
+        None => return,
+    };
+
+    let stats = service.get_exercise_stats(tag);
 
     if stats.is_none() {
-        println!("No exercises are loaded.");
+        print_no_exercises_loaded(tag);
         return;
     }
 
@@ -320,9 +516,35 @@ fn count_command() {
         exercise_cnt, earliest_exercise
     );
 
-    let due_cnt = service.count_due().unwrap_or(0);
+    let due_cnt = service.count_due(tag).unwrap_or(0);
+
+    println!("{} exercises are currently due.", due_cnt);
+
+    let waiting_cnt = service.count_new_waiting(tag);
+
+    if waiting_cnt > 0 {
+        println!("{} new exercises are waiting for a later day.", waiting_cnt);
+    }
+
+    println!();
+
+    let deck_counts = service.get_deck_counts();
 
-    println!("{} exercises are currently due.\n", due_cnt);
+    // not worth a breakdown when nothing is in a deck
+    if tag.is_some() || deck_counts.iter().all(|(deck, _, _)| deck.is_none()) {
+        return;
+    }
+
+    println!("By deck:");
+    for (deck, exercise_cnt, due_cnt) in deck_counts {
+        println!(
+            "  {}: {} exercises, {} due",
+            deck.as_deref().unwrap_or("(no deck)"),
+            exercise_cnt,
+            due_cnt
+        );
+    }
+    println!();
 }
 
 fn test_connection_command() {
@@ -338,25 +560,41 @@ fn test_connection_command() {
     println!("Live and test connections succeeded.");
 }
 
-fn ls_command() {
-    let service = ExerciseService::new_live();
+fn ls_command(tag: Option<&str>) {
+    let service = match start_live_service() {
+        Some(service) => service,
+        None => return,
+    };
 
-    if let Err(e) = service {
-        eprintln!("Error starting up: {}", e);
+    let exercises = service.get_all_by_due_date_desc(tag);
+
+    if exercises.is_empty() {
+        print_no_exercises_loaded(tag);
         return;
     }
 
-    let service = service.unwrap();
+    println!("{} exercises:\n", exercises.len());
 
-    if !service.schema_is_loaded() {
-        eprintln!("Schema is not loaded. Please run bootstrap_schema.");
-        return;
+    // TODO page these the way git log does
+    for exercise in exercises.iter() {
+        print_full_exercise(exercise);
+        if exercise.suspended {
+            println!("Suspended:\n  yes");
+        }
+        println!("Due at:\n  {}\n", &exercise.due_at);
     }
+}
+
+fn due_command(tag: Option<&str>) {
+    let service = match start_live_service() {
+        Some(service) => service,
+        None => return,
+    };
 
-    let exercises = service.get_all_by_due_date_desc();
+    let exercises = service.get_due(tag);
 
     if exercises.is_empty() {
-        println!("No exercises loaded.");
+        println!("No exercises are currently due. Run 'ls' to see exercises due later.");
         return;
     }
 
@@ -364,72 +602,96 @@ fn ls_command() {
 
     // TODO page these the way git log does
     for exercise in exercises.iter() {
-        print_full_exercise(&exercise);
+        print_partial_exercise(exercise);
+        if let Some(id) = exercise.id {
+            println!("ID:\n  {}", id);
+        } else {
+            println!("ID:\n  ???? No ID, this is a bug");
+        }
         println!("Due at:\n  {}\n", &exercise.due_at);
     }
 }
 
-fn due_command() {
-    let service = ExerciseService::new_live();
+fn leeches_command() {
+    let service = match start_live_service() {
+        Some(service) => service,
+        None => return,
+    };
 
-    if let Err(e) = service {
-        eprintln!("Error starting up: {}", e);
+    let exercises = service.get_leeches();
+
+    if exercises.is_empty() {
+        println!("No exercises are leeches.");
         return;
     }
 
-    let service = service.unwrap();
+    println!(
+        "{} exercises keep being forgotten. Consider rewriting them with 'edit' and 'update':\n",
+        exercises.len()
+    );
 
-    if !service.schema_is_loaded() {
-        eprintln!("Schema is not loaded. Please run bootstrap_schema.");
-        return;
+    for exercise in exercises.iter() {
+        print_full_exercise(exercise);
+        println!("Lapses:\n  {}", exercise.lapses);
+        if exercise.suspended {
+            println!("Suspended:\n  yes");
+        }
+        println!();
     }
+}
 
-    let exercises = service.get_due();
-
-    if exercises.is_empty() {
-        println!("No exercises are currently due. Run 'ls' to see exercises due later.");
+fn print_leech_warning(exercise: &Exercise, service: &ExerciseService) {
+    if !service.is_leech(exercise) {
         return;
     }
 
-    println!("{} exercises:\n", exercises.len());
-
-    // TODO page these the way git log does
-    for exercise in exercises.iter() {
-        print_partial_exercise(&exercise);
-        if exercise.id.is_some() {
-            println!("ID:\n  {}", &exercise.id.unwrap());
-        } else {
-            println!("ID:\n  ???? No ID, this is a bug");
-        }
-        println!("Due at:\n  {}\n", &exercise.due_at);
+    println!(
+        "\nThis exercise has been forgotten {} times. Run 'leeches' to see exercises that may need rewriting.",
+        exercise.lapses
+    );
+    if exercise.suspended {
+        println!("It has been suspended and won't come up for review any more.");
     }
 }
 
-fn confirm_exercise_answer(exercise: &mut Exercise, service: &ExerciseService) {
+fn confirm_exercise_answer(
+    exercise: &mut Exercise,
+    reference_answer: &str,
+    service: &ExerciseService,
+    started_at: DateTime<Utc>,
+) -> Option<Review> {
     print!("\n\n");
-    print_labeled_field("Reference", &exercise.reference_answer);
+    print_labeled_field("Reference", reference_answer);
     print_labeled_field("Source", &exercise.source);
 
-    println!("Is the answer you had in mind correct?");
+    println!("How well did you know it?");
 
-    let confirmation_options = [
-        HorizontalMenuOption::new("Yes", 'y'),
-        HorizontalMenuOption::new("No", 'n'),
-    ];
+    let grade_options = Grade::ALL
+        .iter()
+        .zip("1234".chars())
+        .map(|(grade, shortcut)| HorizontalMenuOption::new(grade.label(), shortcut))
+        .collect::<Vec<_>>();
 
-    match horizontal_menu_select(&confirmation_options) {
+    match horizontal_menu_select(&grade_options) {
         Ok(result) => match result {
             Some(selected_index) => {
-                let was_correct = selected_index == 0;
-                exercise.update_repetition_interval(was_correct);
-                if let Err(e) = exercise.update(&service) {
-                    eprintln!("\nError saving exercise: {}", e);
-                }
-
-                if was_correct {
-                    println!("\n\nMarked exercise correct. Exercise is now due at: {}\n", &exercise.due_at);
-                } else {
-                    println!("\n\nMarked exercise incorrect. Exercise is now due at: {}\n", &exercise.due_at);
+                let grade = Grade::ALL[selected_index];
+                match service.review_exercise(exercise, grade, Utc::now() - started_at) {
+                    Ok(review) => {
+                        println!(
+                            "\n\nMarked exercise {}. Exercise is now due at: {}",
+                            grade, &exercise.due_at
+                        );
+                        if review.lapse {
+                            print_leech_warning(exercise, service);
+                        }
+                        println!();
+                        Some(review)
+                    }
+                    Err(e) => {
+                        eprintln!("\nError saving exercise: {}", e);
+                        None
+                    }
                 }
             }
             None => {
@@ -444,9 +706,16 @@ fn confirm_exercise_answer(exercise: &mut Exercise, service: &ExerciseService) {
     }
 }
 
-fn print_next_exercise_input() {
-    if horizontal_menu_select(&[HorizontalMenuOption::new("Continue", 'c')]).is_err() {
-        std::process::exit(1);
+// returns true if the answer should be undone instead
+fn print_next_exercise_input(can_undo: bool) -> bool {
+    let mut options = vec![HorizontalMenuOption::new("Continue", 'c')];
+    if can_undo {
+        options.push(HorizontalMenuOption::new("Undo", 'u'));
+    }
+
+    match horizontal_menu_select(&options) {
+        Ok(result) => result == Some(1),
+        Err(_) => std::process::exit(1),
     }
 }
 
@@ -455,22 +724,20 @@ fn clear_screen() {
     terminal.clear(ClearType::All).unwrap();
 }
 
-fn review_command(time_box_minutes: Option<i64>) {
-    let service = ExerciseService::new_live();
-
-    if let Err(e) = service {
-        eprintln!("Error starting up: {}", e);
-        return;
-    }
-
-    let service = service.unwrap();
-
-    if !service.schema_is_loaded() {
-        eprintln!("Schema is not loaded. Please run bootstrap_schema.");
-        return;
-    }
-
-    let mut exercises = service.get_due();
+fn review_command(
+    time_box_minutes: Option<i64>,
+    order: Option<ReviewOrder>,
+    tag: Option<&str>,
+    deck: Option<&str>,
+) {
+    let service = match start_live_service() {
+        Some(service) => service,
+        None => return,
+    };
+
+    let exercises = service.get_due_for_review(order, tag, deck);
+    let time_box_minutes =
+        time_box_minutes.or_else(|| deck.and_then(|deck| service.deck_time_box_minutes(deck)));
 
     if exercises.is_empty() {
         println!("No exercises are due.");
@@ -479,11 +746,21 @@ fn review_command(time_box_minutes: Option<i64>) {
 
     let exercise_cnt = exercises.len();
 
+    // skipped exercises go to the back of the queue
+    let mut queue = exercises.into_iter().collect::<VecDeque<_>>();
+
     clear_screen();
 
     let review_session = ReviewSession::new(time_box_minutes);
 
-    for (i, exercise) in exercises.iter_mut().enumerate() {
+    // skipped exercises come back around, so each one ends up in one of these
+    let mut answered = 0;
+    let mut set_aside = 0;
+
+    while let Some(mut exercise) = queue.pop_front() {
+        let exercise = &mut exercise;
+        let i = exercise_cnt - queue.len() - 1;
+
         // we could set a timer that prints this as soon as time elapses, but
         // waiting until the next exercise is finished to end it seems fine
         if review_session.has_exceeded_timebox() {
@@ -496,15 +773,34 @@ fn review_command(time_box_minutes: Option<i64>) {
         println!(
             "{}{}{}\n",
             Attribute::Bold,
-            review_session.exercise_display_str(i, exercise_cnt, &exercise),
+            review_session.exercise_display_str(i, exercise_cnt, exercise),
             Attribute::Reset
         );
 
-        println!("{}\n", &exercise.description);
+        // exercises with variables get new values every time they're shown.
+        // the exercise itself is what gets saved, so it keeps its template.
+        let shown = match exercise.instantiate() {
+            Ok(shown) => shown,
+            Err(e) => {
+                eprintln!("Couldn't fill in the exercise's variables: {}\n", e);
+                exercise.clone()
+            }
+        };
+
+        println!("{}\n", &shown.description);
+
+        let started_at = Utc::now();
+
+        // what to put back if this answer is undone
+        let snapshot = exercise.clone();
+        let mut review = None;
 
         let options = [
             HorizontalMenuOption::new("Know it", 'y'),
             HorizontalMenuOption::new("Don't know it", 'n'),
+            HorizontalMenuOption::new("Skip", 'k'),
+            HorizontalMenuOption::new("Bury", 'b'),
+            HorizontalMenuOption::new("Suspend", 's'),
             HorizontalMenuOption::new("Quit and edit", 'e'),
         ];
 
@@ -512,16 +808,68 @@ fn review_command(time_box_minutes: Option<i64>) {
             Ok(result) => match result {
                 Some(selected_index) => {
                     if selected_index == 0 {
-                        confirm_exercise_answer(exercise, &service);
+                        review = confirm_exercise_answer(
+                            exercise,
+                            &shown.reference_answer,
+                            &service,
+                            started_at,
+                        );
+                        if review.is_some() {
+                            answered += 1;
+                        }
                     } else if selected_index == 1 {
                         print!("\n\n");
-                        print_labeled_field("Reference", &exercise.reference_answer);
+                        print_labeled_field("Reference", &shown.reference_answer);
                         print_labeled_field("Source", &exercise.source);
 
-                        exercise.update_repetition_interval(false);
-                        if let Err(e) = exercise.update(&service) {
+                        match service.review_exercise(
+                            exercise,
+                            Grade::Again,
+                            Utc::now() - started_at,
+                        ) {
+                            Ok(saved_review) => {
+                                if saved_review.lapse {
+                                    print_leech_warning(exercise, &service);
+                                }
+                                answered += 1;
+                                review = Some(saved_review);
+                            }
+                            Err(e) => eprintln!("\n\nError saving exercise: {}", e),
+                        }
+                    } else if selected_index == 2 {
+                        if let Err(e) = service.skip_exercise(exercise, Utc::now() - started_at) {
                             eprintln!("\n\nError saving exercise: {}", e);
                         }
+
+                        queue.push_back(exercise.clone());
+                        clear_screen();
+                        continue;
+                    } else if selected_index == 3 {
+                        match service.bury_exercise(exercise, Utc::now() - started_at) {
+                            Ok(saved_review) => {
+                                println!(
+                                    "\n\nBuried exercise. Exercise is now due at: {}\n",
+                                    &exercise.due_at
+                                );
+                                set_aside += 1;
+                                review = Some(saved_review);
+                            }
+                            Err(e) => eprintln!("\n\nError saving exercise: {}", e),
+                        }
+                    } else if selected_index == 4 {
+                        match exercise.id {
+                            Some(id) => match service.set_suspended(id, true) {
+                                Ok(_) => {
+                                    println!(
+                                        "\n\nSuspended exercise. Run 'unsuspend {}' to bring it back.\n",
+                                        id
+                                    );
+                                    set_aside += 1;
+                                }
+                                Err(e) => eprintln!("\n\nError suspending exercise: {}", e),
+                            },
+                            None => eprintln!("\n\nExercise has no ID, can't suspend!"),
+                        }
                     } else {
                         // quit and edit
                         if !&exercise.id.is_some() {
@@ -545,7 +893,21 @@ fn review_command(time_box_minutes: Option<i64>) {
                             }
                         }
                     }
-                    print_next_exercise_input();
+
+                    if print_next_exercise_input(review.is_some()) {
+                        match service.undo_review(&snapshot, &review.unwrap()) {
+                            // show it again straight away
+                            Ok(_) => {
+                                if selected_index == 3 {
+                                    set_aside -= 1;
+                                } else {
+                                    answered -= 1;
+                                }
+                                queue.push_front(snapshot);
+                            }
+                            Err(e) => eprintln!("\n\nError undoing answer: {}", e),
+                        }
+                    }
                 }
                 None => {
                     eprintln!("\nNo selection was made.");
@@ -559,7 +921,7 @@ fn review_command(time_box_minutes: Option<i64>) {
         }
 
         // clear the screen if not last exercise
-        if i < exercise_cnt - 1 {
+        if !queue.is_empty() {
             clear_screen();
         }
     }
@@ -567,9 +929,12 @@ fn review_command(time_box_minutes: Option<i64>) {
     println!("\n\n{}Done reviewing!{}", Attribute::Bold, Attribute::Reset);
     println!(
         "\n{} exercises reviewed in {} minutes.",
-        exercise_cnt,
+        answered,
         review_session.elapsed_minutes().num_minutes()
     );
+    if set_aside > 0 {
+        println!("{} more buried or suspended.", set_aside);
+    }
 }
 
 fn main() {
@@ -583,20 +948,30 @@ fn main() {
         .author("Warren Henning <warren.henning@gmail.com>")
         .about("Simple command-line flashcard application")
         .subcommand(
-            SubCommand::with_name("bootstrap_schema")
-                .about("Bootstrap the database schema. Run this first."),
+            SubCommand::with_name("migrate").about(
+                "Create or upgrade the database schema. Run this first, and after upgrading.",
+            ),
         )
+        .subcommand(SubCommand::with_name("bootstrap_schema").about("Same as migrate."))
         .subcommand(
             SubCommand::with_name("drop_schema")
                 .about("Drop the database schema. Normally not needed."),
         )
         .subcommand(
-            SubCommand::with_name("import").about("Import a file").arg(
-                Arg::with_name("path")
-                    .index(1)
-                    .help("The file to import.")
-                    .required(true),
-            ),
+            SubCommand::with_name("import")
+                .about("Import a file")
+                .arg(
+                    Arg::with_name("path")
+                        .index(1)
+                        .help("The file to import.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("deck")
+                        .long("deck")
+                        .help("Deck to put the imported exercises in.")
+                        .takes_value(true),
+                ),
         )
         .subcommand(
             SubCommand::with_name("check")
@@ -642,7 +1017,8 @@ fn main() {
                         .help("The string to search for (including the ID field).")
                         .index(1)
                         .required(true),
-                ),
+                )
+                .arg(tag_arg()),
         )
         .subcommand(
             SubCommand::with_name("delete")
@@ -654,25 +1030,108 @@ fn main() {
                         .required(true),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("suspend")
+                .about("Keep an exercise from coming up for review without deleting it.")
+                .arg(
+                    Arg::with_name("ID")
+                        .help("Primary key of the exercise to suspend.")
+                        .index(1)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("unsuspend")
+                .about("Let a suspended exercise come up for review again.")
+                .arg(
+                    Arg::with_name("ID")
+                        .help("Primary key of the exercise to unsuspend.")
+                        .index(1)
+                        .required(true),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("test_connection")
                 .about("Test the database configuration in config.toml."),
         )
-        .subcommand(SubCommand::with_name("count").about("Count exercises."))
-        .subcommand(SubCommand::with_name("ls").about("List all exercuses by due date descending."))
         .subcommand(
-            SubCommand::with_name("due").about("List all due exercises by due date descending."),
+            SubCommand::with_name("count")
+                .about("Count exercises.")
+                .arg(tag_arg()),
         )
         .subcommand(
-            SubCommand::with_name("schedule").about("List dates when exercises will be due"),
+            SubCommand::with_name("ls")
+                .about("List all exercuses by due date descending.")
+                .arg(tag_arg()),
         )
         .subcommand(
-            SubCommand::with_name("review").about(&review_str[..]).arg(
-                Arg::with_name("minutes")
-                    .help("Number of minutes to spend reviewing")
-                    .takes_value(true)
-                    .index(1),
-            ),
+            SubCommand::with_name("due")
+                .about("List all due exercises by due date descending.")
+                .arg(tag_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("schedule")
+                .about("List dates when exercises will be due")
+                .arg(tag_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("shift")
+                .about("Move due dates forward, e.g. after a vacation.")
+                .arg(
+                    Arg::with_name("days")
+                        .long("days")
+                        .help("Number of days to move every due date forward by.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("spread")
+                        .long("spread")
+                        .help("Spread whatever is due afterwards over this many days.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Show when exercises would be due without changing anything."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("deps")
+                .about("Show what an exercise builds on and what builds on it.")
+                .arg(
+                    Arg::with_name("ID")
+                        .help("Primary key of the exercise.")
+                        .index(1)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("leeches")
+                .about("List exercises that keep being forgotten, most lapses first."),
+        )
+        .subcommand(
+            SubCommand::with_name("review")
+                .about(&review_str[..])
+                .arg(
+                    Arg::with_name("minutes")
+                        .help("Number of minutes to spend reviewing")
+                        .takes_value(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("order")
+                        .long("order")
+                        .help("Order to review exercises in, instead of review_order from config.toml")
+                        .possible_values(&ReviewOrder::ALL.iter().map(|o| o.as_str()).collect::<Vec<_>>())
+                        .takes_value(true),
+                )
+                .arg(tag_arg())
+                .arg(
+                    Arg::with_name("deck")
+                        .long("deck")
+                        .help("Only review exercises in this deck, using its settings from config.toml.")
+                        .takes_value(true),
+                ),
         );
 
     let matches = app.clone().get_matches();
@@ -689,10 +1148,11 @@ fn main() {
     // necessary. let me know if I've done something wrong and there is a way to
     // make this work that actually does work.
     let args = std::env::args().collect::<Vec<_>>();
+    let tag = flag_value(&args, "--tag");
 
     match matches.subcommand_name().unwrap() {
-        "bootstrap_schema" => {
-            bootstrap_schema_command();
+        "migrate" | "bootstrap_schema" => {
+            migrate_command();
             return;
         }
         "drop_schema" => {
@@ -701,14 +1161,14 @@ fn main() {
         }
         "import" => {
             // see comment above
-            let path = &args[2];
-            import_command(&path, false);
+            let path = positional_args(&args)[0];
+            import_command(path, flag_value(&args, "--deck"), false);
             return;
         }
         "check" => {
             // see comment above
             let path = &args[2];
-            import_command(&path, true);
+            import_command(path, None, true);
             return;
         }
         "edit" => {
@@ -729,8 +1189,8 @@ fn main() {
         }
         "grep" => {
             // see comment above
-            let query = &args[2];
-            grep_command(&query);
+            let query = positional_args(&args)[0];
+            grep_command(query, tag);
             return;
         }
         "delete" => {
@@ -742,8 +1202,17 @@ fn main() {
             }
             return;
         }
+        "suspend" | "unsuspend" => {
+            // see comment above
+            let id_str = &args[2];
+            match id_str.parse::<i32>() {
+                Ok(id) => suspend_command(id, matches.subcommand_name() == Some("suspend")),
+                Err(_) => eprintln!("Cannot convert '{}' to a primary key", id_str),
+            }
+            return;
+        }
         "count" => {
-            count_command();
+            count_command(tag);
             return;
         }
         "test_connection" => {
@@ -751,27 +1220,66 @@ fn main() {
             return;
         }
         "ls" => {
-            ls_command();
+            ls_command(tag);
             return;
         }
         "due" => {
-            due_command();
+            due_command(tag);
             return;
         }
         "schedule" => {
-            schedule_command();
+            schedule_command(tag);
+            return;
+        }
+        "shift" => {
+            // see comment above
+            let mut amounts = vec![];
+            for flag in &["--days", "--spread"] {
+                let amount_str = flag_value(&args, flag).unwrap_or("0");
+                match amount_str.parse::<i32>() {
+                    Ok(amount) => amounts.push(amount),
+                    Err(_) => {
+                        eprintln!("Cannot convert '{}' to a number of days", amount_str);
+                        return;
+                    }
+                }
+            }
+            let dry_run = args.iter().any(|arg| arg == "--dry-run");
+            shift_command(amounts[0], amounts[1], dry_run);
+            return;
+        }
+        "leeches" => {
+            leeches_command();
+            return;
+        }
+        "deps" => {
+            // see comment above
+            let id_str = &args[2];
+            match id_str.parse::<i32>() {
+                Ok(id) => deps_command(id),
+                Err(_) => eprintln!("Cannot convert '{}' to a primary key", id_str),
+            }
             return;
         }
         "review" => {
             // see comment above
-            if args.len() == 3 {
-                let minutes_str = &args[2];
-                match minutes_str.parse::<i64>() {
-                    Ok(minutes) => review_command(Some(minutes)),
-                    Err(_) => eprintln!("Cannot convert '{}' to a minute amount", minutes_str),
+            let order = match flag_value(&args, "--order").map(str::parse::<ReviewOrder>) {
+                Some(Ok(order)) => Some(order),
+                Some(Err(e)) => {
+                    eprintln!("{}", e);
+                    return;
                 }
-            } else {
-                review_command(None);
+                None => None,
+            };
+
+            let deck = flag_value(&args, "--deck");
+
+            match positional_args(&args).first() {
+                Some(minutes_str) => match minutes_str.parse::<i64>() {
+                    Ok(minutes) => review_command(Some(minutes), order, tag, deck),
+                    Err(_) => eprintln!("Cannot convert '{}' to a minute amount", minutes_str),
+                },
+                None => review_command(None, order, tag, deck),
             }
             return;
         }
@@ -0,0 +1,292 @@
+use std::error::Error;
+
+use crate::make_error;
+
+// schema changes, oldest first. each one is applied exactly once and recorded
+// in the schema_version table, so never edit a migration that has shipped;
+// add a new one to the end instead.
+//
+// the first migration uses "if not exists" everywhere so that databases made
+// by the old bootstrap_schema, which predates schema_version, can be brought
+// up to date without losing anything.
+pub struct Migration {
+    pub version: i32,
+    pub description: &'static str,
+    pub postgres: &'static str,
+    pub sqlite: &'static str,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Create exercises table",
+        postgres: "
+        create table if not exists schema_version(
+            version integer primary key,
+            applied_at timestamp with time zone not null default now()
+        );
+
+        create table if not exists exercises(
+            id serial primary key,
+            created_at date not null default current_date,
+            description text unique not null,
+            source text not null,
+            reference_answer text not null,
+            due_at date not null default current_date,
+            update_interval integer not null default 0,
+            consecutive_successful_reviews integer not null default 0
+        );
+
+        create index if not exists exercises_due_at on exercises(due_at);",
+        // autoincrement so IDs of deleted exercises are never handed out again,
+        // same as a postgres serial column
+        sqlite: "
+        create table if not exists schema_version(
+            version integer primary key,
+            applied_at text not null default current_timestamp
+        );
+
+        create table if not exists exercises(
+            id integer primary key autoincrement,
+            created_at text not null default current_date,
+            description text unique not null,
+            source text not null,
+            reference_answer text not null,
+            due_at text not null default current_date,
+            update_interval integer not null default 0,
+            consecutive_successful_reviews integer not null default 0
+        );
+
+        create index if not exists exercises_due_at on exercises(due_at);",
+    },
+    Migration {
+        version: 2,
+        description: "Record every review",
+        postgres: "
+        create table reviews(
+            id serial primary key,
+            exercise_id integer not null references exercises(id) on delete cascade,
+            reviewed_at timestamp with time zone not null default now(),
+            outcome text not null,
+            time_spent_seconds integer not null,
+            interval_before integer not null,
+            interval_after integer not null
+        );
+
+        create index reviews_exercise_id on reviews(exercise_id);",
+        sqlite: "
+        create table reviews(
+            id integer primary key autoincrement,
+            exercise_id integer not null references exercises(id) on delete cascade,
+            reviewed_at text not null default current_timestamp,
+            outcome text not null,
+            time_spent_seconds integer not null,
+            interval_before integer not null,
+            interval_after integer not null
+        );
+
+        create index reviews_exercise_id on reviews(exercise_id);",
+    },
+    Migration {
+        version: 3,
+        description: "Grade answers instead of marking them correct or incorrect",
+        postgres: "
+        update reviews set outcome = 'good' where outcome = 'correct';
+        update reviews set outcome = 'again' where outcome = 'incorrect';
+
+        alter table exercises add column last_grade text;
+
+        update exercises set last_grade = (
+            select outcome from reviews
+            where reviews.exercise_id = exercises.id
+            order by reviewed_at desc, id desc
+            limit 1
+        );",
+        sqlite: "
+        update reviews set outcome = 'good' where outcome = 'correct';
+        update reviews set outcome = 'again' where outcome = 'incorrect';
+
+        alter table exercises add column last_grade text;
+
+        update exercises set last_grade = (
+            select outcome from reviews
+            where reviews.exercise_id = exercises.id
+            order by reviewed_at desc, id desc
+            limit 1
+        );",
+    },
+    Migration {
+        version: 4,
+        description: "Give every exercise its own easiness factor",
+        postgres: "
+        alter table exercises add column easiness_factor double precision not null default 2.0;",
+        sqlite: "
+        alter table exercises add column easiness_factor real not null default 2.0;",
+    },
+    Migration {
+        version: 5,
+        description: "Record lapses and relearning",
+        postgres: "
+        alter table exercises add column relearning_step integer;
+        alter table reviews add column lapse boolean not null default false;
+        alter table reviews add column relearning_step integer;
+        update reviews set lapse = true where outcome = 'again' and interval_before > 0;",
+        sqlite: "
+        alter table exercises add column relearning_step integer;
+        alter table reviews add column lapse integer not null default 0;
+        alter table reviews add column relearning_step integer;
+        update reviews set lapse = 1 where outcome = 'again' and interval_before > 0;",
+    },
+    Migration {
+        version: 6,
+        description: "Count lapses and allow suspending exercises",
+        postgres: "
+        alter table exercises add column lapses integer not null default 0;
+        alter table exercises add column suspended boolean not null default false;
+        update exercises set lapses = (
+            select count(*) from reviews where reviews.exercise_id = exercises.id and lapse
+        );",
+        sqlite: "
+        alter table exercises add column lapses integer not null default 0;
+        alter table exercises add column suspended integer not null default 0;
+        update exercises set lapses = (
+            select count(*) from reviews where reviews.exercise_id = exercises.id and lapse
+        );",
+    },
+    // exercises studied before reviews were recorded have no reviews to go
+    // by, but their schedule shows they aren't new. the day they were
+    // created is the best guess at when they were first reviewed.
+    Migration {
+        version: 7,
+        description: "Keep track of which exercises are new",
+        postgres: "
+        alter table exercises add column first_reviewed_at date;
+
+        update exercises set first_reviewed_at = coalesce(
+            (
+                select min(reviewed_at)::date from reviews
+                where reviews.exercise_id = exercises.id
+                and outcome not in ('skipped', 'buried')
+            ),
+            case when update_interval > 0 or consecutive_successful_reviews > 0
+            then created_at end
+        );",
+        sqlite: "
+        alter table exercises add column first_reviewed_at text;
+
+        update exercises set first_reviewed_at = coalesce(
+            (
+                select date(min(reviewed_at)) from reviews
+                where reviews.exercise_id = exercises.id
+                and outcome not in ('skipped', 'buried')
+            ),
+            case when update_interval > 0 or consecutive_successful_reviews > 0
+            then created_at end
+        );",
+    },
+    Migration {
+        version: 8,
+        description: "Tag exercises",
+        postgres: "
+        create table exercise_tags(
+            exercise_id integer not null references exercises(id) on delete cascade,
+            tag text not null,
+            primary key(exercise_id, tag)
+        );
+
+        create index exercise_tags_tag on exercise_tags(tag);",
+        sqlite: "
+        create table exercise_tags(
+            exercise_id integer not null references exercises(id) on delete cascade,
+            tag text not null,
+            primary key(exercise_id, tag)
+        );
+
+        create index exercise_tags_tag on exercise_tags(tag);",
+    },
+    Migration {
+        version: 9,
+        description: "Put exercises in decks",
+        postgres: "
+        alter table exercises add column deck text;
+
+        create index exercises_deck on exercises(deck);",
+        sqlite: "
+        alter table exercises add column deck text;
+
+        create index exercises_deck on exercises(deck);",
+    },
+    Migration {
+        version: 10,
+        description: "Link exercises to their prerequisites",
+        postgres: "
+        create table exercise_prerequisites(
+            exercise_id integer not null references exercises(id) on delete cascade,
+            prerequisite_id integer not null references exercises(id) on delete cascade,
+            primary key(exercise_id, prerequisite_id)
+        );
+
+        create index exercise_prerequisites_prerequisite_id
+            on exercise_prerequisites(prerequisite_id);",
+        sqlite: "
+        create table exercise_prerequisites(
+            exercise_id integer not null references exercises(id) on delete cascade,
+            prerequisite_id integer not null references exercises(id) on delete cascade,
+            primary key(exercise_id, prerequisite_id)
+        );
+
+        create index exercise_prerequisites_prerequisite_id
+            on exercise_prerequisites(prerequisite_id);",
+    },
+    Migration {
+        version: 11,
+        description: "Make several exercises from one cloze",
+        postgres: "
+        alter table exercises add column sibling_group text;
+        alter table exercises add column cloze text;
+        alter table exercises add column cloze_number integer;
+
+        create index exercises_sibling_group on exercises(sibling_group);",
+        sqlite: "
+        alter table exercises add column sibling_group text;
+        alter table exercises add column cloze text;
+        alter table exercises add column cloze_number integer;
+
+        create index exercises_sibling_group on exercises(sibling_group);",
+    },
+    Migration {
+        version: 12,
+        description: "Let exercises have variables",
+        postgres: "
+        alter table exercises add column variables text;",
+        sqlite: "
+        alter table exercises add column variables text;",
+    },
+];
+
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+// for a database that was migrated by a newer arete than this one
+pub fn schema_too_new_error(current_version: i32) -> Box<dyn Error> {
+    make_error(format!(
+        "Database schema is at version {}, but this version of arete only knows about versions up to {}. Please upgrade arete.",
+        current_version,
+        latest_version()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_numbered_in_order() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as i32 + 1);
+        }
+
+        assert_eq!(latest_version(), MIGRATIONS.len() as i32);
+    }
+}
@@ -0,0 +1,172 @@
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::make_error;
+
+// how well an answer was recalled, worst first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Grade {
+    Again,
+    Hard,
+    Good,
+    Easy,
+}
+
+impl Grade {
+    pub const ALL: [Grade; 4] = [Grade::Again, Grade::Hard, Grade::Good, Grade::Easy];
+
+    // this is what gets stored in the database, so don't change these
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Grade::Again => "again",
+            Grade::Hard => "hard",
+            Grade::Good => "good",
+            Grade::Easy => "easy",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Grade::Again => "Again",
+            Grade::Hard => "Hard",
+            Grade::Good => "Good",
+            Grade::Easy => "Easy",
+        }
+    }
+
+    // the 0-5 "quality of response" from SM-2
+    pub fn quality(self) -> i32 {
+        match self {
+            Grade::Again => 1,
+            Grade::Hard => 3,
+            Grade::Good => 4,
+            Grade::Easy => 5,
+        }
+    }
+
+    pub fn is_correct(self) -> bool {
+        self != Grade::Again
+    }
+}
+
+impl fmt::Display for Grade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Grade {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> Result<Grade, Self::Err> {
+        match s {
+            "again" => Ok(Grade::Again),
+            "hard" => Ok(Grade::Hard),
+            "good" => Ok(Grade::Good),
+            "easy" => Ok(Grade::Easy),
+            _ => Err(make_error(format!("Unknown grade '{}'", s))),
+        }
+    }
+}
+
+// what happened to an exercise when it came up for review. skipped and buried
+// exercises weren't answered, so they leave the schedule alone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Graded(Grade),
+    // moved to the end of the session
+    Skipped,
+    // put off until tomorrow
+    Buried,
+}
+
+impl Outcome {
+    // stored in the same column as grades, so these can't clash with them
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Graded(grade) => grade.as_str(),
+            Outcome::Skipped => "skipped",
+            Outcome::Buried => "buried",
+        }
+    }
+
+    pub fn grade(self) -> Option<Grade> {
+        match self {
+            Outcome::Graded(grade) => Some(grade),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Outcome {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> Result<Outcome, Self::Err> {
+        match s {
+            "skipped" => Ok(Outcome::Skipped),
+            "buried" => Ok(Outcome::Buried),
+            _ => Ok(Outcome::Graded(s.parse()?)),
+        }
+    }
+}
+
+// one answer to one exercise. intervals are in days, the same as
+// Exercise::update_interval. lapse is set when an exercise that had been
+// learned was forgotten, and relearning_step is where the exercise was left
+// in its relearning steps afterwards, if anywhere.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Review {
+    pub id: Option<i32>,
+    pub exercise_id: i32,
+    pub reviewed_at: DateTime<Utc>,
+    pub outcome: Outcome,
+    pub time_spent_seconds: i32,
+    pub interval_before: i32,
+    pub interval_after: i32,
+    pub lapse: bool,
+    pub relearning_step: Option<i32>,
+}
+
+impl Review {
+    // None if the exercise wasn't answered
+    pub fn grade(&self) -> Option<Grade> {
+        self.outcome.grade()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grade_round_trip() {
+        for grade in &Grade::ALL {
+            assert_eq!(grade.as_str().parse::<Grade>().unwrap(), *grade);
+        }
+
+        assert!("correct".parse::<Grade>().is_err());
+    }
+
+    #[test]
+    fn test_outcome_round_trip() {
+        let outcomes = Grade::ALL
+            .iter()
+            .map(|grade| Outcome::Graded(*grade))
+            .chain(vec![Outcome::Skipped, Outcome::Buried]);
+
+        for outcome in outcomes {
+            assert_eq!(outcome.as_str().parse::<Outcome>().unwrap(), outcome);
+        }
+
+        assert_eq!(Outcome::Buried.grade(), None);
+        assert_eq!(Outcome::Graded(Grade::Hard).grade(), Some(Grade::Hard));
+        assert!("correct".parse::<Outcome>().is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use chrono::{Duration, NaiveDate};
+use rand::Rng;
+
+use crate::scheduler::{SchedulingConfig, ONE_DAY};
+
+// shorter intervals than this are left exactly as the scheduler made them,
+// since a day either way would make a big difference
+const MIN_ADJUSTABLE_INTERVAL: i32 = ONE_DAY * 3;
+
+// without this, exercises imported together get the same grades on the same
+// day and stay in lockstep forever. fuzz picks an interval at random close to
+// the one the scheduler asked for, then load balancing moves the due date to
+// the lightest day nearby.
+//
+// due_counts looks up the number of exercises due per day between two dates,
+// inclusive, like get_schedule does for every day. it's only called when load
+// balancing actually has a window to look at.
+pub fn adjust_interval<F, R>(
+    config: &SchedulingConfig,
+    update_interval: i32,
+    today: NaiveDate,
+    due_counts: F,
+    rng: &mut R,
+) -> i32
+where
+    F: FnOnce(NaiveDate, NaiveDate) -> Vec<(NaiveDate, i32)>,
+    R: Rng,
+{
+    if update_interval < MIN_ADJUSTABLE_INTERVAL {
+        return update_interval;
+    }
+
+    let mut update_interval = update_interval;
+
+    if config.fuzz > 0.0 {
+        let spread = std::cmp::max(
+            ONE_DAY,
+            (f64::from(update_interval) * config.fuzz).round() as i32,
+        );
+        update_interval = rng.gen_range(update_interval - spread, update_interval + spread + 1);
+    }
+
+    // never move by more than a quarter of the interval, so a 4 day interval
+    // can only move by a day
+    let window = std::cmp::min(config.load_balance_days, update_interval / 4);
+
+    if window > 0 {
+        let due_counts = due_counts(
+            today + Duration::days(i64::from(update_interval - window)),
+            today + Duration::days(i64::from(update_interval + window)),
+        );
+
+        let due_on = |interval: i32| {
+            let due_at = today + Duration::days(i64::from(interval));
+
+            due_counts
+                .iter()
+                .find(|(date, _)| *date == due_at)
+                .map_or(0, |(_, count)| *count)
+        };
+
+        // fewest exercises first, then closest to the original
+        update_interval = (update_interval - window..=update_interval + window)
+            .min_by_key(|interval| (due_on(*interval), (interval - update_interval).abs()))
+            .unwrap();
+    }
+
+    update_interval.clamp(MIN_ADJUSTABLE_INTERVAL, config.max_interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn config(fuzz: f64, load_balance_days: i32) -> SchedulingConfig {
+        SchedulingConfig {
+            fuzz,
+            load_balance_days,
+            ..SchedulingConfig::default()
+        }
+    }
+
+    fn no_counts(_: NaiveDate, _: NaiveDate) -> Vec<(NaiveDate, i32)> {
+        panic!("due counts aren't needed without load balancing");
+    }
+
+    fn counts(
+        due_counts: &[(NaiveDate, i32)],
+    ) -> impl FnOnce(NaiveDate, NaiveDate) -> Vec<(NaiveDate, i32)> + '_ {
+        move |first, last| {
+            due_counts
+                .iter()
+                .filter(|(date, _)| *date >= first && *date <= last)
+                .cloned()
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_adjust_interval_off_by_default() {
+        let today = NaiveDate::from_ymd(2019, 6, 1);
+        let mut rng = StdRng::seed_from_u64(1);
+
+        for interval in 1..=90 {
+            assert_eq!(
+                adjust_interval(
+                    &SchedulingConfig::default(),
+                    interval,
+                    today,
+                    no_counts,
+                    &mut rng
+                ),
+                interval
+            );
+        }
+    }
+
+    #[test]
+    fn test_adjust_interval_fuzz() {
+        let today = NaiveDate::from_ymd(2019, 6, 1);
+        let mut rng = StdRng::seed_from_u64(1);
+        let config = config(0.1, 0);
+
+        // short intervals are left alone
+        assert_eq!(adjust_interval(&config, 1, today, no_counts, &mut rng), 1);
+        assert_eq!(adjust_interval(&config, 2, today, no_counts, &mut rng), 2);
+
+        let intervals = (0..100)
+            .map(|_| adjust_interval(&config, 30, today, no_counts, &mut rng))
+            .collect::<Vec<_>>();
+
+        assert!(intervals.iter().all(|i| *i >= 27 && *i <= 33));
+        assert!(intervals.iter().any(|i| *i < 30));
+        assert!(intervals.iter().any(|i| *i > 30));
+
+        // still capped
+        assert!((0..100).all(|_| adjust_interval(&config, 90, today, no_counts, &mut rng) <= 90));
+    }
+
+    #[test]
+    fn test_adjust_interval_load_balancing() {
+        let today = NaiveDate::from_ymd(2019, 6, 1);
+        let mut rng = StdRng::seed_from_u64(1);
+        let day = |n| today + Duration::days(n);
+
+        let due_counts = [(day(9), 3), (day(10), 50), (day(11), 2), (day(12), 2)];
+
+        // moves to the lightest day within the window
+        assert_eq!(
+            adjust_interval(&config(0.0, 2), 10, today, counts(&due_counts), &mut rng),
+            8
+        );
+        assert_eq!(
+            adjust_interval(&config(0.0, 1), 10, today, counts(&due_counts), &mut rng),
+            11
+        );
+
+        // the window is limited to a quarter of the interval
+        assert_eq!(
+            adjust_interval(&config(0.0, 5), 4, today, counts(&[(day(4), 10)]), &mut rng),
+            3
+        );
+
+        // only the days in the window are looked up
+        let mut window = None;
+        adjust_interval(
+            &config(0.0, 2),
+            10,
+            today,
+            |first, last| {
+                window = Some((first, last));
+                vec![]
+            },
+            &mut rng,
+        );
+        assert_eq!(window, Some((day(8), day(12))));
+
+        // stays put when nothing is lighter
+        assert_eq!(
+            adjust_interval(&config(0.0, 2), 20, today, counts(&due_counts), &mut rng),
+            20
+        );
+    }
+}
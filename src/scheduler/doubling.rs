@@ -0,0 +1,212 @@
+use chrono::NaiveDate;
+
+use crate::scheduler::{Scheduler, SchedulingConfig, SchedulingState, ONE_DAY};
+use crate::{Grade, Review};
+
+// the original arete schedule: due first_interval days after the first
+// success, then the interval is multiplied by growth_factor every time, up to
+// max_interval. Hard repeats the last interval and Easy skips a step.
+#[derive(Default)]
+pub struct DoublingScheduler {
+    config: SchedulingConfig,
+}
+
+impl DoublingScheduler {
+    pub fn new(config: SchedulingConfig) -> DoublingScheduler {
+        DoublingScheduler { config }
+    }
+
+    // always moves forward at least a day, even with a small growth factor
+    fn grow(&self, update_interval: i32, steps: i32) -> i32 {
+        let grown = f64::from(update_interval) * self.config.growth_factor.powi(steps);
+
+        std::cmp::max(update_interval + ONE_DAY, grown.round() as i32)
+    }
+}
+
+impl Scheduler for DoublingScheduler {
+    fn next_state(
+        &self,
+        state: SchedulingState,
+        grade: Grade,
+        _history: &[Review],
+        today: NaiveDate,
+    ) -> SchedulingState {
+        if !grade.is_correct() {
+            return state.lapsed(&self.config, today);
+        }
+
+        if let Some(state) = state.relearned(&self.config, today) {
+            return state;
+        }
+
+        let first_interval = self.config.first_interval;
+
+        // a lapse may have left an interval to carry on from
+        let update_interval = match (grade, state.update_interval) {
+            (Grade::Easy, 0) => self.grow(first_interval, 1),
+            (_, 0) => first_interval,
+            (Grade::Hard, _) => state.update_interval,
+            (Grade::Easy, _) => self.grow(state.update_interval, 2),
+            _ => self.grow(state.update_interval, 1),
+        };
+
+        state.succeeded(&self.config, update_interval, today)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{todays_date, Exercise};
+    use chrono::Duration;
+
+    fn review_with(scheduler: &DoublingScheduler, exercise: &mut Exercise, grade: Grade) {
+        let state = scheduler.next_state(exercise.scheduling_state(), grade, &[], todays_date());
+        exercise.set_scheduling_state(state);
+    }
+
+    fn review(exercise: &mut Exercise, grade: Grade) {
+        review_with(&DoublingScheduler::default(), exercise, grade);
+    }
+
+    #[test]
+    fn test_doubling_intervals() {
+        let mut exercise = Exercise::new("", "", "");
+
+        let today = todays_date();
+
+        assert_eq!(exercise.due_at, today);
+        assert_eq!(exercise.consecutive_successful_reviews, 0);
+        assert_eq!(exercise.update_interval, 0);
+
+        review(&mut exercise, Grade::Good);
+        assert_eq!(exercise.due_at, today + Duration::days(1));
+        assert_eq!(exercise.consecutive_successful_reviews, 1);
+        assert_eq!(exercise.update_interval, 1);
+
+        review(&mut exercise, Grade::Good);
+        assert_eq!(exercise.due_at, today + Duration::days(2));
+        assert_eq!(exercise.consecutive_successful_reviews, 2);
+        assert_eq!(exercise.update_interval, 2);
+
+        review(&mut exercise, Grade::Good);
+        assert_eq!(exercise.due_at, today + Duration::days(4));
+        assert_eq!(exercise.consecutive_successful_reviews, 3);
+        assert_eq!(exercise.update_interval, 4);
+
+        review(&mut exercise, Grade::Good);
+        assert_eq!(exercise.due_at, today + Duration::days(8));
+        assert_eq!(exercise.consecutive_successful_reviews, 4);
+        assert_eq!(exercise.update_interval, 8);
+
+        review(&mut exercise, Grade::Good);
+        assert_eq!(exercise.due_at, today + Duration::days(16));
+        assert_eq!(exercise.consecutive_successful_reviews, 5);
+        assert_eq!(exercise.update_interval, 16);
+
+        review(&mut exercise, Grade::Good);
+        assert_eq!(exercise.due_at, today + Duration::days(32));
+        assert_eq!(exercise.consecutive_successful_reviews, 6);
+        assert_eq!(exercise.update_interval, 32);
+
+        review(&mut exercise, Grade::Good);
+        assert_eq!(exercise.due_at, today + Duration::days(64));
+        assert_eq!(exercise.consecutive_successful_reviews, 7);
+        assert_eq!(exercise.update_interval, 64);
+
+        for i in 1..100 {
+            review(&mut exercise, Grade::Good);
+            assert_eq!(exercise.due_at, today + Duration::days(90));
+            assert_eq!(exercise.consecutive_successful_reviews, 7 + i);
+            assert_eq!(exercise.update_interval, 90);
+        }
+
+        review(&mut exercise, Grade::Again);
+        assert_eq!(exercise.due_at, today);
+        assert_eq!(exercise.consecutive_successful_reviews, 0);
+        assert_eq!(exercise.update_interval, 0);
+
+        review(&mut exercise, Grade::Good);
+        assert_eq!(exercise.due_at, today + Duration::days(1));
+        assert_eq!(exercise.consecutive_successful_reviews, 1);
+        assert_eq!(exercise.update_interval, 1);
+    }
+
+    #[test]
+    fn test_doubling_hard_and_easy() {
+        let today = todays_date();
+
+        let mut exercise = Exercise::new("", "", "");
+        review(&mut exercise, Grade::Hard);
+        assert_eq!(exercise.update_interval, 1);
+        assert_eq!(exercise.consecutive_successful_reviews, 1);
+
+        review(&mut exercise, Grade::Good);
+        review(&mut exercise, Grade::Good);
+        assert_eq!(exercise.update_interval, 4);
+
+        // hard keeps the interval where it is
+        review(&mut exercise, Grade::Hard);
+        assert_eq!(exercise.update_interval, 4);
+        assert_eq!(exercise.due_at, today + Duration::days(4));
+        assert_eq!(exercise.consecutive_successful_reviews, 4);
+
+        // easy skips a step
+        review(&mut exercise, Grade::Easy);
+        assert_eq!(exercise.update_interval, 16);
+        assert_eq!(exercise.due_at, today + Duration::days(16));
+
+        review(&mut exercise, Grade::Easy);
+        review(&mut exercise, Grade::Easy);
+        assert_eq!(exercise.update_interval, 90);
+
+        let mut exercise = Exercise::new("", "", "");
+        review(&mut exercise, Grade::Easy);
+        assert_eq!(exercise.update_interval, 2);
+        assert_eq!(exercise.due_at, today + Duration::days(2));
+
+        // the easiness factor is left alone
+        assert_eq!(
+            exercise.easiness_factor,
+            crate::scheduler::sm2::INITIAL_EASINESS_FACTOR
+        );
+    }
+
+    #[test]
+    fn test_doubling_with_config() {
+        let today = todays_date();
+        let scheduler = DoublingScheduler::new(SchedulingConfig {
+            max_interval: 20,
+            first_interval: 3,
+            growth_factor: 1.5,
+            lapse_interval: 1,
+            ..SchedulingConfig::default()
+        });
+
+        let mut exercise = Exercise::new("", "", "");
+
+        let mut intervals = vec![];
+        for _ in 0..6 {
+            review_with(&scheduler, &mut exercise, Grade::Good);
+            intervals.push(exercise.update_interval);
+        }
+        assert_eq!(intervals, vec![3, 5, 8, 12, 18, 20]);
+        assert_eq!(exercise.due_at, today + Duration::days(20));
+
+        review_with(&scheduler, &mut exercise, Grade::Again);
+        assert_eq!(exercise.update_interval, 1);
+        assert_eq!(exercise.due_at, today + Duration::days(1));
+        assert_eq!(exercise.consecutive_successful_reviews, 0);
+
+        // a growth factor of 1 still moves forward
+        let scheduler = DoublingScheduler::new(SchedulingConfig {
+            growth_factor: 1.0,
+            ..SchedulingConfig::default()
+        });
+        let mut exercise = Exercise::new("", "", "");
+        review_with(&scheduler, &mut exercise, Grade::Good);
+        review_with(&scheduler, &mut exercise, Grade::Good);
+        assert_eq!(exercise.update_interval, 2);
+    }
+}
@@ -0,0 +1,276 @@
+use chrono::{Local, NaiveDate};
+
+use crate::scheduler::{Scheduler, SchedulingConfig, SchedulingState};
+use crate::{Grade, Review};
+
+// FSRS-4.5: https://github.com/open-spaced-repetition/fsrs4anki/wiki/The-Algorithm
+//
+// models each exercise's memory with a stability (how many days until recall
+// probability drops to 90%) and a difficulty (1 to 10). both are rebuilt from
+// the review history every time, so nothing extra has to be stored and
+// exercises reviewed under another scheduler pick up where they left off.
+
+pub const DEFAULT_TARGET_RETENTION: f64 = 0.9;
+
+// the default parameters, fit by the FSRS authors on a large set of Anki reviews
+const WEIGHTS: [f64; 17] = [
+    0.4872, 1.4003, 3.7145, 13.8206, 5.1618, 1.2298, 0.8975, 0.031, 1.6474, 0.1367, 1.0461, 2.1072,
+    0.0793, 0.3246, 1.587, 0.2272, 2.8755,
+];
+const DECAY: f64 = -0.5;
+// chosen so that retrievability is exactly 90% after `stability` days
+const FACTOR: f64 = 19.0 / 81.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct MemoryState {
+    stability: f64,
+    difficulty: f64,
+}
+
+// FSRS numbers its grades from 1
+fn rating(grade: Grade) -> f64 {
+    match grade {
+        Grade::Again => 1.0,
+        Grade::Hard => 2.0,
+        Grade::Good => 3.0,
+        Grade::Easy => 4.0,
+    }
+}
+
+fn initial_difficulty(grade: Grade) -> f64 {
+    (WEIGHTS[4] - (rating(grade) - 3.0) * WEIGHTS[5]).clamp(1.0, 10.0)
+}
+
+// the probability of recalling something with the given stability after
+// elapsed_days
+fn retrievability(elapsed_days: f64, stability: f64) -> f64 {
+    (1.0 + FACTOR * elapsed_days / stability).powf(DECAY)
+}
+
+impl MemoryState {
+    fn initial(grade: Grade) -> MemoryState {
+        MemoryState {
+            stability: WEIGHTS[rating(grade) as usize - 1].max(0.1),
+            difficulty: initial_difficulty(grade),
+        }
+    }
+
+    fn after_review(self, grade: Grade, elapsed_days: f64) -> MemoryState {
+        let r = retrievability(elapsed_days, self.stability);
+
+        // drift towards the difficulty of a new exercise graded Good so it
+        // can't get stuck at either end
+        let difficulty = self.difficulty - WEIGHTS[6] * (rating(grade) - 3.0);
+        let difficulty = (WEIGHTS[7] * initial_difficulty(Grade::Good)
+            + (1.0 - WEIGHTS[7]) * difficulty)
+            .clamp(1.0, 10.0);
+
+        let stability = if grade.is_correct() {
+            let hard_penalty = if grade == Grade::Hard {
+                WEIGHTS[15]
+            } else {
+                1.0
+            };
+            let easy_bonus = if grade == Grade::Easy {
+                WEIGHTS[16]
+            } else {
+                1.0
+            };
+
+            self.stability
+                * (1.0
+                    + WEIGHTS[8].exp()
+                        * (11.0 - difficulty)
+                        * self.stability.powf(-WEIGHTS[9])
+                        * (((1.0 - r) * WEIGHTS[10]).exp() - 1.0)
+                        * hard_penalty
+                        * easy_bonus)
+        } else {
+            WEIGHTS[11]
+                * difficulty.powf(-WEIGHTS[12])
+                * ((self.stability + 1.0).powf(WEIGHTS[13]) - 1.0)
+                * ((1.0 - r) * WEIGHTS[14]).exp()
+        };
+
+        MemoryState {
+            stability,
+            difficulty,
+        }
+    }
+}
+
+// replays every review, oldest first. None if there are no reviews.
+fn memory_state(reviews: &[(NaiveDate, Grade)]) -> Option<MemoryState> {
+    let mut state: Option<(MemoryState, NaiveDate)> = None;
+
+    for &(reviewed_on, grade) in reviews {
+        let next_state = match state {
+            Some((state, last_reviewed_on)) => {
+                let elapsed_days = (reviewed_on - last_reviewed_on).num_days() as f64;
+                state.after_review(grade, elapsed_days)
+            }
+            None => MemoryState::initial(grade),
+        };
+
+        state = Some((next_state, reviewed_on));
+    }
+
+    state.map(|(state, _)| state)
+}
+
+// target_retention is the probability of still knowing the answer when an
+// exercise comes due. higher means shorter intervals and more reviews.
+#[derive(Default)]
+pub struct FsrsScheduler {
+    config: SchedulingConfig,
+}
+
+impl FsrsScheduler {
+    pub fn new(config: SchedulingConfig) -> FsrsScheduler {
+        FsrsScheduler { config }
+    }
+
+    fn interval(&self, stability: f64) -> i32 {
+        let interval = stability / FACTOR * (self.config.target_retention.powf(1.0 / DECAY) - 1.0);
+
+        interval.round() as i32
+    }
+}
+
+impl Scheduler for FsrsScheduler {
+    fn next_state(
+        &self,
+        state: SchedulingState,
+        grade: Grade,
+        history: &[Review],
+        today: NaiveDate,
+    ) -> SchedulingState {
+        if !grade.is_correct() {
+            return state.lapsed(&self.config, today);
+        }
+
+        if let Some(state) = state.relearned(&self.config, today) {
+            return state;
+        }
+
+        // skipped and buried exercises weren't recalled either way
+        let mut reviews = history
+            .iter()
+            .filter_map(|review| {
+                let reviewed_on = review
+                    .reviewed_at
+                    .with_timezone(&Local)
+                    .naive_local()
+                    .date();
+                review.grade().map(|grade| (reviewed_on, grade))
+            })
+            .collect::<Vec<_>>();
+        reviews.push((today, grade));
+
+        let memory = memory_state(&reviews).unwrap();
+
+        state.succeeded(&self.config, self.interval(memory.stability), today)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{todays_date, Exercise, Outcome};
+    use chrono::{Duration, TimeZone, Utc};
+
+    fn review_on(date: NaiveDate, grade: Grade) -> Review {
+        Review {
+            id: None,
+            exercise_id: 1,
+            reviewed_at: Local
+                .from_local_date(&date)
+                .unwrap()
+                .and_hms(12, 0, 0)
+                .with_timezone(&Utc),
+            outcome: Outcome::Graded(grade),
+            time_spent_seconds: 10,
+            interval_before: 0,
+            interval_after: 0,
+            lapse: false,
+            relearning_step: None,
+        }
+    }
+
+    fn interval_after(scheduler: &FsrsScheduler, history: &[Review], grade: Grade) -> i32 {
+        let state = Exercise::new("", "", "").scheduling_state();
+        let state = scheduler.next_state(state, grade, history, todays_date());
+
+        assert_eq!(
+            state.due_at,
+            todays_date() + Duration::days(i64::from(state.update_interval))
+        );
+
+        state.update_interval
+    }
+
+    #[test]
+    fn test_fsrs_first_review() {
+        let scheduler = FsrsScheduler::default();
+
+        // at 90% retention, the interval is the stability
+        assert_eq!(interval_after(&scheduler, &[], Grade::Hard), 1);
+        assert_eq!(interval_after(&scheduler, &[], Grade::Good), 4);
+        assert_eq!(interval_after(&scheduler, &[], Grade::Easy), 14);
+
+        let state = Exercise::new("", "", "").scheduling_state();
+        let state = scheduler.next_state(state, Grade::Again, &[], todays_date());
+        assert_eq!(state.update_interval, 0);
+        assert_eq!(state.due_at, todays_date());
+        assert_eq!(state.consecutive_successful_reviews, 0);
+    }
+
+    #[test]
+    fn test_fsrs_uses_review_history() {
+        let scheduler = FsrsScheduler::default();
+        let today = todays_date();
+
+        let remembered = [
+            review_on(today - Duration::days(12), Grade::Good),
+            review_on(today - Duration::days(8), Grade::Good),
+        ];
+        let forgotten = [
+            review_on(today - Duration::days(12), Grade::Good),
+            review_on(today - Duration::days(8), Grade::Again),
+        ];
+
+        let first_interval = interval_after(&scheduler, &[], Grade::Good);
+        let remembered_interval = interval_after(&scheduler, &remembered, Grade::Good);
+        let forgotten_interval = interval_after(&scheduler, &forgotten, Grade::Good);
+
+        assert!(remembered_interval > first_interval);
+        assert!(forgotten_interval < remembered_interval);
+
+        // reviewing again the same day doesn't change anything
+        let state = memory_state(&[(today, Grade::Good)]).unwrap();
+        assert_eq!(state.after_review(Grade::Good, 0.0), state);
+    }
+
+    #[test]
+    fn test_fsrs_target_retention() {
+        let today = todays_date();
+        let history = [
+            review_on(today - Duration::days(20), Grade::Good),
+            review_on(today - Duration::days(16), Grade::Good),
+        ];
+
+        let with_retention = |target_retention| {
+            FsrsScheduler::new(SchedulingConfig {
+                target_retention,
+                ..SchedulingConfig::default()
+            })
+        };
+        let relaxed = with_retention(0.8);
+        let strict = with_retention(0.95);
+
+        assert!(
+            interval_after(&strict, &history, Grade::Good)
+                < interval_after(&relaxed, &history, Grade::Good)
+        );
+    }
+}
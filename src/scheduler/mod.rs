@@ -0,0 +1,493 @@
+use chrono::{Duration, NaiveDate};
+use serde_derive::Deserialize;
+use std::error::Error;
+
+use crate::{make_error, Grade, Review};
+
+pub mod balance;
+pub mod doubling;
+pub mod fsrs;
+pub mod order;
+pub mod shift;
+pub mod sm2;
+
+pub use self::doubling::DoublingScheduler;
+pub use self::fsrs::FsrsScheduler;
+pub use self::order::ReviewOrder;
+pub use self::sm2::Sm2Scheduler;
+
+pub const ONE_DAY: i32 = 1;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SchedulerKind {
+    #[default]
+    Doubling,
+    Sm2,
+    Fsrs,
+}
+
+// the [scheduling] section of config.toml. every setting is optional.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct SchedulingConfig {
+    pub scheduler: SchedulerKind,
+    // longest time between reviews, in days
+    pub max_interval: i32,
+    // days until an exercise is due after its first successful review
+    pub first_interval: i32,
+    // what the doubling scheduler multiplies the interval by. SM-2 uses each
+    // exercise's easiness factor instead and FSRS works it out itself.
+    pub growth_factor: f64,
+    // what the interval is multiplied by when an exercise is failed. 0 starts
+    // it over.
+    pub lapse_factor: f64,
+    // the shortest interval after a failed review. 0 is the same day.
+    pub lapse_interval: i32,
+    // days between reviews while relearning a lapsed exercise, before it goes
+    // back to its reduced interval. empty skips relearning.
+    pub relearning_steps: Vec<i32>,
+    // only used by FSRS
+    pub target_retention: f64,
+    // how much successful intervals vary at random, as a fraction of the
+    // interval. 0 turns it off.
+    pub fuzz: f64,
+    // how many days a due date may move to land on a day with fewer exercises
+    // due. 0 turns it off.
+    pub load_balance_days: i32,
+    // how many lapses make an exercise a leech
+    pub leech_threshold: i32,
+    // whether leeches are suspended as soon as they become one
+    pub suspend_leeches: bool,
+    // what order due exercises are reviewed in
+    pub review_order: ReviewOrder,
+    // how many new exercises can come up for review each day. 0 means no
+    // limit.
+    pub new_per_day: i32,
+}
+
+impl Default for SchedulingConfig {
+    fn default() -> SchedulingConfig {
+        SchedulingConfig {
+            scheduler: SchedulerKind::default(),
+            max_interval: ONE_DAY * 90,
+            first_interval: ONE_DAY,
+            growth_factor: 2.0,
+            lapse_factor: 0.0,
+            lapse_interval: 0,
+            relearning_steps: vec![],
+            target_retention: fsrs::DEFAULT_TARGET_RETENTION,
+            fuzz: 0.0,
+            load_balance_days: 0,
+            leech_threshold: 8,
+            suspend_leeches: false,
+            review_order: ReviewOrder::default(),
+            new_per_day: 0,
+        }
+    }
+}
+
+impl SchedulingConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.first_interval < ONE_DAY {
+            return Err(make_error(format!(
+                "first_interval must be at least 1 day, got {}",
+                self.first_interval
+            )));
+        }
+
+        if self.max_interval < self.first_interval {
+            return Err(make_error(format!(
+                "max_interval must be at least first_interval ({}), got {}",
+                self.first_interval, self.max_interval
+            )));
+        }
+
+        if self.growth_factor < 1.0 {
+            return Err(make_error(format!(
+                "growth_factor must be at least 1, got {}",
+                self.growth_factor
+            )));
+        }
+
+        if self.lapse_interval < 0 || self.lapse_interval > self.max_interval {
+            return Err(make_error(format!(
+                "lapse_interval must be between 0 and max_interval ({}), got {}",
+                self.max_interval, self.lapse_interval
+            )));
+        }
+
+        if self.lapse_factor < 0.0 || self.lapse_factor > 1.0 {
+            return Err(make_error(format!(
+                "lapse_factor must be between 0 and 1, got {}",
+                self.lapse_factor
+            )));
+        }
+
+        if let Some(step) = self
+            .relearning_steps
+            .iter()
+            .find(|step| **step < 0 || **step > self.max_interval)
+        {
+            return Err(make_error(format!(
+                "relearning_steps must be between 0 and max_interval ({}), got {}",
+                self.max_interval, step
+            )));
+        }
+
+        if self.target_retention <= 0.0 || self.target_retention >= 1.0 {
+            return Err(make_error(format!(
+                "target_retention must be between 0 and 1, got {}",
+                self.target_retention
+            )));
+        }
+
+        if self.fuzz < 0.0 || self.fuzz >= 1.0 {
+            return Err(make_error(format!(
+                "fuzz must be at least 0 and less than 1, got {}",
+                self.fuzz
+            )));
+        }
+
+        if self.load_balance_days < 0 {
+            return Err(make_error(format!(
+                "load_balance_days must be at least 0, got {}",
+                self.load_balance_days
+            )));
+        }
+
+        if self.new_per_day < 0 {
+            return Err(make_error(format!(
+                "new_per_day must be at least 0, got {}",
+                self.new_per_day
+            )));
+        }
+
+        if self.leech_threshold < 1 {
+            return Err(make_error(format!(
+                "leech_threshold must be at least 1, got {}",
+                self.leech_threshold
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn open_scheduler(&self) -> Result<Box<dyn Scheduler>, Box<dyn Error>> {
+        self.validate()?;
+
+        Ok(match self.scheduler {
+            SchedulerKind::Doubling => Box::new(DoublingScheduler::new(self.clone())),
+            SchedulerKind::Sm2 => Box::new(Sm2Scheduler::new(self.clone())),
+            SchedulerKind::Fsrs => Box::new(FsrsScheduler::new(self.clone())),
+        })
+    }
+
+    fn clamp_interval(&self, update_interval: i32) -> i32 {
+        update_interval.clamp(ONE_DAY, self.max_interval)
+    }
+}
+
+// the parts of an exercise that schedulers read and write
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SchedulingState {
+    pub due_at: NaiveDate,
+    pub update_interval: i32,
+    pub consecutive_successful_reviews: i32,
+    // only SM-2 changes this, the others carry it along untouched
+    pub easiness_factor: f64,
+    // which of the relearning steps the exercise is on, if it's relearning.
+    // update_interval is the interval it goes back to afterwards.
+    pub relearning_step: Option<i32>,
+}
+
+impl SchedulingState {
+    fn due_in(self, days: i32, today: NaiveDate) -> NaiveDate {
+        today + Duration::days(i64::from(days))
+    }
+
+    // failing something that had been learned, as opposed to failing a new
+    // exercise or failing again while relearning
+    pub fn is_lapse(&self, grade: Grade) -> bool {
+        !grade.is_correct()
+            && self.consecutive_successful_reviews > 0
+            && self.relearning_step.is_none()
+    }
+
+    // shared by every scheduler: shrinks the interval according to the lapse
+    // settings and starts relearning, if there are any relearning steps
+    pub fn lapsed(self, config: &SchedulingConfig, today: NaiveDate) -> SchedulingState {
+        let update_interval = std::cmp::max(
+            config.lapse_interval,
+            (f64::from(self.update_interval) * config.lapse_factor).round() as i32,
+        );
+
+        let relearning_step = match config.relearning_steps.first() {
+            Some(_) if self.is_lapse(Grade::Again) || self.relearning_step.is_some() => Some(0),
+            _ => None,
+        };
+
+        // failing while relearning starts the steps over without shrinking
+        // the interval any further
+        let update_interval = if self.relearning_step.is_some() {
+            self.update_interval
+        } else {
+            update_interval
+        };
+
+        let due_at = match relearning_step {
+            Some(_) => self.due_in(config.relearning_steps[0], today),
+            None => self.due_in(update_interval, today),
+        };
+
+        SchedulingState {
+            due_at,
+            update_interval,
+            consecutive_successful_reviews: 0,
+            relearning_step,
+            ..self
+        }
+    }
+
+    // a successful review while relearning moves on to the next step, or back
+    // to regular reviews after the last one. None if not relearning.
+    pub fn relearned(self, config: &SchedulingConfig, today: NaiveDate) -> Option<SchedulingState> {
+        let next_step = self.relearning_step? + 1;
+
+        Some(match config.relearning_steps.get(next_step as usize) {
+            Some(days) => SchedulingState {
+                due_at: self.due_in(*days, today),
+                relearning_step: Some(next_step),
+                ..self
+            },
+            None => {
+                let update_interval = config.clamp_interval(self.update_interval);
+
+                SchedulingState {
+                    due_at: self.due_in(update_interval, today),
+                    update_interval,
+                    consecutive_successful_reviews: 1,
+                    relearning_step: None,
+                    ..self
+                }
+            }
+        })
+    }
+
+    // the interval is clamped to the configured limits
+    pub fn succeeded(
+        self,
+        config: &SchedulingConfig,
+        update_interval: i32,
+        today: NaiveDate,
+    ) -> SchedulingState {
+        let update_interval = config.clamp_interval(update_interval);
+
+        SchedulingState {
+            due_at: self.due_in(update_interval, today),
+            update_interval,
+            consecutive_successful_reviews: self.consecutive_successful_reviews + 1,
+            relearning_step: None,
+            ..self
+        }
+    }
+}
+
+// decides when an exercise comes up again after a review. implementations are
+// pure functions of their arguments, so they can be tested without a database.
+pub trait Scheduler {
+    // history is every earlier review of the exercise, oldest first, not
+    // including this one. only FSRS needs it.
+    fn next_state(
+        &self,
+        state: SchedulingState,
+        grade: Grade,
+        history: &[Review],
+        today: NaiveDate,
+    ) -> SchedulingState;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml_str: &str) -> SchedulingConfig {
+        toml::from_str(toml_str).unwrap()
+    }
+
+    #[test]
+    fn test_scheduling_config_defaults() {
+        assert_eq!(parse(""), SchedulingConfig::default());
+
+        let config = parse("scheduler = \"sm2\"\nmax_interval = 180");
+        assert_eq!(config.scheduler, SchedulerKind::Sm2);
+        assert_eq!(config.review_order, ReviewOrder::DueDate);
+        assert_eq!(config.max_interval, 180);
+        assert_eq!(config.first_interval, 1);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_scheduling_config_validation() {
+        let default = SchedulingConfig::default();
+        assert!(default.open_scheduler().is_ok());
+
+        let invalid = [
+            SchedulingConfig {
+                first_interval: 0,
+                ..default.clone()
+            },
+            SchedulingConfig {
+                max_interval: 10,
+                first_interval: 20,
+                ..default.clone()
+            },
+            SchedulingConfig {
+                growth_factor: 0.5,
+                ..default.clone()
+            },
+            SchedulingConfig {
+                lapse_interval: -1,
+                ..default.clone()
+            },
+            SchedulingConfig {
+                lapse_factor: 1.5,
+                ..default.clone()
+            },
+            SchedulingConfig {
+                relearning_steps: vec![1, -1],
+                ..default.clone()
+            },
+            SchedulingConfig {
+                target_retention: 1.0,
+                ..default.clone()
+            },
+            SchedulingConfig {
+                target_retention: 0.0,
+                ..default.clone()
+            },
+            SchedulingConfig {
+                fuzz: -0.1,
+                ..default.clone()
+            },
+            SchedulingConfig {
+                fuzz: 1.0,
+                ..default.clone()
+            },
+            SchedulingConfig {
+                leech_threshold: 0,
+                ..default.clone()
+            },
+            SchedulingConfig {
+                new_per_day: -1,
+                ..default.clone()
+            },
+            SchedulingConfig {
+                load_balance_days: -1,
+                ..default.clone()
+            },
+        ];
+
+        for config in &invalid {
+            assert!(config.validate().is_err());
+            assert!(config.open_scheduler().is_err());
+        }
+    }
+
+    fn learned_state(today: NaiveDate) -> SchedulingState {
+        SchedulingState {
+            due_at: today,
+            update_interval: 40,
+            consecutive_successful_reviews: 5,
+            easiness_factor: 2.0,
+            relearning_step: None,
+        }
+    }
+
+    #[test]
+    fn test_lapse_starts_over_by_default() {
+        let today = NaiveDate::from_ymd(2019, 6, 1);
+        let config = SchedulingConfig::default();
+
+        let state = learned_state(today);
+        assert!(state.is_lapse(Grade::Again));
+        assert!(!state.is_lapse(Grade::Hard));
+
+        let state = state.lapsed(&config, today);
+        assert_eq!(state.update_interval, 0);
+        assert_eq!(state.due_at, today);
+        assert_eq!(state.consecutive_successful_reviews, 0);
+        assert_eq!(state.relearning_step, None);
+
+        // failing it again before getting it right isn't another lapse
+        assert!(!state.is_lapse(Grade::Again));
+    }
+
+    #[test]
+    fn test_lapse_factor_and_minimum_interval() {
+        let today = NaiveDate::from_ymd(2019, 6, 1);
+        let config = SchedulingConfig {
+            lapse_factor: 0.5,
+            lapse_interval: 2,
+            ..SchedulingConfig::default()
+        };
+
+        let state = learned_state(today).lapsed(&config, today);
+        assert_eq!(state.update_interval, 20);
+        assert_eq!(state.due_at, today + Duration::days(20));
+        assert_eq!(state.consecutive_successful_reviews, 0);
+
+        let state = SchedulingState {
+            update_interval: 3,
+            ..learned_state(today)
+        }
+        .lapsed(&config, today);
+        assert_eq!(state.update_interval, 2);
+        assert_eq!(state.due_at, today + Duration::days(2));
+    }
+
+    #[test]
+    fn test_relearning_steps() {
+        let today = NaiveDate::from_ymd(2019, 6, 1);
+        let config = SchedulingConfig {
+            lapse_factor: 0.5,
+            relearning_steps: vec![1, 3],
+            ..SchedulingConfig::default()
+        };
+
+        let state = learned_state(today).lapsed(&config, today);
+        assert_eq!(state.relearning_step, Some(0));
+        assert_eq!(state.update_interval, 20);
+        assert_eq!(state.due_at, today + Duration::days(1));
+
+        let state = state.relearned(&config, today).unwrap();
+        assert_eq!(state.relearning_step, Some(1));
+        assert_eq!(state.due_at, today + Duration::days(3));
+
+        // failing while relearning starts the steps over, but doesn't shrink
+        // the interval again
+        let failed = state.lapsed(&config, today);
+        assert_eq!(failed.relearning_step, Some(0));
+        assert_eq!(failed.update_interval, 20);
+        assert_eq!(failed.due_at, today + Duration::days(1));
+
+        // after the last step, it goes back to regular reviews
+        let state = state.relearned(&config, today).unwrap();
+        assert_eq!(state.relearning_step, None);
+        assert_eq!(state.update_interval, 20);
+        assert_eq!(state.consecutive_successful_reviews, 1);
+        assert_eq!(state.due_at, today + Duration::days(20));
+
+        assert_eq!(state.relearned(&config, today), None);
+
+        // new exercises don't relearn
+        let state = SchedulingState {
+            update_interval: 0,
+            consecutive_successful_reviews: 0,
+            ..learned_state(today)
+        }
+        .lapsed(&config, today);
+        assert_eq!(state.relearning_step, None);
+        assert_eq!(state.due_at, today);
+    }
+}
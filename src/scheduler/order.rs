@@ -0,0 +1,189 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde_derive::Deserialize;
+use std::str::FromStr;
+
+use crate::{make_error, Exercise};
+
+// the order due exercises come up in during review. with a timebox, whatever
+// comes last is what gets cut off.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewOrder {
+    // most recently due first, the way get_due returns them
+    #[default]
+    DueDate,
+    MostOverdue,
+    ShortestInterval,
+    Random,
+    // grouped by source, most overdue first within each
+    BySource,
+    // one from each source in turn, so similar exercises aren't back to back
+    Interleaved,
+}
+
+impl ReviewOrder {
+    pub const ALL: [ReviewOrder; 6] = [
+        ReviewOrder::DueDate,
+        ReviewOrder::MostOverdue,
+        ReviewOrder::ShortestInterval,
+        ReviewOrder::Random,
+        ReviewOrder::BySource,
+        ReviewOrder::Interleaved,
+    ];
+
+    // the same names config.toml uses
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReviewOrder::DueDate => "due_date",
+            ReviewOrder::MostOverdue => "most_overdue",
+            ReviewOrder::ShortestInterval => "shortest_interval",
+            ReviewOrder::Random => "random",
+            ReviewOrder::BySource => "by_source",
+            ReviewOrder::Interleaved => "interleaved",
+        }
+    }
+}
+
+impl FromStr for ReviewOrder {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> Result<ReviewOrder, Self::Err> {
+        ReviewOrder::ALL
+            .iter()
+            .find(|order| order.as_str() == s)
+            .cloned()
+            .ok_or_else(|| {
+                let names = ReviewOrder::ALL
+                    .iter()
+                    .map(|order| order.as_str())
+                    .collect::<Vec<_>>();
+                make_error(format!(
+                    "Unknown review order '{}', expected one of: {}",
+                    s,
+                    names.join(", ")
+                ))
+            })
+    }
+}
+
+fn by_most_overdue(exercises: &mut [Exercise]) {
+    exercises.sort_by(|a, b| a.due_at.cmp(&b.due_at).then(a.id.cmp(&b.id)));
+}
+
+// exercises is expected to be in get_due order
+pub fn order_exercises<R: Rng>(
+    mut exercises: Vec<Exercise>,
+    order: ReviewOrder,
+    rng: &mut R,
+) -> Vec<Exercise> {
+    match order {
+        ReviewOrder::DueDate => {}
+        ReviewOrder::MostOverdue => by_most_overdue(&mut exercises),
+        ReviewOrder::ShortestInterval => {
+            by_most_overdue(&mut exercises);
+            // stable, so equal intervals stay most overdue first
+            exercises.sort_by_key(|exercise| exercise.update_interval);
+        }
+        ReviewOrder::Random => exercises.shuffle(rng),
+        ReviewOrder::BySource => {
+            by_most_overdue(&mut exercises);
+            exercises.sort_by(|a, b| a.source.cmp(&b.source));
+        }
+        ReviewOrder::Interleaved => {
+            by_most_overdue(&mut exercises);
+
+            // sources in order of their most overdue exercise
+            let mut sources: Vec<Vec<Exercise>> = vec![];
+            for exercise in exercises {
+                match sources.iter_mut().find(|s| s[0].source == exercise.source) {
+                    Some(source) => source.push(exercise),
+                    None => sources.push(vec![exercise]),
+                }
+            }
+
+            let exercise_cnt = sources.iter().map(Vec::len).sum();
+            let mut interleaved = vec![];
+            let mut round = 0;
+            while interleaved.len() < exercise_cnt {
+                for source in sources.iter() {
+                    if let Some(exercise) = source.get(round) {
+                        interleaved.push(exercise.clone());
+                    }
+                }
+                round += 1;
+            }
+
+            return interleaved;
+        }
+    }
+
+    exercises
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn due_exercises() -> Vec<Exercise> {
+        let today = NaiveDate::from_ymd(2019, 6, 10);
+
+        // (id, days overdue, interval, source)
+        let mut exercises = [
+            (1, 1, 8, "b"),
+            (2, 5, 2, "a"),
+            (3, 0, 1, "a"),
+            (4, 3, 16, "c"),
+            (5, 2, 4, "a"),
+        ]
+        .iter()
+        .map(|(id, overdue, interval, source)| {
+            let mut exercise = Exercise::new(&format!("foo {}", id), source, "baz");
+            exercise.id = Some(*id);
+            exercise.due_at = today - Duration::days(*overdue);
+            exercise.update_interval = *interval;
+            exercise
+        })
+        .collect::<Vec<_>>();
+
+        exercises.sort_by(|a, b| b.due_at.cmp(&a.due_at).then(b.id.cmp(&a.id)));
+        exercises
+    }
+
+    fn ordered_ids(order: ReviewOrder) -> Vec<i32> {
+        let mut rng = StdRng::seed_from_u64(1);
+
+        order_exercises(due_exercises(), order, &mut rng)
+            .iter()
+            .map(|exercise| exercise.id.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_review_orders() {
+        assert_eq!(ordered_ids(ReviewOrder::DueDate), vec![3, 1, 5, 4, 2]);
+        assert_eq!(ordered_ids(ReviewOrder::MostOverdue), vec![2, 4, 5, 1, 3]);
+        assert_eq!(
+            ordered_ids(ReviewOrder::ShortestInterval),
+            vec![3, 2, 5, 1, 4]
+        );
+        assert_eq!(ordered_ids(ReviewOrder::BySource), vec![2, 5, 3, 1, 4]);
+        assert_eq!(ordered_ids(ReviewOrder::Interleaved), vec![2, 4, 1, 5, 3]);
+
+        let mut random = ordered_ids(ReviewOrder::Random);
+        random.sort();
+        assert_eq!(random, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_review_order_names() {
+        for order in &ReviewOrder::ALL {
+            assert_eq!(order.as_str().parse::<ReviewOrder>().unwrap(), *order);
+        }
+
+        assert!("alphabetical".parse::<ReviewOrder>().is_err());
+    }
+}
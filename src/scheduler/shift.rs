@@ -0,0 +1,118 @@
+use chrono::{Duration, NaiveDate};
+
+use crate::Exercise;
+
+// for coming back from a break. every due date moves days later, then
+// whatever is due by today is spread evenly over the next spread_days days,
+// most overdue first, so the backlog doesn't all land on the first day back.
+// intervals are left alone.
+//
+// only the exercises whose due date changed are returned.
+pub fn shift_due_dates(
+    exercises: &[Exercise],
+    days: i32,
+    spread_days: i32,
+    today: NaiveDate,
+) -> Vec<Exercise> {
+    let mut shifted = exercises
+        .iter()
+        .map(|exercise| {
+            let mut exercise = exercise.clone();
+            exercise.due_at += Duration::days(i64::from(days));
+            exercise
+        })
+        .collect::<Vec<_>>();
+
+    let mut backlog = shifted
+        .iter_mut()
+        .filter(|exercise| exercise.due_at <= today)
+        .collect::<Vec<_>>();
+    backlog.sort_by(|a, b| a.due_at.cmp(&b.due_at).then(a.id.cmp(&b.id)));
+
+    let backlog_cnt = backlog.len();
+
+    if spread_days > 1 {
+        for (i, exercise) in backlog.into_iter().enumerate() {
+            // i * spread_days / backlog_cnt rounds down, so earlier days get
+            // any extra
+            let day = i * spread_days as usize / backlog_cnt;
+            exercise.due_at = today + Duration::days(day as i64);
+        }
+    }
+
+    shifted
+        .into_iter()
+        .zip(exercises)
+        .filter(|(shifted, exercise)| shifted.due_at != exercise.due_at)
+        .map(|(shifted, _)| shifted)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exercise_due(id: i32, due_at: NaiveDate) -> Exercise {
+        let mut exercise = Exercise::new(&format!("foo {}", id), "bar", "baz");
+        exercise.id = Some(id);
+        exercise.due_at = due_at;
+        exercise.update_interval = 4;
+        exercise
+    }
+
+    fn due_dates(exercises: &[Exercise]) -> Vec<(i32, NaiveDate)> {
+        exercises
+            .iter()
+            .map(|exercise| (exercise.id.unwrap(), exercise.due_at))
+            .collect()
+    }
+
+    #[test]
+    fn test_shift_due_dates() {
+        let today = NaiveDate::from_ymd(2019, 6, 10);
+        let day = |n| today + Duration::days(n);
+
+        let exercises = [
+            exercise_due(1, day(-7)),
+            exercise_due(2, day(-1)),
+            exercise_due(3, day(3)),
+        ];
+
+        assert!(shift_due_dates(&exercises, 0, 0, today).is_empty());
+
+        let shifted = shift_due_dates(&exercises, 7, 0, today);
+        assert_eq!(
+            due_dates(&shifted),
+            vec![(1, day(0)), (2, day(6)), (3, day(10))]
+        );
+        assert!(shifted.iter().all(|exercise| exercise.update_interval == 4));
+
+        // only what's still due gets spread out
+        let shifted = shift_due_dates(&exercises, 2, 3, today);
+        assert_eq!(
+            due_dates(&shifted),
+            vec![(1, day(0)), (2, day(1)), (3, day(5))]
+        );
+    }
+
+    #[test]
+    fn test_spread_backlog() {
+        let today = NaiveDate::from_ymd(2019, 6, 10);
+
+        let exercises = (1..=10)
+            .map(|id| exercise_due(id, today - Duration::days(i64::from(20 - id))))
+            .collect::<Vec<_>>();
+
+        let shifted = shift_due_dates(&exercises, 0, 3, today);
+
+        // the most overdue come first, and the first day gets the extra one
+        let days = shifted
+            .iter()
+            .map(|exercise| (exercise.due_at - today).num_days())
+            .collect::<Vec<_>>();
+        assert_eq!(days, vec![0, 0, 0, 0, 1, 1, 1, 2, 2, 2]);
+
+        // spreading over a single day doesn't change anything
+        assert!(shift_due_dates(&exercises, 0, 1, today).is_empty());
+    }
+}
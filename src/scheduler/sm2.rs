@@ -0,0 +1,143 @@
+use chrono::NaiveDate;
+
+use crate::scheduler::{Scheduler, SchedulingConfig, SchedulingState, ONE_DAY};
+use crate::{Grade, Review};
+
+/* SM-2 starts at 2.5, but 2.0 keeps the familiar doubling for exercises that keep getting graded Good */
+pub const INITIAL_EASINESS_FACTOR: f64 = 2.0;
+pub const MIN_EASINESS_FACTOR: f64 = 1.3;
+
+// SM-2: https://www.supermemo.com/en/archives1990-2015/english/ol/sm2
+//
+// the easiness factor moves with every grade and is what the interval gets
+// multiplied by on a successful review.
+#[derive(Default)]
+pub struct Sm2Scheduler {
+    config: SchedulingConfig,
+}
+
+impl Sm2Scheduler {
+    pub fn new(config: SchedulingConfig) -> Sm2Scheduler {
+        Sm2Scheduler { config }
+    }
+}
+
+impl Scheduler for Sm2Scheduler {
+    fn next_state(
+        &self,
+        state: SchedulingState,
+        grade: Grade,
+        _history: &[Review],
+        today: NaiveDate,
+    ) -> SchedulingState {
+        let q = f64::from(5 - grade.quality());
+        let state = SchedulingState {
+            easiness_factor: f64::max(
+                MIN_EASINESS_FACTOR,
+                state.easiness_factor + (0.1 - q * (0.08 + q * 0.02)),
+            ),
+            ..state
+        };
+
+        if !grade.is_correct() {
+            return state.lapsed(&self.config, today);
+        }
+
+        if let Some(state) = state.relearned(&self.config, today) {
+            return state;
+        }
+
+        // a lapse may have left an interval to carry on from
+        let update_interval = match state.update_interval {
+            0 => self.config.first_interval,
+            _ => {
+                let next_interval =
+                    (f64::from(state.update_interval) * state.easiness_factor).round() as i32;
+                // always move forward at least a day, even with a low easiness factor
+                std::cmp::max(state.update_interval + ONE_DAY, next_interval)
+            }
+        };
+
+        state.succeeded(&self.config, update_interval, today)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{todays_date, Exercise};
+    use chrono::Duration;
+
+    fn review(exercise: &mut Exercise, grade: Grade) {
+        let state = Sm2Scheduler::default().next_state(
+            exercise.scheduling_state(),
+            grade,
+            &[],
+            todays_date(),
+        );
+        exercise.set_scheduling_state(state);
+    }
+
+    fn assert_easiness_factor(exercise: &Exercise, expected: f64) {
+        assert!(
+            (exercise.easiness_factor - expected).abs() < 1e-9,
+            "expected easiness factor {}, got {}",
+            expected,
+            exercise.easiness_factor
+        );
+    }
+
+    #[test]
+    fn test_exercise_update_interval_easiness_factor() {
+        let today = todays_date();
+
+        let mut exercise = Exercise::new("", "", "");
+        assert_easiness_factor(&exercise, INITIAL_EASINESS_FACTOR);
+
+        review(&mut exercise, Grade::Hard);
+        assert_eq!(exercise.update_interval, 1);
+        assert_eq!(exercise.consecutive_successful_reviews, 1);
+        assert_easiness_factor(&exercise, 1.86);
+
+        // good leaves the easiness factor alone
+        review(&mut exercise, Grade::Good);
+        assert_eq!(exercise.update_interval, 2);
+        review(&mut exercise, Grade::Good);
+        assert_eq!(exercise.update_interval, 4);
+        assert_easiness_factor(&exercise, 1.86);
+
+        review(&mut exercise, Grade::Hard);
+        assert_easiness_factor(&exercise, 1.72);
+        assert_eq!(exercise.update_interval, 7);
+        assert_eq!(exercise.due_at, today + Duration::days(7));
+        assert_eq!(exercise.consecutive_successful_reviews, 4);
+
+        review(&mut exercise, Grade::Easy);
+        assert_easiness_factor(&exercise, 1.82);
+        assert_eq!(exercise.update_interval, 13);
+        assert_eq!(exercise.due_at, today + Duration::days(13));
+
+        for _ in 0..10 {
+            review(&mut exercise, Grade::Easy);
+        }
+        assert_eq!(exercise.update_interval, 90);
+
+        // failing resets the interval and makes the exercise harder
+        let mut exercise = Exercise::new("", "", "");
+        review(&mut exercise, Grade::Again);
+        assert_eq!(exercise.update_interval, 0);
+        assert_eq!(exercise.due_at, today);
+        assert_easiness_factor(&exercise, 1.46);
+
+        review(&mut exercise, Grade::Again);
+        assert_easiness_factor(&exercise, MIN_EASINESS_FACTOR);
+
+        // even at the minimum easiness factor, the interval keeps growing
+        review(&mut exercise, Grade::Good);
+        assert_eq!(exercise.update_interval, 1);
+        review(&mut exercise, Grade::Good);
+        assert_eq!(exercise.update_interval, 2);
+        review(&mut exercise, Grade::Good);
+        assert_eq!(exercise.update_interval, 3);
+    }
+}
@@ -0,0 +1,530 @@
+use chrono::NaiveDate;
+use std::cell::RefCell;
+use std::error::Error;
+
+use crate::migrations::{latest_version, Migration};
+use crate::store::Store;
+use crate::{make_error, Exercise, Prerequisite, Review};
+
+#[derive(Default)]
+struct MemoryData {
+    schema_version: i32,
+    next_id: i32,
+    exercises: Vec<Exercise>,
+    next_review_id: i32,
+    reviews: Vec<Review>,
+}
+
+// keeps everything in a Vec for the lifetime of the store. nothing is ever
+// written anywhere, which makes it handy for tests and dry runs.
+#[derive(Default)]
+pub struct MemoryStore {
+    data: RefCell<MemoryData>,
+}
+
+fn by_due_date_desc(exercises: &mut [Exercise]) {
+    exercises.sort_by(|a, b| b.due_at.cmp(&a.due_at).then(b.id.cmp(&a.id)));
+}
+
+// checks the whole batch up front, so a failure doesn't leave half of it saved
+fn insert_in(
+    exercises: &mut Vec<Exercise>,
+    next_id: i32,
+    new_exercises: &[Exercise],
+) -> Result<(), Box<dyn Error>> {
+    for (i, exercise) in new_exercises.iter().enumerate() {
+        if exercise.id.is_some() {
+            return Err(make_error("Cannot insert, has PK".to_string()));
+        }
+
+        let duplicate = exercises
+            .iter()
+            .chain(new_exercises[..i].iter())
+            .any(|e| e.description == exercise.description);
+
+        if duplicate {
+            return Err(duplicate_description_error(&exercise.description));
+        }
+    }
+
+    let mut saved_exercises = new_exercises.to_vec();
+    for (i, saved_exercise) in saved_exercises.iter_mut().enumerate() {
+        saved_exercise.id = Some(next_id + i as i32);
+        // the SQL stores hand them back sorted
+        saved_exercise.tags.sort();
+    }
+
+    // prerequisites can be anywhere in the batch, so they can only be
+    // checked once every exercise has an ID
+    let all_exercises = exercises
+        .iter()
+        .chain(saved_exercises.iter())
+        .cloned()
+        .collect::<Vec<_>>();
+    for saved_exercise in saved_exercises.iter_mut() {
+        saved_exercise.prerequisites =
+            resolve_prerequisites(&all_exercises, &saved_exercise.prerequisites)?;
+    }
+
+    exercises.extend(saved_exercises);
+
+    Ok(())
+}
+
+fn update_in(exercises: &mut [Exercise], exercise: &Exercise) -> Result<u64, Box<dyn Error>> {
+    if exercises
+        .iter()
+        .any(|e| e.id != exercise.id && e.description == exercise.description)
+    {
+        return Err(duplicate_description_error(&exercise.description));
+    }
+
+    let prerequisites = resolve_prerequisites(exercises, &exercise.prerequisites)?;
+
+    match exercises.iter_mut().find(|e| e.id == exercise.id) {
+        Some(saved_exercise) => {
+            *saved_exercise = exercise.clone();
+            saved_exercise.prerequisites = prerequisites;
+            saved_exercise.tags.sort();
+            Ok(1)
+        }
+        None => Ok(0),
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    // a migrated store holding copies of exercises that were already saved
+    // somewhere else, e.g. to preview changes to them
+    pub fn with_exercises(exercises: &[Exercise]) -> MemoryStore {
+        let store = MemoryStore::new();
+
+        {
+            let mut data = store.data.borrow_mut();
+            data.schema_version = latest_version();
+            data.exercises = exercises.to_vec();
+            data.next_id = exercises.iter().filter_map(|e| e.id).max().unwrap_or(0) + 1;
+            data.next_review_id = 1;
+        }
+
+        store
+    }
+
+    fn count_by_due_date<F>(&self, predicate: F) -> Vec<(NaiveDate, i32)>
+    where
+        F: Fn(&Exercise) -> bool,
+    {
+        let mut counts: Vec<(NaiveDate, i32)> = vec![];
+
+        let mut due_dates = self
+            .data
+            .borrow()
+            .exercises
+            .iter()
+            .filter(|e| !e.suspended && predicate(e))
+            .map(|e| e.due_at)
+            .collect::<Vec<_>>();
+        due_dates.sort();
+
+        for due_at in due_dates {
+            match counts.last_mut() {
+                Some((date, count)) if *date == due_at => *count += 1,
+                _ => counts.push((due_at, 1)),
+            }
+        }
+
+        counts
+    }
+
+    fn filter_exercises<F>(&self, predicate: F) -> Vec<Exercise>
+    where
+        F: Fn(&Exercise) -> bool,
+    {
+        let mut exercises = self
+            .data
+            .borrow()
+            .exercises
+            .iter()
+            .filter(|e| predicate(e))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        by_due_date_desc(&mut exercises);
+
+        exercises
+    }
+
+    fn check_schema_loaded(&self) -> Result<(), Box<dyn Error>> {
+        if self.data.borrow().schema_version > 0 {
+            Ok(())
+        } else {
+            Err(make_error("Schema is not loaded".to_string()))
+        }
+    }
+}
+
+fn delete_in(exercises: &mut Vec<Exercise>, pk: i32) -> Result<(), Box<dyn Error>> {
+    let exercise_cnt = exercises.len();
+    exercises.retain(|e| e.id != Some(pk));
+
+    let rows_affected = exercise_cnt - exercises.len();
+    if rows_affected != 1 {
+        return Err(make_error(format!(
+            "Expected 1 row to be affected, got {} instead",
+            rows_affected
+        )));
+    }
+
+    // the SQL stores cascade this too
+    for exercise in exercises.iter_mut() {
+        exercise
+            .prerequisites
+            .retain(|prerequisite| *prerequisite != Prerequisite::Id(pk));
+    }
+
+    Ok(())
+}
+
+fn duplicate_description_error(description: &str) -> Box<dyn Error> {
+    make_error(format!(
+        "An exercise with description \"{}\" already exists",
+        description
+    ))
+}
+
+// what the SQL stores do with a subquery: points every prerequisite at the ID
+// of the exercise it refers to
+fn resolve_prerequisites(
+    exercises: &[Exercise],
+    prerequisites: &[Prerequisite],
+) -> Result<Vec<Prerequisite>, Box<dyn Error>> {
+    let mut ids = vec![];
+
+    for prerequisite in prerequisites {
+        let found = exercises.iter().find(|e| match prerequisite {
+            Prerequisite::Id(id) => e.id == Some(*id),
+            Prerequisite::Description(description) => e.description == *description,
+        });
+
+        match found.and_then(|e| e.id) {
+            Some(id) => ids.push(id),
+            None => {
+                return Err(make_error(format!(
+                    "Prerequisite {} does not exist",
+                    prerequisite
+                )))
+            }
+        }
+    }
+
+    ids.sort();
+    ids.dedup();
+
+    Ok(ids.into_iter().map(Prerequisite::Id).collect())
+}
+
+impl Store for MemoryStore {
+    fn schema_version(&self) -> Result<i32, Box<dyn Error>> {
+        Ok(self.data.borrow().schema_version)
+    }
+
+    // the in-memory layout is always the latest one, so there is nothing to
+    // do besides keeping track of the version
+    fn apply_migration(&self, migration: &Migration) -> Result<(), Box<dyn Error>> {
+        let mut data = self.data.borrow_mut();
+
+        if migration.version <= data.schema_version {
+            return Err(make_error(format!(
+                "Migration {} has already been applied",
+                migration.version
+            )));
+        }
+
+        if data.schema_version == 0 {
+            data.next_id = 1;
+            data.next_review_id = 1;
+        }
+
+        data.schema_version = migration.version;
+
+        Ok(())
+    }
+
+    fn drop_schema(&self) -> Result<(), Box<dyn Error>> {
+        *self.data.borrow_mut() = MemoryData::default();
+
+        Ok(())
+    }
+
+    fn get_by_pk(&self, pk: i32) -> Option<Exercise> {
+        self.filter_exercises(|e| e.id == Some(pk))
+            .into_iter()
+            .next()
+    }
+
+    fn get_due(&self, today: NaiveDate) -> Vec<Exercise> {
+        self.filter_exercises(|e| e.due_at <= today && !e.suspended)
+    }
+
+    fn get_all_by_due_date_desc(&self) -> Vec<Exercise> {
+        self.filter_exercises(|_| true)
+    }
+
+    fn grep(&self, query_string: &str) -> Vec<Exercise> {
+        self.filter_exercises(|e| {
+            e.description.contains(query_string)
+                || e.source.contains(query_string)
+                || e.reference_answer.contains(query_string)
+                || e.id.unwrap_or(-1).to_string().contains(query_string)
+        })
+    }
+
+    fn get_siblings(&self, sibling_group: &str) -> Vec<Exercise> {
+        self.filter_exercises(|e| e.sibling_group.as_deref() == Some(sibling_group))
+    }
+
+    fn save(&self, exercises: &[Exercise]) -> Result<(), Box<dyn Error>> {
+        self.check_schema_loaded()?;
+
+        let mut data = self.data.borrow_mut();
+
+        let next_id = data.next_id;
+        insert_in(&mut data.exercises, next_id, exercises)?;
+        data.next_id += exercises.len() as i32;
+
+        Ok(())
+    }
+
+    fn update(&self, exercise: &Exercise) -> Result<u64, Box<dyn Error>> {
+        if exercise.id.is_none() {
+            return Err(make_error("Cannot insert, has no PK".to_string()));
+        }
+
+        self.check_schema_loaded()?;
+
+        update_in(&mut self.data.borrow_mut().exercises, exercise)
+    }
+
+    fn update_all(&self, exercises: &[Exercise]) -> Result<(), Box<dyn Error>> {
+        self.check_schema_loaded()?;
+
+        let mut data = self.data.borrow_mut();
+
+        // work on a copy and only swap it in once every update has gone
+        // through, so a failure doesn't leave half of the batch saved
+        let mut updated_exercises = data.exercises.clone();
+
+        for exercise in exercises {
+            if exercise.id.is_none() || update_in(&mut updated_exercises, exercise)? == 0 {
+                return Err(make_error(format!(
+                    "Exercise {} does not exist",
+                    exercise.id.unwrap_or(-1)
+                )));
+            }
+        }
+
+        data.exercises = updated_exercises;
+
+        Ok(())
+    }
+
+    fn delete(&self, pk: i32) -> Result<(), Box<dyn Error>> {
+        let mut data = self.data.borrow_mut();
+
+        delete_in(&mut data.exercises, pk)?;
+        // the SQL stores cascade these
+        data.reviews.retain(|r| r.exercise_id != pk);
+
+        Ok(())
+    }
+
+    fn replace_siblings(
+        &self,
+        updated: &[Exercise],
+        new: &[Exercise],
+        deleted: &[i32],
+    ) -> Result<(), Box<dyn Error>> {
+        self.check_schema_loaded()?;
+
+        let mut data = self.data.borrow_mut();
+
+        // like update_all, only swapped in once everything has gone through
+        let mut exercises = data.exercises.clone();
+
+        for pk in deleted {
+            delete_in(&mut exercises, *pk)?;
+        }
+        for exercise in updated {
+            update_in(&mut exercises, exercise)?;
+        }
+        let next_id = data.next_id;
+        insert_in(&mut exercises, next_id, new)?;
+
+        data.exercises = exercises;
+        data.next_id += new.len() as i32;
+        data.reviews.retain(|r| !deleted.contains(&r.exercise_id));
+
+        Ok(())
+    }
+
+    fn schedule(&self) -> Vec<(NaiveDate, i32)> {
+        self.count_by_due_date(|_| true)
+    }
+
+    fn schedule_between(
+        &self,
+        first: NaiveDate,
+        last: NaiveDate,
+        excluding: i32,
+    ) -> Vec<(NaiveDate, i32)> {
+        self.count_by_due_date(|e| e.due_at >= first && e.due_at <= last && e.id != Some(excluding))
+    }
+
+    fn stats(&self) -> Option<(i32, NaiveDate)> {
+        let data = self.data.borrow();
+
+        data.exercises
+            .iter()
+            .map(|e| e.created_at)
+            .min()
+            .map(|earliest| (data.exercises.len() as i32, earliest))
+    }
+
+    fn count_due(&self, today: NaiveDate) -> Option<i32> {
+        Some(self.get_due(today).len() as i32)
+    }
+
+    fn save_review(&self, exercise: &Exercise, review: &Review) -> Result<i32, Box<dyn Error>> {
+        if self.update(exercise)? != 1 {
+            return Err(make_error(format!(
+                "Exercise {} does not exist",
+                review.exercise_id
+            )));
+        }
+
+        let mut data = self.data.borrow_mut();
+
+        let review_id = data.next_review_id;
+        let mut saved_review = review.clone();
+        saved_review.id = Some(review_id);
+        data.next_review_id += 1;
+        data.reviews.push(saved_review);
+
+        Ok(review_id)
+    }
+
+    fn undo_review(&self, exercise: &Exercise, review_id: i32) -> Result<(), Box<dyn Error>> {
+        let exercise_id = exercise.id.unwrap_or(-1);
+
+        let review_exists = self
+            .data
+            .borrow()
+            .reviews
+            .iter()
+            .any(|r| r.id == Some(review_id) && r.exercise_id == exercise_id);
+
+        // check everything before changing anything, like a rollback would
+        if !review_exists || self.update(exercise)? != 1 {
+            return Err(make_error(format!(
+                "Review {} of exercise {} does not exist",
+                review_id, exercise_id
+            )));
+        }
+
+        self.data
+            .borrow_mut()
+            .reviews
+            .retain(|r| r.id != Some(review_id));
+
+        Ok(())
+    }
+
+    fn get_reviews(&self, exercise_id: i32) -> Vec<Review> {
+        let mut reviews = self
+            .data
+            .borrow()
+            .reviews
+            .iter()
+            .filter(|r| r.exercise_id == exercise_id)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        reviews.sort_by(|a, b| a.reviewed_at.cmp(&b.reviewed_at).then(a.id.cmp(&b.id)));
+
+        reviews
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_update_all_transaction_handling() {
+        let store = MemoryStore::with_exercises(&[]);
+
+        store
+            .save(&[
+                Exercise::new("foo", "bar", "baz"),
+                Exercise::new("foo 2", "bar 2", "baz 2"),
+            ])
+            .unwrap();
+
+        let mut exercise = store.get_by_pk(1).unwrap();
+        exercise.source = "changed".to_string();
+        let mut missing_exercise = store.get_by_pk(2).unwrap();
+        missing_exercise.id = Some(1234);
+        missing_exercise.description = "foo 3".to_string();
+        assert_eq!(
+            format!(
+                "{}",
+                store
+                    .update_all(&[exercise.clone(), missing_exercise])
+                    .unwrap_err()
+            ),
+            "Exercise 1234 does not exist"
+        );
+
+        // a later update failing the checks undoes the earlier ones too
+        let mut duplicate_exercise = store.get_by_pk(2).unwrap();
+        duplicate_exercise.description = "foo".to_string();
+        assert!(store
+            .update_all(&[exercise.clone(), duplicate_exercise])
+            .is_err());
+
+        let mut exercise_2 = store.get_by_pk(2).unwrap();
+        exercise_2.prerequisites = vec![Prerequisite::Description("nope".to_string())];
+        assert!(store.update_all(&[exercise.clone(), exercise_2]).is_err());
+
+        assert_eq!(store.get_by_pk(1).unwrap().source, "bar");
+        assert!(store.get_by_pk(2).unwrap().prerequisites.is_empty());
+
+        store.update_all(&[exercise]).unwrap();
+        assert_eq!(store.get_by_pk(1).unwrap().source, "changed");
+    }
+
+    #[test]
+    fn test_memory_replace_siblings_transaction_handling() {
+        let store = MemoryStore::with_exercises(&[]);
+
+        store
+            .save(&[
+                Exercise::new("foo", "bar", "baz"),
+                Exercise::new("foo 2", "bar", "baz"),
+            ])
+            .unwrap();
+
+        let mut exercise = store.get_by_pk(1).unwrap();
+        exercise.source = "quux".to_string();
+
+        // nothing is changed if a deleted sibling is missing
+        assert!(store
+            .replace_siblings(&[exercise], &[], &[2, 1234])
+            .is_err());
+        assert_eq!(store.get_by_pk(1).unwrap().source, "bar");
+        assert!(store.get_by_pk(2).is_some());
+    }
+}
@@ -0,0 +1,375 @@
+use chrono::NaiveDate;
+use std::error::Error;
+
+use crate::migrations::Migration;
+use crate::{Exercise, Review};
+
+pub mod memory;
+pub mod postgres;
+pub mod sqlite;
+
+pub use self::memory::MemoryStore;
+pub use self::postgres::PostgresStore;
+pub use self::sqlite::SqliteStore;
+
+// everything ExerciseService needs from a storage backend. implementations own
+// their connection and are free to store exercises however they like, as long
+// as they behave the same way from the outside: saving a batch is all or
+// nothing, descriptions are unique, and lists come back ordered by due date
+// descending, then ID descending.
+pub trait Store {
+    // 0 if no migrations have been applied yet
+    fn schema_version(&self) -> Result<i32, Box<dyn Error>>;
+
+    // runs the migration and records its version, all in one transaction
+    fn apply_migration(&self, migration: &Migration) -> Result<(), Box<dyn Error>>;
+
+    fn drop_schema(&self) -> Result<(), Box<dyn Error>>;
+
+    fn get_by_pk(&self, pk: i32) -> Option<Exercise>;
+
+    fn get_due(&self, today: NaiveDate) -> Vec<Exercise>;
+
+    fn get_all_by_due_date_desc(&self) -> Vec<Exercise>;
+
+    fn grep(&self, query_string: &str) -> Vec<Exercise>;
+
+    // every exercise in the sibling group, by due date like
+    // get_all_by_due_date_desc
+    fn get_siblings(&self, sibling_group: &str) -> Vec<Exercise>;
+
+    fn save(&self, exercises: &[Exercise]) -> Result<(), Box<dyn Error>>;
+
+    fn update(&self, exercise: &Exercise) -> Result<u64, Box<dyn Error>>;
+
+    // all or nothing, like save
+    fn update_all(&self, exercises: &[Exercise]) -> Result<(), Box<dyn Error>>;
+
+    fn delete(&self, pk: i32) -> Result<(), Box<dyn Error>>;
+
+    // saves an edit to a group of siblings: deletes some, then updates and
+    // inserts the rest. all or nothing, like save.
+    fn replace_siblings(
+        &self,
+        updated: &[Exercise],
+        new: &[Exercise],
+        deleted: &[i32],
+    ) -> Result<(), Box<dyn Error>>;
+
+    fn schedule(&self) -> Vec<(NaiveDate, i32)>;
+
+    // the part of schedule from first to last, inclusive, not counting the
+    // exercise with the given ID
+    fn schedule_between(
+        &self,
+        first: NaiveDate,
+        last: NaiveDate,
+        excluding: i32,
+    ) -> Vec<(NaiveDate, i32)>;
+
+    fn stats(&self) -> Option<(i32, NaiveDate)>;
+
+    fn count_due(&self, today: NaiveDate) -> Option<i32>;
+
+    // saves the exercise's new scheduling state together with the review that
+    // produced it, so one is never saved without the other. returns the ID of
+    // the new review.
+    fn save_review(&self, exercise: &Exercise, review: &Review) -> Result<i32, Box<dyn Error>>;
+
+    // the opposite of save_review: puts back the exercise as it was before the
+    // review and deletes the review, again all or nothing
+    fn undo_review(&self, exercise: &Exercise, review_id: i32) -> Result<(), Box<dyn Error>>;
+
+    // oldest first
+    fn get_reviews(&self, exercise_id: i32) -> Vec<Review>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations::MIGRATIONS;
+    use crate::tests::{exercise_with, ids};
+    use crate::{ExerciseService, Grade, Outcome, Prerequisite, TEST_DATABASE};
+    use chrono::{DateTime, Utc};
+
+    // every store has to behave the same way from the outside, so these run
+    // against each of them, starting from an empty, migrated store every time
+    const STORE_CHECKS: &[fn(&dyn Store)] = &[
+        check_reviews,
+        check_tags,
+        check_decks,
+        check_prerequisites,
+        check_variables,
+        check_siblings,
+    ];
+
+    fn migrated<S: Store>(store: S) -> S {
+        for migration in MIGRATIONS {
+            store.apply_migration(migration).unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn test_memory_store() {
+        for check in STORE_CHECKS {
+            check(&migrated(MemoryStore::new()));
+        }
+    }
+
+    #[test]
+    fn test_sqlite_store() {
+        for check in STORE_CHECKS {
+            check(&migrated(SqliteStore::open_in_memory().unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_test_database_store() {
+        let _lock = TEST_DATABASE.lock().unwrap_or_else(|e| e.into_inner());
+
+        for check in STORE_CHECKS {
+            check(ExerciseService::new_test().store());
+        }
+    }
+
+    fn check_reviews(store: &dyn Store) {
+        store.save(&[Exercise::new("foo", "bar", "baz")]).unwrap();
+
+        let mut exercise = store.get_by_pk(1).unwrap();
+        exercise.update_interval = 1;
+
+        // whole seconds, since postgres only keeps microseconds
+        let reviewed_at = "2019-06-01T10:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let review = Review {
+            id: None,
+            exercise_id: 1,
+            reviewed_at,
+            outcome: Outcome::Graded(Grade::Good),
+            time_spent_seconds: 12,
+            interval_before: 0,
+            interval_after: 1,
+            lapse: false,
+            relearning_step: None,
+        };
+
+        assert_eq!(store.save_review(&exercise, &review).unwrap(), 1);
+
+        assert_eq!(store.get_by_pk(1).unwrap().update_interval, 1);
+
+        let reviews = store.get_reviews(1);
+        assert_eq!(reviews.len(), 1);
+        assert_eq!(reviews[0].id, Some(1));
+        assert_eq!(reviews[0].reviewed_at, reviewed_at);
+        assert_eq!(reviews[0].outcome, Outcome::Graded(Grade::Good));
+        assert_eq!(reviews[0].time_spent_seconds, 12);
+
+        // a review of a missing exercise saves nothing, not even the exercise
+        let mut missing_exercise = exercise.clone();
+        missing_exercise.id = Some(1234);
+        missing_exercise.update_interval = 2;
+        let mut missing_review = review.clone();
+        missing_review.exercise_id = 1234;
+        assert!(store
+            .save_review(&missing_exercise, &missing_review)
+            .is_err());
+
+        // undoing puts the exercise back and deletes the review
+        exercise.update_interval = 2;
+        let review_id = store.save_review(&exercise, &review).unwrap();
+
+        exercise.update_interval = 1;
+        store.undo_review(&exercise, review_id).unwrap();
+        assert_eq!(store.get_by_pk(1).unwrap().update_interval, 1);
+        assert_eq!(store.get_reviews(1).len(), 1);
+
+        exercise.update_interval = 5;
+        assert!(store.undo_review(&exercise, review_id).is_err());
+        assert_eq!(store.get_by_pk(1).unwrap().update_interval, 1);
+
+        // history goes away with the exercise
+        store.delete(1).unwrap();
+        assert!(store.get_reviews(1).is_empty());
+    }
+
+    fn check_tags(store: &dyn Store) {
+        store
+            .save(&[
+                exercise_with("foo", |exercise| {
+                    exercise.tags = vec!["b".to_string(), "a/c".to_string()]
+                }),
+                Exercise::new("foo 2", "bar", "baz"),
+            ])
+            .unwrap();
+
+        // sorted on the way out, whatever order they went in
+        let mut exercise = store.get_by_pk(1).unwrap();
+        assert_eq!(exercise.tags, vec!["a/c", "b"]);
+        assert!(store.get_by_pk(2).unwrap().tags.is_empty());
+
+        // updating an exercise replaces them
+        exercise.tags = vec!["e".to_string(), "d".to_string()];
+        store.update(&exercise).unwrap();
+        assert_eq!(store.get_by_pk(1).unwrap().tags, vec!["d", "e"]);
+    }
+
+    fn check_decks(store: &dyn Store) {
+        store
+            .save(&[
+                exercise_with("foo", |exercise| exercise.deck = Some("math".to_string())),
+                Exercise::new("foo 2", "bar", "baz"),
+            ])
+            .unwrap();
+
+        let mut exercise = store.get_by_pk(1).unwrap();
+        assert_eq!(exercise.deck.as_deref(), Some("math"));
+        assert_eq!(store.get_by_pk(2).unwrap().deck, None);
+
+        // moving an exercise out of its deck
+        exercise.deck = None;
+        store.update(&exercise).unwrap();
+        assert_eq!(store.get_by_pk(1).unwrap().deck, None);
+    }
+
+    fn check_prerequisites(store: &dyn Store) {
+        store
+            .save(&[
+                Exercise::new("foo", "bar", "baz"),
+                exercise_with("foo 2", |exercise| {
+                    exercise.prerequisites = vec![Prerequisite::Description("foo".to_string())]
+                }),
+            ])
+            .unwrap();
+
+        let mut exercise = store.get_by_pk(2).unwrap();
+        assert_eq!(exercise.prerequisites, vec![Prerequisite::Id(1)]);
+
+        store
+            .save(&[exercise_with("foo 3", |exercise| {
+                exercise.prerequisites = vec![Prerequisite::Id(1), Prerequisite::Id(2)]
+            })])
+            .unwrap();
+        assert_eq!(
+            store.get_by_pk(3).unwrap().prerequisites,
+            vec![Prerequisite::Id(1), Prerequisite::Id(2)]
+        );
+
+        exercise.prerequisites = vec![Prerequisite::Id(3)];
+        store.update(&exercise).unwrap();
+        assert_eq!(
+            store.get_by_pk(2).unwrap().prerequisites,
+            vec![Prerequisite::Id(3)]
+        );
+
+        // nothing is saved if a prerequisite is missing
+        let missing = |prerequisite| {
+            exercise_with("foo 4", |exercise| {
+                exercise.prerequisites = vec![prerequisite]
+            })
+        };
+        assert_eq!(
+            format!(
+                "{}",
+                store
+                    .save(&[missing(Prerequisite::Description("nope".to_string()))])
+                    .unwrap_err()
+            ),
+            "Prerequisite \"nope\" does not exist"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                store.save(&[missing(Prerequisite::Id(42))]).unwrap_err()
+            ),
+            "Prerequisite 42 does not exist"
+        );
+        assert!(store.get_by_pk(4).is_none());
+
+        // deleting an exercise unlinks it from the exercises that needed it
+        store.delete(1).unwrap();
+        assert_eq!(
+            store.get_by_pk(3).unwrap().prerequisites,
+            vec![Prerequisite::Id(2)]
+        );
+    }
+
+    fn check_variables(store: &dyn Store) {
+        store
+            .save(&[
+                exercise_with("{a} [...]", |exercise| {
+                    exercise.sibling_group = Some("foo".to_string());
+                    exercise.cloze = Some("{a} {{c1::b}}".to_string());
+                    exercise.cloze_number = Some(1);
+                    exercise
+                        .variables
+                        .insert("a".to_string(), "1..5".to_string());
+                    exercise.variables.insert("b".to_string(), "a".to_string());
+                }),
+                Exercise::new("foo 2", "bar", "baz"),
+            ])
+            .unwrap();
+
+        let mut exercise = store.get_by_pk(1).unwrap();
+        assert_eq!(exercise.sibling_group.as_deref(), Some("foo"));
+        assert_eq!(exercise.cloze.as_deref(), Some("{a} {{c1::b}}"));
+        assert_eq!(exercise.cloze_number, Some(1));
+        assert_eq!(exercise.variables["a"], "1..5");
+        assert_eq!(exercise.variables["b"], "a");
+
+        let plain_exercise = store.get_by_pk(2).unwrap();
+        assert!(plain_exercise.sibling_group.is_none());
+        assert!(plain_exercise.cloze.is_none());
+        assert!(plain_exercise.cloze_number.is_none());
+        assert!(plain_exercise.variables.is_empty());
+
+        exercise.cloze = None;
+        exercise.cloze_number = None;
+        exercise.variables.remove("b");
+        store.update(&exercise).unwrap();
+
+        let exercise = store.get_by_pk(1).unwrap();
+        assert!(exercise.cloze.is_none());
+        assert!(exercise.cloze_number.is_none());
+        assert_eq!(exercise.variables.len(), 1);
+    }
+
+    fn check_siblings(store: &dyn Store) {
+        let sibling = |description: &str| {
+            exercise_with(description, |exercise| {
+                exercise.sibling_group = Some("a".to_string())
+            })
+        };
+        store
+            .save(&[
+                sibling("foo"),
+                sibling("foo 2"),
+                Exercise::new("foo 3", "bar", "baz"),
+            ])
+            .unwrap();
+
+        assert_eq!(ids(store.get_siblings("a")), vec![2, 1]);
+        assert!(store.get_siblings("b").is_empty());
+
+        let mut exercise = store.get_by_pk(1).unwrap();
+        exercise.source = "quux".to_string();
+
+        // nothing is saved if any part fails
+        assert!(store
+            .replace_siblings(&[exercise.clone()], &[sibling("foo 3")], &[2])
+            .is_err());
+        assert_eq!(store.get_by_pk(1).unwrap().source, "bar");
+        assert!(store.get_by_pk(2).is_some());
+
+        // a deleted sibling's description can be taken by a new one
+        store
+            .replace_siblings(&[exercise], &[sibling("foo 2")], &[2])
+            .unwrap();
+        assert_eq!(store.get_by_pk(1).unwrap().source, "quux");
+        assert!(store.get_by_pk(2).is_none());
+        // postgres doesn't hand back IDs taken by a failed transaction
+        let siblings = store.get_siblings("a");
+        assert_eq!(siblings.len(), 2);
+        assert_eq!(siblings[0].description, "foo 2");
+        assert_eq!(siblings[1].id, Some(1));
+    }
+}
@@ -0,0 +1,569 @@
+use chrono::NaiveDate;
+use postgres::rows::Row;
+use postgres::transaction::Transaction;
+use postgres::types::ToSql;
+use postgres::{Connection, GenericConnection, TlsMode};
+use std::error::Error;
+
+use crate::migrations::Migration;
+use crate::store::Store;
+use crate::template;
+use crate::{make_error, Exercise, Grade, Prerequisite, Review};
+
+pub struct PostgresStore {
+    conn: Connection,
+}
+
+fn exercise_from_row(row: &Row) -> Exercise {
+    Exercise {
+        id: Some(row.get(0)),
+        created_at: row.get(1),
+        due_at: row.get(2),
+        description: row.get(3),
+        source: row.get(4),
+        reference_answer: row.get(5),
+        tags: row.get(15),
+        update_interval: row.get(6),
+        consecutive_successful_reviews: row.get(7),
+        last_grade: row
+            .get::<_, Option<String>>(8)
+            .map(|grade| grade.parse().unwrap()),
+        easiness_factor: row.get(9),
+        relearning_step: row.get(10),
+        lapses: row.get(11),
+        suspended: row.get(12),
+        first_reviewed_at: row.get(13),
+        deck: row.get(14),
+        prerequisites: row
+            .get::<_, Vec<i32>>(16)
+            .into_iter()
+            .map(Prerequisite::Id)
+            .collect(),
+        sibling_group: row.get(17),
+        cloze: row.get(18),
+        cloze_number: row.get(19),
+        variables: template::definitions_from_column(row.get(20)),
+    }
+}
+
+fn review_from_row(row: &Row) -> Review {
+    let outcome: String = row.get(3);
+
+    Review {
+        id: Some(row.get(0)),
+        exercise_id: row.get(1),
+        reviewed_at: row.get(2),
+        outcome: outcome.parse().unwrap(),
+        time_spent_seconds: row.get(4),
+        interval_before: row.get(5),
+        interval_after: row.get(6),
+        lapse: row.get(7),
+        relearning_step: row.get(8),
+    }
+}
+
+fn sql_column_list() -> &'static str {
+    "id, created_at, due_at, description, source, reference_answer, update_interval,
+    consecutive_successful_reviews, last_grade, easiness_factor, relearning_step, lapses,
+    suspended, first_reviewed_at, deck,
+    array(select tag from exercise_tags where exercise_id = exercises.id order by tag),
+    array(select prerequisite_id from exercise_prerequisites where exercise_id = exercises.id
+        order by prerequisite_id),
+    sibling_group, cloze, cloze_number, variables"
+}
+
+impl PostgresStore {
+    pub fn connect(url: &str) -> Result<PostgresStore, Box<dyn Error>> {
+        let conn = Connection::connect(url, TlsMode::None)?;
+
+        Ok(PostgresStore { conn })
+    }
+
+    fn query_exercises(&self, query: &str, params: &[&dyn ToSql]) -> Vec<Exercise> {
+        let mut exercises = vec![];
+
+        for row in &self.conn.query(query, params).unwrap() {
+            exercises.push(exercise_from_row(&row));
+        }
+
+        exercises
+    }
+
+    fn insert_tags(
+        conn: &dyn GenericConnection,
+        exercise_id: i32,
+        tags: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        for tag in tags {
+            conn.execute(
+                "insert into exercise_tags(exercise_id, tag) values($1, $2)",
+                &[&exercise_id, tag],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn insert_prerequisites(
+        conn: &dyn GenericConnection,
+        exercise_id: i32,
+        prerequisites: &[Prerequisite],
+    ) -> Result<(), Box<dyn Error>> {
+        for prerequisite in prerequisites {
+            let rows_affected = match prerequisite {
+                Prerequisite::Id(id) => conn.execute(
+                    "insert into exercise_prerequisites(exercise_id, prerequisite_id)
+                    select $1, id from exercises where id = $2",
+                    &[&exercise_id, id],
+                )?,
+                Prerequisite::Description(description) => conn.execute(
+                    "insert into exercise_prerequisites(exercise_id, prerequisite_id)
+                    select $1, id from exercises where description = $2",
+                    &[&exercise_id, description],
+                )?,
+            };
+
+            if rows_affected != 1 {
+                return Err(make_error(format!(
+                    "Prerequisite {} does not exist",
+                    prerequisite
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn create(tx: &Transaction, exercise: &Exercise) -> Result<i32, Box<dyn Error>> {
+        // exercise was already inserted
+        if exercise.id.is_some() {
+            return Err(make_error("Cannot insert, has PK".to_string()));
+        }
+
+        // we can let postgres insert some defaults
+        let values: &[&dyn ToSql] = &[
+            &exercise.created_at,
+            &exercise.due_at,
+            &exercise.description,
+            &exercise.source,
+            &exercise.reference_answer,
+            &exercise.deck,
+            &exercise.sibling_group,
+            &exercise.cloze,
+            &exercise.cloze_number,
+            &template::definitions_to_column(&exercise.variables),
+        ];
+
+        // the code doesn't really need the generated values when creating, so I don't feel the need to write the code to fill in data
+        // for fields I don't actually need. the ID is the exception, since the tags refer to it.
+        let query = "insert into exercises(created_at, due_at, description, source, reference_answer, deck, sibling_group, cloze, cloze_number, variables) values($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) returning id";
+        let id = tx.query(query, values)?.get(0).get(0);
+
+        PostgresStore::insert_tags(tx, id, &exercise.tags)?;
+
+        Ok(id)
+    }
+
+    fn create_all(tx: &Transaction, exercises: &[Exercise]) -> Result<(), Box<dyn Error>> {
+        let mut ids = vec![];
+        for exercise in exercises {
+            // @Performance we could probably do bulk inserts but for small files it won't matter
+            ids.push(PostgresStore::create(tx, exercise)?);
+        }
+
+        // prerequisites can be anywhere in the batch, so they go in once
+        // every exercise has an ID
+        for (id, exercise) in ids.into_iter().zip(exercises) {
+            PostgresStore::insert_prerequisites(tx, id, &exercise.prerequisites)?;
+        }
+
+        Ok(())
+    }
+
+    fn delete_exercise(conn: &dyn GenericConnection, pk: i32) -> Result<(), Box<dyn Error>> {
+        let rows_affected = conn.execute("delete from exercises where id = $1", &[&pk])?;
+
+        if rows_affected == 1 {
+            Ok(())
+        } else {
+            Err(make_error(format!(
+                "Expected 1 row to be affected, got {} instead",
+                rows_affected
+            )))
+        }
+    }
+
+    fn update_exercise(
+        conn: &dyn GenericConnection,
+        exercise: &Exercise,
+    ) -> Result<u64, Box<dyn Error>> {
+        if exercise.id.is_none() {
+            return Err(make_error("Cannot insert, has no PK".to_string()));
+        }
+
+        let query =
+            "update exercises set created_at = $1, due_at = $2, description = $3, source = $4,
+        reference_answer = $5, update_interval = $6, consecutive_successful_reviews = $7,
+        last_grade = $8, easiness_factor = $9, relearning_step = $10,
+        lapses = $11, suspended = $12, first_reviewed_at = $13,
+        deck = $14, sibling_group = $15, cloze = $16, cloze_number = $17,
+        variables = $18 where id = $19";
+
+        let values: &[&dyn ToSql] = &[
+            &exercise.created_at,
+            &exercise.due_at,
+            &exercise.description,
+            &exercise.source,
+            &exercise.reference_answer,
+            &exercise.update_interval,
+            &exercise.consecutive_successful_reviews,
+            &exercise.last_grade.map(Grade::as_str),
+            &exercise.easiness_factor,
+            &exercise.relearning_step,
+            &exercise.lapses,
+            &exercise.suspended,
+            &exercise.first_reviewed_at,
+            &exercise.deck,
+            &exercise.sibling_group,
+            &exercise.cloze,
+            &exercise.cloze_number,
+            &template::definitions_to_column(&exercise.variables),
+            &exercise.id.unwrap(),
+        ];
+
+        let rows_affected = conn.execute(query, values)?;
+
+        // replace whatever tags and prerequisites it had before
+        if rows_affected == 1 {
+            conn.execute(
+                "delete from exercise_tags where exercise_id = $1",
+                &[&exercise.id],
+            )?;
+            PostgresStore::insert_tags(conn, exercise.id.unwrap(), &exercise.tags)?;
+
+            conn.execute(
+                "delete from exercise_prerequisites where exercise_id = $1",
+                &[&exercise.id],
+            )?;
+            PostgresStore::insert_prerequisites(
+                conn,
+                exercise.id.unwrap(),
+                &exercise.prerequisites,
+            )?;
+        }
+
+        Ok(rows_affected)
+    }
+}
+
+impl Store for PostgresStore {
+    fn schema_version(&self) -> Result<i32, Box<dyn Error>> {
+        let query = "SELECT EXISTS (
+            SELECT 1
+            FROM   information_schema.tables
+            WHERE  table_schema = 'public'
+            AND    table_name = 'schema_version'
+        )";
+
+        let has_version_table: bool = self.conn.query(query, &[])?.get(0).get(0);
+
+        if !has_version_table {
+            return Ok(0);
+        }
+
+        let query = "select coalesce(max(version), 0) from schema_version";
+
+        Ok(self.conn.query(query, &[])?.get(0).get(0))
+    }
+
+    fn apply_migration(&self, migration: &Migration) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+
+        tx.batch_execute(migration.postgres)?;
+        tx.execute(
+            "insert into schema_version(version) values($1)",
+            &[&migration.version],
+        )?;
+
+        Ok(tx.commit()?)
+    }
+
+    fn drop_schema(&self) -> Result<(), Box<dyn Error>> {
+        self.conn.batch_execute(
+            "drop table if exists exercise_prerequisites cascade;
+            drop table if exists exercise_tags cascade;
+            drop table if exists reviews cascade;
+            drop table if exists exercises cascade;
+            drop table if exists schema_version cascade;",
+        )?;
+
+        Ok(())
+    }
+
+    fn get_by_pk(&self, pk: i32) -> Option<Exercise> {
+        let query = format!(
+            "
+        SELECT
+            {}
+        FROM
+            exercises
+        WHERE
+            id = $1
+        ",
+            sql_column_list()
+        );
+
+        self.query_exercises(&query, &[&pk]).into_iter().next()
+    }
+
+    fn get_due(&self, today: NaiveDate) -> Vec<Exercise> {
+        let due_query = format!(
+            "
+        SELECT
+            {}
+        FROM
+            exercises
+        WHERE
+            due_at <= $1
+            and not suspended
+        ORDER BY
+            due_at desc,
+            id desc",
+            sql_column_list()
+        );
+
+        self.query_exercises(&due_query, &[&today])
+    }
+
+    fn get_all_by_due_date_desc(&self) -> Vec<Exercise> {
+        let due_query = format!(
+            "
+        SELECT
+            {}
+        FROM
+            exercises
+        ORDER BY
+            due_at desc,
+            id desc",
+            sql_column_list()
+        );
+
+        self.query_exercises(&due_query, &[])
+    }
+
+    fn grep(&self, query_string: &str) -> Vec<Exercise> {
+        let grep_query = format!(
+            "
+        SELECT
+            {}
+        FROM
+            exercises
+        WHERE
+            description like ('%' || $1 || '%')
+            or source like ('%' || $1 || '%')
+            or reference_answer like ('%' || $1 || '%')
+            or id::text like ('%' || $1 || '%')
+        ORDER BY
+            due_at desc,
+            id desc",
+            sql_column_list()
+        );
+
+        self.query_exercises(&grep_query, &[&query_string])
+    }
+
+    fn get_siblings(&self, sibling_group: &str) -> Vec<Exercise> {
+        let query = format!(
+            "
+        SELECT
+            {}
+        FROM
+            exercises
+        WHERE
+            sibling_group = $1
+        ORDER BY
+            due_at desc,
+            id desc",
+            sql_column_list()
+        );
+
+        self.query_exercises(&query, &[&sibling_group])
+    }
+
+    fn save(&self, exercises: &[Exercise]) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+
+        // if this fails, dropping tx without committing rolls everything back
+        PostgresStore::create_all(&tx, exercises)?;
+
+        Ok(tx.commit()?)
+    }
+
+    fn update(&self, exercise: &Exercise) -> Result<u64, Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+
+        let rows_affected = PostgresStore::update_exercise(&tx, exercise)?;
+
+        tx.commit()?;
+
+        Ok(rows_affected)
+    }
+
+    fn update_all(&self, exercises: &[Exercise]) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+
+        for exercise in exercises {
+            PostgresStore::update_exercise(&tx, exercise)?;
+        }
+
+        Ok(tx.commit()?)
+    }
+
+    fn delete(&self, pk: i32) -> Result<(), Box<dyn Error>> {
+        PostgresStore::delete_exercise(&self.conn, pk)
+    }
+
+    fn replace_siblings(
+        &self,
+        updated: &[Exercise],
+        new: &[Exercise],
+        deleted: &[i32],
+    ) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+
+        // deleted first, so their descriptions are free for the others
+        for pk in deleted {
+            PostgresStore::delete_exercise(&tx, *pk)?;
+        }
+        for exercise in updated {
+            PostgresStore::update_exercise(&tx, exercise)?;
+        }
+        PostgresStore::create_all(&tx, new)?;
+
+        Ok(tx.commit()?)
+    }
+
+    fn schedule(&self) -> Vec<(NaiveDate, i32)> {
+        let query = "select due_at, count(*)::integer from exercises where not suspended
+            group by due_at order by due_at";
+
+        let mut counts = vec![];
+
+        for row in &self.conn.query(query, &[]).unwrap() {
+            counts.push((row.get(0), row.get(1)));
+        }
+
+        counts
+    }
+
+    fn schedule_between(
+        &self,
+        first: NaiveDate,
+        last: NaiveDate,
+        excluding: i32,
+    ) -> Vec<(NaiveDate, i32)> {
+        let query = "select due_at, count(*)::integer from exercises where not suspended
+            and due_at between $1 and $2 and id <> $3
+            group by due_at order by due_at";
+
+        let mut counts = vec![];
+
+        for row in &self
+            .conn
+            .query(query, &[&first, &last, &excluding])
+            .unwrap()
+        {
+            counts.push((row.get(0), row.get(1)));
+        }
+
+        counts
+    }
+
+    fn stats(&self) -> Option<(i32, NaiveDate)> {
+        // need the explicit ::integer cast to let rust-postgres convert the type
+        let query = "select count(*)::integer, min(created_at) from exercises";
+
+        let rows = self.conn.query(query, &[]).unwrap();
+        let row = rows.get(0);
+
+        // min() is null when there are no exercises
+        let earliest: Option<NaiveDate> = row.get(1);
+        earliest.map(|earliest| (row.get(0), earliest))
+    }
+
+    fn count_due(&self, today: NaiveDate) -> Option<i32> {
+        let query = "select count(*)::integer from exercises where due_at <= $1 and not suspended";
+
+        self.conn
+            .query(query, &[&today])
+            .unwrap()
+            .iter()
+            .next()
+            .map(|row| row.get(0))
+    }
+
+    fn save_review(&self, exercise: &Exercise, review: &Review) -> Result<i32, Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+
+        PostgresStore::update_exercise(&tx, exercise)?;
+
+        let values: &[&dyn ToSql] = &[
+            &review.exercise_id,
+            &review.reviewed_at,
+            &review.outcome.as_str(),
+            &review.time_spent_seconds,
+            &review.interval_before,
+            &review.interval_after,
+            &review.lapse,
+            &review.relearning_step,
+        ];
+
+        let rows = tx.query(
+            "insert into reviews(exercise_id, reviewed_at, outcome, time_spent_seconds,
+            interval_before, interval_after, lapse, relearning_step)
+            values($1, $2, $3, $4, $5, $6, $7, $8) returning id",
+            values,
+        )?;
+        let review_id = rows.get(0).get(0);
+
+        tx.commit()?;
+
+        Ok(review_id)
+    }
+
+    fn undo_review(&self, exercise: &Exercise, review_id: i32) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+
+        let rows_affected = tx.execute(
+            "delete from reviews where id = $1 and exercise_id = $2",
+            &[&review_id, &exercise.id],
+        )?;
+
+        if rows_affected != 1 {
+            return Err(make_error(format!(
+                "Review {} of exercise {} does not exist",
+                review_id,
+                exercise.id.unwrap_or(-1)
+            )));
+        }
+
+        PostgresStore::update_exercise(&tx, exercise)?;
+
+        Ok(tx.commit()?)
+    }
+
+    fn get_reviews(&self, exercise_id: i32) -> Vec<Review> {
+        let query = "select id, exercise_id, reviewed_at, outcome, time_spent_seconds,
+            interval_before, interval_after, lapse, relearning_step from reviews
+            where exercise_id = $1
+            order by reviewed_at, id";
+
+        let mut reviews = vec![];
+
+        for row in &self.conn.query(query, &[&exercise_id]).unwrap() {
+            reviews.push(review_from_row(&row));
+        }
+
+        reviews
+    }
+}
@@ -0,0 +1,837 @@
+use chrono::NaiveDate;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Row, NO_PARAMS};
+use std::error::Error;
+use std::path::Path;
+
+use crate::migrations::Migration;
+use crate::store::Store;
+use crate::template;
+use crate::{make_error, Exercise, Grade, Prerequisite, Review};
+
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+fn exercise_from_row(row: &Row) -> rusqlite::Result<Exercise> {
+    Ok(Exercise {
+        id: Some(row.get(0)?),
+        created_at: row.get(1)?,
+        due_at: row.get(2)?,
+        description: row.get(3)?,
+        source: row.get(4)?,
+        reference_answer: row.get(5)?,
+        tags: tags_from_column(row.get(15)?),
+        update_interval: row.get(6)?,
+        consecutive_successful_reviews: row.get(7)?,
+        last_grade: row
+            .get::<_, Option<String>>(8)?
+            .map(|grade| grade.parse().unwrap()),
+        easiness_factor: row.get(9)?,
+        relearning_step: row.get(10)?,
+        lapses: row.get(11)?,
+        suspended: row.get(12)?,
+        first_reviewed_at: row.get(13)?,
+        deck: row.get(14)?,
+        prerequisites: prerequisites_from_column(row.get(16)?),
+        sibling_group: row.get(17)?,
+        cloze: row.get(18)?,
+        cloze_number: row.get(19)?,
+        variables: template::definitions_from_column(row.get(20)?),
+    })
+}
+
+// group_concat is null when there are no tags and doesn't promise any order.
+// tags can't contain spaces, so splitting on them is safe.
+fn tags_from_column(tags: Option<String>) -> Vec<String> {
+    let mut tags = tags
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    tags.sort();
+    tags
+}
+
+fn prerequisites_from_column(prerequisites: Option<String>) -> Vec<Prerequisite> {
+    let mut ids = prerequisites
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(|id| id.parse().unwrap())
+        .collect::<Vec<i32>>();
+    ids.sort();
+    ids.into_iter().map(Prerequisite::Id).collect()
+}
+
+fn review_from_row(row: &Row) -> rusqlite::Result<Review> {
+    let outcome: String = row.get(3)?;
+
+    Ok(Review {
+        id: Some(row.get(0)?),
+        exercise_id: row.get(1)?,
+        reviewed_at: row.get(2)?,
+        outcome: outcome.parse().unwrap(),
+        time_spent_seconds: row.get(4)?,
+        interval_before: row.get(5)?,
+        interval_after: row.get(6)?,
+        lapse: row.get(7)?,
+        relearning_step: row.get(8)?,
+    })
+}
+
+fn sql_column_list() -> &'static str {
+    "id, created_at, due_at, description, source, reference_answer, update_interval,
+    consecutive_successful_reviews, last_grade, easiness_factor, relearning_step, lapses,
+    suspended, first_reviewed_at, deck,
+    (select group_concat(tag, ' ') from exercise_tags where exercise_id = exercises.id),
+    (select group_concat(prerequisite_id, ' ') from exercise_prerequisites
+        where exercise_id = exercises.id),
+    sibling_group, cloze, cloze_number, variables"
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<SqliteStore, Box<dyn Error>> {
+        SqliteStore::from_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<SqliteStore, Box<dyn Error>> {
+        SqliteStore::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<SqliteStore, Box<dyn Error>> {
+        // sqlite leaves foreign key enforcement off unless asked, per connection
+        conn.execute_batch("pragma foreign_keys = on")?;
+
+        Ok(SqliteStore { conn })
+    }
+
+    fn query_exercises(&self, query: &str, params: &[&dyn ToSql]) -> Vec<Exercise> {
+        let mut statement = self.conn.prepare(query).unwrap();
+
+        statement
+            .query_map(params, exercise_from_row)
+            .unwrap()
+            .map(|exercise| exercise.unwrap())
+            .collect()
+    }
+
+    // no transaction of its own, so it can be part of a bigger one
+    fn update_exercise(&self, exercise: &Exercise) -> Result<u64, Box<dyn Error>> {
+        if exercise.id.is_none() {
+            return Err(make_error("Cannot insert, has no PK".to_string()));
+        }
+
+        let query =
+            "update exercises set created_at = ?1, due_at = ?2, description = ?3, source = ?4,
+        reference_answer = ?5, update_interval = ?6, consecutive_successful_reviews = ?7,
+        last_grade = ?8, easiness_factor = ?9, relearning_step = ?10,
+        lapses = ?11, suspended = ?12, first_reviewed_at = ?13,
+        deck = ?14, sibling_group = ?15, cloze = ?16, cloze_number = ?17,
+        variables = ?18 where id = ?19";
+
+        let values: &[&dyn ToSql] = &[
+            &exercise.created_at,
+            &exercise.due_at,
+            &exercise.description,
+            &exercise.source,
+            &exercise.reference_answer,
+            &exercise.update_interval,
+            &exercise.consecutive_successful_reviews,
+            &exercise.last_grade.map(Grade::as_str),
+            &exercise.easiness_factor,
+            &exercise.relearning_step,
+            &exercise.lapses,
+            &exercise.suspended,
+            &exercise.first_reviewed_at,
+            &exercise.deck,
+            &exercise.sibling_group,
+            &exercise.cloze,
+            &exercise.cloze_number,
+            &template::definitions_to_column(&exercise.variables),
+            &exercise.id.unwrap(),
+        ];
+
+        let rows_affected = self.conn.execute(query, values)?;
+
+        // replace whatever tags and prerequisites it had before
+        if rows_affected == 1 {
+            self.conn.execute(
+                "delete from exercise_tags where exercise_id = ?1",
+                &[&exercise.id],
+            )?;
+            self.insert_tags(exercise.id.unwrap(), &exercise.tags)?;
+
+            self.conn.execute(
+                "delete from exercise_prerequisites where exercise_id = ?1",
+                &[&exercise.id],
+            )?;
+            self.insert_prerequisites(exercise.id.unwrap(), &exercise.prerequisites)?;
+        }
+
+        Ok(rows_affected as u64)
+    }
+
+    // rusqlite's Transaction wants a &mut Connection, so manage it by hand
+    fn in_transaction<T, F>(&self, f: F) -> Result<T, Box<dyn Error>>
+    where
+        F: FnOnce() -> Result<T, Box<dyn Error>>,
+    {
+        self.conn.execute_batch("begin")?;
+
+        match f() {
+            Ok(result) => {
+                self.conn.execute_batch("commit")?;
+                Ok(result)
+            }
+            Err(e) => {
+                self.conn.execute_batch("rollback")?;
+                Err(e)
+            }
+        }
+    }
+
+    fn insert_tags(&self, exercise_id: i32, tags: &[String]) -> Result<(), Box<dyn Error>> {
+        for tag in tags {
+            self.conn.execute(
+                "insert into exercise_tags(exercise_id, tag) values(?1, ?2)",
+                &[&exercise_id as &dyn ToSql, tag],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn insert_prerequisites(
+        &self,
+        exercise_id: i32,
+        prerequisites: &[Prerequisite],
+    ) -> Result<(), Box<dyn Error>> {
+        for prerequisite in prerequisites {
+            let rows_affected = match prerequisite {
+                Prerequisite::Id(id) => self.conn.execute(
+                    "insert into exercise_prerequisites(exercise_id, prerequisite_id)
+                    select ?1, id from exercises where id = ?2",
+                    &[&exercise_id, id],
+                )?,
+                Prerequisite::Description(description) => self.conn.execute(
+                    "insert into exercise_prerequisites(exercise_id, prerequisite_id)
+                    select ?1, id from exercises where description = ?2",
+                    &[&exercise_id as &dyn ToSql, description],
+                )?,
+            };
+
+            if rows_affected != 1 {
+                return Err(make_error(format!(
+                    "Prerequisite {} does not exist",
+                    prerequisite
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn create_all(&self, exercises: &[Exercise]) -> Result<(), Box<dyn Error>> {
+        let mut ids = vec![];
+        for exercise in exercises {
+            ids.push(self.create(exercise)?);
+        }
+
+        // prerequisites can be anywhere in the batch, so they go in once
+        // every exercise has an ID
+        for (id, exercise) in ids.into_iter().zip(exercises) {
+            self.insert_prerequisites(id, &exercise.prerequisites)?;
+        }
+
+        Ok(())
+    }
+
+    fn create(&self, exercise: &Exercise) -> Result<i32, Box<dyn Error>> {
+        // exercise was already inserted
+        if exercise.id.is_some() {
+            return Err(make_error("Cannot insert, has PK".to_string()));
+        }
+
+        let values: &[&dyn ToSql] = &[
+            &exercise.created_at,
+            &exercise.due_at,
+            &exercise.description,
+            &exercise.source,
+            &exercise.reference_answer,
+            &exercise.deck,
+            &exercise.sibling_group,
+            &exercise.cloze,
+            &exercise.cloze_number,
+            &template::definitions_to_column(&exercise.variables),
+        ];
+
+        let query = "insert into exercises(created_at, due_at, description, source, reference_answer, deck, sibling_group, cloze, cloze_number, variables) values(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)";
+        self.conn.execute(query, values)?;
+
+        let id = self.conn.last_insert_rowid() as i32;
+        self.insert_tags(id, &exercise.tags)?;
+
+        Ok(id)
+    }
+}
+
+impl Store for SqliteStore {
+    fn schema_version(&self) -> Result<i32, Box<dyn Error>> {
+        let query = "select exists (
+            select 1
+            from   sqlite_master
+            where  type = 'table'
+            and    name = 'schema_version'
+        )";
+
+        let has_version_table: bool = self.conn.query_row(query, NO_PARAMS, |row| row.get(0))?;
+
+        if !has_version_table {
+            return Ok(0);
+        }
+
+        let query = "select coalesce(max(version), 0) from schema_version";
+
+        Ok(self.conn.query_row(query, NO_PARAMS, |row| row.get(0))?)
+    }
+
+    fn apply_migration(&self, migration: &Migration) -> Result<(), Box<dyn Error>> {
+        self.in_transaction(|| {
+            self.conn.execute_batch(migration.sqlite)?;
+            self.conn.execute(
+                "insert into schema_version(version) values(?1)",
+                &[&migration.version],
+            )?;
+
+            Ok(())
+        })
+    }
+
+    fn drop_schema(&self) -> Result<(), Box<dyn Error>> {
+        self.conn.execute_batch(
+            "drop table if exists exercise_prerequisites;
+            drop table if exists exercise_tags;
+            drop table if exists reviews;
+            drop table if exists exercises;
+            drop table if exists schema_version;",
+        )?;
+
+        Ok(())
+    }
+
+    fn get_by_pk(&self, pk: i32) -> Option<Exercise> {
+        let query = format!(
+            "
+        SELECT
+            {}
+        FROM
+            exercises
+        WHERE
+            id = ?1
+        ",
+            sql_column_list()
+        );
+
+        self.query_exercises(&query, &[&pk]).into_iter().next()
+    }
+
+    fn get_due(&self, today: NaiveDate) -> Vec<Exercise> {
+        let due_query = format!(
+            "
+        SELECT
+            {}
+        FROM
+            exercises
+        WHERE
+            due_at <= ?1
+            and not suspended
+        ORDER BY
+            due_at desc,
+            id desc",
+            sql_column_list()
+        );
+
+        self.query_exercises(&due_query, &[&today])
+    }
+
+    fn get_all_by_due_date_desc(&self) -> Vec<Exercise> {
+        let due_query = format!(
+            "
+        SELECT
+            {}
+        FROM
+            exercises
+        ORDER BY
+            due_at desc,
+            id desc",
+            sql_column_list()
+        );
+
+        self.query_exercises(&due_query, &[])
+    }
+
+    fn grep(&self, query_string: &str) -> Vec<Exercise> {
+        // instr rather than like, since sqlite's like is case insensitive and
+        // postgres' isn't
+        let grep_query = format!(
+            "
+        SELECT
+            {}
+        FROM
+            exercises
+        WHERE
+            instr(description, ?1) > 0
+            or instr(source, ?1) > 0
+            or instr(reference_answer, ?1) > 0
+            or instr(cast(id as text), ?1) > 0
+        ORDER BY
+            due_at desc,
+            id desc",
+            sql_column_list()
+        );
+
+        self.query_exercises(&grep_query, &[&query_string])
+    }
+
+    fn get_siblings(&self, sibling_group: &str) -> Vec<Exercise> {
+        let query = format!(
+            "
+        SELECT
+            {}
+        FROM
+            exercises
+        WHERE
+            sibling_group = ?1
+        ORDER BY
+            due_at desc,
+            id desc",
+            sql_column_list()
+        );
+
+        self.query_exercises(&query, &[&sibling_group])
+    }
+
+    fn save(&self, exercises: &[Exercise]) -> Result<(), Box<dyn Error>> {
+        self.in_transaction(|| self.create_all(exercises))
+    }
+
+    fn update(&self, exercise: &Exercise) -> Result<u64, Box<dyn Error>> {
+        self.in_transaction(|| self.update_exercise(exercise))
+    }
+
+    fn update_all(&self, exercises: &[Exercise]) -> Result<(), Box<dyn Error>> {
+        self.in_transaction(|| {
+            for exercise in exercises {
+                self.update_exercise(exercise)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn delete(&self, pk: i32) -> Result<(), Box<dyn Error>> {
+        let rows_affected = self
+            .conn
+            .execute("delete from exercises where id = ?1", &[&pk])?;
+
+        if rows_affected == 1 {
+            Ok(())
+        } else {
+            Err(make_error(format!(
+                "Expected 1 row to be affected, got {} instead",
+                rows_affected
+            )))
+        }
+    }
+
+    fn replace_siblings(
+        &self,
+        updated: &[Exercise],
+        new: &[Exercise],
+        deleted: &[i32],
+    ) -> Result<(), Box<dyn Error>> {
+        self.in_transaction(|| {
+            // deleted first, so their descriptions are free for the others
+            for pk in deleted {
+                self.delete(*pk)?;
+            }
+            for exercise in updated {
+                self.update_exercise(exercise)?;
+            }
+            self.create_all(new)
+        })
+    }
+
+    fn schedule(&self) -> Vec<(NaiveDate, i32)> {
+        let query = "select due_at, count(*) from exercises where not suspended
+            group by due_at order by due_at";
+
+        let mut statement = self.conn.prepare(query).unwrap();
+
+        statement
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|count| count.unwrap())
+            .collect()
+    }
+
+    fn schedule_between(
+        &self,
+        first: NaiveDate,
+        last: NaiveDate,
+        excluding: i32,
+    ) -> Vec<(NaiveDate, i32)> {
+        let query = "select due_at, count(*) from exercises where not suspended
+            and due_at between ?1 and ?2 and id <> ?3
+            group by due_at order by due_at";
+
+        let mut statement = self.conn.prepare(query).unwrap();
+
+        statement
+            .query_map(&[&first as &dyn ToSql, &last, &excluding], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap()
+            .map(|count| count.unwrap())
+            .collect()
+    }
+
+    fn stats(&self) -> Option<(i32, NaiveDate)> {
+        let query = "select count(*), min(created_at) from exercises";
+
+        self.conn
+            .query_row(query, NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+            .ok()
+    }
+
+    fn count_due(&self, today: NaiveDate) -> Option<i32> {
+        let query = "select count(*) from exercises where due_at <= ?1 and not suspended";
+
+        self.conn.query_row(query, &[&today], |row| row.get(0)).ok()
+    }
+
+    fn save_review(&self, exercise: &Exercise, review: &Review) -> Result<i32, Box<dyn Error>> {
+        self.in_transaction(|| {
+            self.update_exercise(exercise)?;
+
+            let values: &[&dyn ToSql] = &[
+                &review.exercise_id,
+                &review.reviewed_at,
+                &review.outcome.as_str(),
+                &review.time_spent_seconds,
+                &review.interval_before,
+                &review.interval_after,
+                &review.lapse,
+                &review.relearning_step,
+            ];
+
+            self.conn.execute(
+                "insert into reviews(exercise_id, reviewed_at, outcome, time_spent_seconds,
+                interval_before, interval_after, lapse, relearning_step)
+            values(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                values,
+            )?;
+
+            Ok(self.conn.last_insert_rowid() as i32)
+        })
+    }
+
+    fn undo_review(&self, exercise: &Exercise, review_id: i32) -> Result<(), Box<dyn Error>> {
+        self.in_transaction(|| {
+            let rows_affected = self.conn.execute(
+                "delete from reviews where id = ?1 and exercise_id = ?2",
+                &[&review_id as &dyn ToSql, &exercise.id],
+            )?;
+
+            if rows_affected != 1 {
+                return Err(make_error(format!(
+                    "Review {} of exercise {} does not exist",
+                    review_id,
+                    exercise.id.unwrap_or(-1)
+                )));
+            }
+
+            self.update_exercise(exercise)?;
+
+            Ok(())
+        })
+    }
+
+    fn get_reviews(&self, exercise_id: i32) -> Vec<Review> {
+        let query = "select id, exercise_id, reviewed_at, outcome, time_spent_seconds,
+            interval_before, interval_after, lapse, relearning_step from reviews
+            where exercise_id = ?1
+            order by reviewed_at, id";
+
+        let mut statement = self.conn.prepare(query).unwrap();
+
+        statement
+            .query_map(&[&exercise_id], review_from_row)
+            .unwrap()
+            .map(|review| review.unwrap())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations::MIGRATIONS;
+    use chrono::Duration;
+
+    fn new_test_store() -> SqliteStore {
+        let store = SqliteStore::open_in_memory().unwrap();
+        for migration in MIGRATIONS {
+            store.apply_migration(migration).unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn test_sqlite_migrating_and_dropping() {
+        let store = SqliteStore::open_in_memory().unwrap();
+
+        assert_eq!(store.schema_version().unwrap(), 0);
+
+        for migration in MIGRATIONS {
+            store.apply_migration(migration).unwrap();
+            assert_eq!(store.schema_version().unwrap(), migration.version);
+        }
+
+        // a migration is only ever recorded once
+        assert!(store.apply_migration(&MIGRATIONS[0]).is_err());
+
+        store.drop_schema().unwrap();
+        assert_eq!(store.schema_version().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_sqlite_migrating_pre_migration_database() {
+        let store = SqliteStore::open_in_memory().unwrap();
+
+        // what bootstrap_schema used to create, before schema_version existed
+        store
+            .conn
+            .execute_batch(
+                "create table exercises(
+                id integer primary key autoincrement,
+                created_at text not null default current_date,
+                description text unique not null,
+                source text not null,
+                reference_answer text not null,
+                due_at text not null default current_date,
+                update_interval integer not null default 0,
+                consecutive_successful_reviews integer not null default 0
+            );
+
+            insert into exercises(description, source, reference_answer)
+            values('foo', 'bar', 'baz')",
+            )
+            .unwrap();
+
+        assert_eq!(store.schema_version().unwrap(), 0);
+
+        for migration in MIGRATIONS {
+            store.apply_migration(migration).unwrap();
+        }
+
+        assert_eq!(store.get_all_by_due_date_desc().len(), 1);
+    }
+
+    #[test]
+    fn test_sqlite_migrating_exercises_studied_before_reviews() {
+        let store = SqliteStore::open_in_memory().unwrap();
+
+        store.apply_migration(&MIGRATIONS[0]).unwrap();
+
+        // studied before there was a reviews table to record it in
+        store
+            .conn
+            .execute_batch(
+                "insert into exercises(description, source, reference_answer, created_at,
+                update_interval, consecutive_successful_reviews)
+                values('foo 1', 'bar', 'baz', '2019-05-01', 3, 2),
+                ('foo 2', 'bar', 'baz', '2019-05-01', 0, 0)",
+            )
+            .unwrap();
+
+        for migration in &MIGRATIONS[1..] {
+            store.apply_migration(migration).unwrap();
+        }
+
+        assert_eq!(
+            store.get_by_pk(1).unwrap().first_reviewed_at,
+            Some(NaiveDate::from_ymd(2019, 5, 1))
+        );
+        assert!(store.get_by_pk(2).unwrap().is_new());
+    }
+
+    #[test]
+    fn test_sqlite_migrating_correct_incorrect_reviews() {
+        let store = SqliteStore::open_in_memory().unwrap();
+
+        for migration in &MIGRATIONS[..2] {
+            store.apply_migration(migration).unwrap();
+        }
+
+        // saved the way the schema at the time would have
+        store
+            .conn
+            .execute_batch(
+                "insert into exercises(description, source, reference_answer)
+                values('foo', 'bar', 'baz');
+
+                insert into reviews(exercise_id, reviewed_at, outcome, time_spent_seconds,
+                interval_before, interval_after)
+                values(1, '2019-06-01T10:00:00Z', 'correct', 10, 0, 1),
+                (1, '2019-06-02T10:00:00Z', 'incorrect', 10, 1, 0)",
+            )
+            .unwrap();
+
+        for migration in &MIGRATIONS[2..] {
+            store.apply_migration(migration).unwrap();
+        }
+
+        let grades = store
+            .get_reviews(1)
+            .iter()
+            .map(|r| r.grade().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(grades, vec![Grade::Good, Grade::Again]);
+
+        assert_eq!(store.get_by_pk(1).unwrap().last_grade, Some(Grade::Again));
+    }
+
+    #[test]
+    fn test_sqlite_save_update_and_query() {
+        let store = new_test_store();
+
+        let today = chrono::Local::today().naive_local();
+
+        store
+            .save(&[
+                Exercise::new("foo", "bar", "baz"),
+                Exercise::new("Foo 2", "bar 2", "baz 2"),
+            ])
+            .unwrap();
+
+        assert_eq!(store.stats(), Some((2, today)));
+        assert_eq!(store.count_due(today), Some(2));
+
+        let saved_exercises = store.get_all_by_due_date_desc();
+        assert_eq!(saved_exercises.len(), 2);
+        assert_eq!(saved_exercises[0].id, Some(2));
+        assert_eq!(saved_exercises[0].description, "Foo 2");
+        assert_eq!(saved_exercises[1].id, Some(1));
+        assert_eq!(saved_exercises[1].description, "foo");
+
+        let mut exercise = store.get_by_pk(1).unwrap();
+        assert_eq!(exercise.created_at, today);
+        assert_eq!(exercise.source, "bar");
+        assert_eq!(exercise.reference_answer, "baz");
+
+        exercise.due_at += Duration::days(1);
+        exercise.update_interval = 1;
+        exercise.consecutive_successful_reviews = 1;
+        exercise.easiness_factor = 1.5;
+        exercise.first_reviewed_at = Some(today);
+        assert_eq!(store.update(&exercise).unwrap(), 1);
+
+        let exercise = store.get_by_pk(1).unwrap();
+        assert_eq!(exercise.due_at, today + Duration::days(1));
+        assert_eq!(exercise.update_interval, 1);
+        assert_eq!(exercise.consecutive_successful_reviews, 1);
+        assert_eq!(exercise.easiness_factor, 1.5);
+        assert_eq!(exercise.first_reviewed_at, Some(today));
+        assert!(store.get_by_pk(2).unwrap().first_reviewed_at.is_none());
+
+        let due = store.get_due(today);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].id, Some(2));
+        assert_eq!(store.count_due(today), Some(1));
+
+        assert_eq!(
+            store.schedule(),
+            vec![(today, 1), (today + Duration::days(1), 1)]
+        );
+        assert_eq!(
+            store.schedule_between(today, today + Duration::days(1), 2),
+            vec![(today + Duration::days(1), 1)]
+        );
+        assert_eq!(
+            store.schedule_between(today + Duration::days(1), today + Duration::days(7), 3),
+            vec![(today + Duration::days(1), 1)]
+        );
+        assert!(store.schedule_between(today, today, 2).is_empty());
+
+        // grep is case sensitive, like it is with postgres
+        assert_eq!(store.grep("foo").len(), 1);
+        assert_eq!(store.grep("Foo").len(), 1);
+        assert_eq!(store.grep("bar").len(), 2);
+        assert_eq!(store.grep("2")[0].id, Some(2));
+        assert_eq!(store.grep("blah").len(), 0);
+
+        // suspended exercises are never due
+        let mut exercise = store.get_by_pk(2).unwrap();
+        exercise.lapses = 3;
+        exercise.suspended = true;
+        store.update(&exercise).unwrap();
+
+        let exercise = store.get_by_pk(2).unwrap();
+        assert_eq!(exercise.lapses, 3);
+        assert!(exercise.suspended);
+        assert!(store.get_due(today).is_empty());
+        assert_eq!(store.count_due(today), Some(0));
+        assert_eq!(store.schedule(), vec![(today + Duration::days(1), 1)]);
+        assert_eq!(store.get_all_by_due_date_desc().len(), 2);
+
+        assert!(store.delete(1).is_ok());
+        assert!(store.delete(1).is_err());
+        assert!(store.get_by_pk(1).is_none());
+
+        // IDs aren't reused after a delete
+        store.save(&[Exercise::new("quux", "bar", "baz")]).unwrap();
+        assert!(store.get_by_pk(3).is_some());
+    }
+
+    #[test]
+    fn test_sqlite_deleting_tags() {
+        let store = new_test_store();
+
+        let mut exercise = Exercise::new("foo", "bar", "baz");
+        exercise.tags = vec!["b".to_string(), "a/c".to_string()];
+        store.save(&[exercise]).unwrap();
+
+        // deleting an exercise deletes its tags
+        store.delete(1).unwrap();
+        let tag_cnt: i32 = store
+            .conn
+            .query_row("select count(*) from exercise_tags", NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(tag_cnt, 0);
+    }
+
+    #[test]
+    fn test_sqlite_save_transaction_handling() {
+        let store = new_test_store();
+
+        let result = store.save(&[
+            Exercise::new("foo", "bar", "baz"),
+            // duplicate description
+            Exercise::new("foo", "bar 2", "baz 2"),
+        ]);
+
+        assert_eq!(
+            format!("{}", result.unwrap_err()),
+            "UNIQUE constraint failed: exercises.description"
+        );
+
+        // nothing from the failed batch should have been saved
+        assert!(store.get_all_by_due_date_desc().is_empty());
+
+        store.save(&[Exercise::new("foo", "bar", "baz")]).unwrap();
+        assert_eq!(store.get_all_by_due_date_desc().len(), 1);
+    }
+}
@@ -0,0 +1,598 @@
+use rand::Rng;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+use crate::make_error;
+
+// exercises with variables are templates. each variable is defined by a
+// range like "2..12", which picks a whole number from it, or by an expression
+// like "a * b" that can use the other variables. the description and
+// reference answer fill in "{expression}" with the expression's value, and
+// "{{" and "}}" stand for literal braces.
+//
+// expressions have numbers, variables, + - * / % ^, parentheses and the
+// functions in call() below. everything is a float, printed without the
+// decimal point when it's a whole number.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    Variable(String),
+    Negate(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+enum Definition {
+    Range(Expr, Expr),
+    Value(Expr),
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Parser<'a> {
+        Parser {
+            source,
+            chars: source.chars().collect(),
+            position: 0,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        while self
+            .chars
+            .get(self.position)
+            .is_some_and(|c| c.is_whitespace())
+        {
+            self.position += 1;
+        }
+        self.chars.get(self.position).cloned()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error(&mut self) -> Box<dyn Error> {
+        match self.peek() {
+            Some(c) => make_error(format!("Unexpected '{}' in '{}'.", c, self.source)),
+            None => make_error(format!("'{}' ends too early.", self.source)),
+        }
+    }
+
+    fn expect_end(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.peek() {
+            Some(_) => Err(self.error()),
+            None => Ok(()),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expr, Box<dyn Error>> {
+        let mut expr = self.term()?;
+
+        while let Some(op) = self.peek().filter(|c| *c == '+' || *c == '-') {
+            self.position += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.term()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Expr, Box<dyn Error>> {
+        let mut expr = self.unary()?;
+
+        while let Some(op) = self.peek().filter(|c| *c == '*' || *c == '/' || *c == '%') {
+            self.position += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, Box<dyn Error>> {
+        if self.eat('-') {
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+
+        let base = self.atom()?;
+
+        // right associative, so 2^3^2 is 2^9
+        if self.eat('^') {
+            return Ok(Expr::Binary('^', Box::new(base), Box::new(self.unary()?)));
+        }
+
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr, Box<dyn Error>> {
+        match self.peek() {
+            Some('(') => {
+                self.position += 1;
+                let expr = self.expression()?;
+                if !self.eat(')') {
+                    return Err(self.error());
+                }
+                Ok(expr)
+            }
+            Some(c) if c.is_ascii_digit() => Ok(Expr::Number(self.number()?)),
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let name = self.name();
+                if !self.eat('(') {
+                    return Ok(Expr::Variable(name));
+                }
+
+                let mut args = vec![];
+                if !self.eat(')') {
+                    loop {
+                        args.push(self.expression()?);
+                        if self.eat(')') {
+                            break;
+                        } else if !self.eat(',') {
+                            return Err(self.error());
+                        }
+                    }
+                }
+                Ok(Expr::Call(name, args))
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, Box<dyn Error>> {
+        let start = self.position;
+        while let Some(c) = self.chars.get(self.position) {
+            // stop at the ".." of a range
+            let is_range = *c == '.' && self.chars.get(self.position + 1) == Some(&'.');
+            if !(c.is_ascii_digit() || *c == '.') || is_range {
+                break;
+            }
+            self.position += 1;
+        }
+
+        let number = self.chars[start..self.position].iter().collect::<String>();
+        number
+            .parse()
+            .map_err(|_| make_error(format!("'{}' is not a number.", number)))
+    }
+
+    fn name(&mut self) -> String {
+        let start = self.position;
+        while self
+            .chars
+            .get(self.position)
+            .is_some_and(|c| c.is_alphanumeric() || *c == '_')
+        {
+            self.position += 1;
+        }
+        self.chars[start..self.position].iter().collect()
+    }
+
+    fn definition(&mut self) -> Result<Definition, Box<dyn Error>> {
+        let expr = self.expression()?;
+
+        let definition = if self.eat('.') {
+            if !self.eat('.') {
+                return Err(self.error());
+            }
+            Definition::Range(expr, self.expression()?)
+        } else {
+            Definition::Value(expr)
+        };
+
+        self.expect_end()?;
+        Ok(definition)
+    }
+}
+
+fn parse_expression(source: &str) -> Result<Expr, Box<dyn Error>> {
+    let mut parser = Parser::new(source);
+    let expr = parser.expression()?;
+    parser.expect_end()?;
+    Ok(expr)
+}
+
+fn variables_used<'a>(expr: &'a Expr, names: &mut Vec<&'a str>) {
+    match expr {
+        Expr::Number(_) => {}
+        Expr::Variable(name) => names.push(name),
+        Expr::Negate(expr) => variables_used(expr, names),
+        Expr::Binary(_, left, right) => {
+            variables_used(left, names);
+            variables_used(right, names);
+        }
+        Expr::Call(_, args) => {
+            for arg in args {
+                variables_used(arg, names);
+            }
+        }
+    }
+}
+
+fn call(name: &str, args: &[f64]) -> Result<f64, Box<dyn Error>> {
+    let value = match (name, args) {
+        ("abs", [x]) => x.abs(),
+        ("sqrt", [x]) if *x >= 0.0 => x.sqrt(),
+        ("sqrt", [x]) => {
+            return Err(make_error(format!(
+                "Can't take the square root of {}.",
+                format_number(*x)
+            )))
+        }
+        ("round", [x]) => x.round(),
+        ("floor", [x]) => x.floor(),
+        ("ceil", [x]) => x.ceil(),
+        ("min", [first, rest @ ..]) => rest.iter().fold(*first, |a, b| a.min(*b)),
+        ("max", [first, rest @ ..]) => rest.iter().fold(*first, |a, b| a.max(*b)),
+        ("abs", _) | ("sqrt", _) | ("round", _) | ("floor", _) | ("ceil", _) => {
+            return Err(make_error(format!("{}() takes one argument.", name)))
+        }
+        ("min", _) | ("max", _) => {
+            return Err(make_error(format!(
+                "{}() takes at least one argument.",
+                name
+            )))
+        }
+        _ => return Err(make_error(format!("Unknown function '{}'.", name))),
+    };
+
+    Ok(value)
+}
+
+fn evaluate(expr: &Expr, values: &HashMap<String, f64>) -> Result<f64, Box<dyn Error>> {
+    match expr {
+        Expr::Number(number) => Ok(*number),
+        Expr::Variable(name) => values
+            .get(name)
+            .cloned()
+            .ok_or_else(|| make_error(format!("Unknown variable '{}'.", name))),
+        Expr::Negate(expr) => Ok(-evaluate(expr, values)?),
+        Expr::Binary(op, left, right) => {
+            let left = evaluate(left, values)?;
+            let right = evaluate(right, values)?;
+
+            match op {
+                '+' => Ok(left + right),
+                '-' => Ok(left - right),
+                '*' => Ok(left * right),
+                '/' | '%' if right == 0.0 => Err(make_error("Division by zero.".to_string())),
+                '/' => Ok(left / right),
+                '%' => Ok(left % right),
+                _ => Ok(left.powf(right)),
+            }
+        }
+        Expr::Call(name, args) => {
+            let args = args
+                .iter()
+                .map(|arg| evaluate(arg, values))
+                .collect::<Result<Vec<_>, _>>()?;
+            call(name, &args)
+        }
+    }
+}
+
+// whole numbers without a decimal point and everything else to at most four
+// decimal places
+pub fn format_number(number: f64) -> String {
+    let rounded = (number * 10_000.0).round() / 10_000.0;
+
+    if rounded.fract() == 0.0 {
+        // no "-0"
+        format!("{}", rounded as i64)
+    } else {
+        format!("{:.4}", rounded).trim_end_matches('0').to_string()
+    }
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+// picks a value for every variable, working out the ones that depend on
+// other variables after those
+pub fn instantiate<R: Rng>(
+    definitions: &BTreeMap<String, String>,
+    rng: &mut R,
+) -> Result<HashMap<String, f64>, Box<dyn Error>> {
+    let mut parsed = BTreeMap::new();
+    for (name, source) in definitions {
+        if !is_valid_name(name) {
+            return Err(make_error(format!(
+                "'{}' can't be the name of a variable. Names can only contain letters, numbers and '_', and can't start with a number.",
+                name
+            )));
+        }
+
+        let definition = Parser::new(source)
+            .definition()
+            .map_err(|e| make_error(format!("Variable {}: {}", name, e)))?;
+        parsed.insert(name.as_str(), definition);
+    }
+
+    let mut values = HashMap::new();
+    for name in parsed.keys() {
+        resolve(name, &parsed, &mut values, &mut vec![], rng)?;
+    }
+
+    Ok(values)
+}
+
+fn resolve<'a, R: Rng>(
+    name: &'a str,
+    definitions: &'a BTreeMap<&'a str, Definition>,
+    values: &mut HashMap<String, f64>,
+    path: &mut Vec<&'a str>,
+    rng: &mut R,
+) -> Result<(), Box<dyn Error>> {
+    if values.contains_key(name) {
+        return Ok(());
+    }
+
+    if path.contains(&name) {
+        path.push(name);
+        return Err(make_error(format!(
+            "Variables can't be defined in a circle: {}",
+            path.join(" uses ")
+        )));
+    }
+
+    let (&name, definition) = match definitions.get_key_value(name) {
+        Some(definition) => definition,
+        // reported by evaluate
+        None => return Ok(()),
+    };
+
+    let mut used = vec![];
+    match definition {
+        Definition::Range(low, high) => {
+            variables_used(low, &mut used);
+            variables_used(high, &mut used);
+        }
+        Definition::Value(expr) => variables_used(expr, &mut used),
+    }
+
+    path.push(name);
+    for used_name in used {
+        resolve(used_name, definitions, values, path, rng)?;
+    }
+    path.pop();
+
+    let value = match definition {
+        Definition::Range(low, high) => {
+            let low = evaluate(low, values)?.round() as i64;
+            let high = evaluate(high, values)?.round() as i64;
+            if low > high {
+                return Err(make_error(format!(
+                    "Variable {}: the range {}..{} is empty.",
+                    name, low, high
+                )));
+            }
+            rng.gen_range(low, high + 1) as f64
+        }
+        Definition::Value(expr) => evaluate(expr, values)?,
+    };
+    values.insert(name.to_string(), value);
+
+    Ok(())
+}
+
+// fills in every "{expression}" in the text
+pub fn render(text: &str, values: &HashMap<String, f64>) -> Result<String, Box<dyn Error>> {
+    let mut rendered = String::new();
+    let mut rest = text;
+
+    while let Some(i) = rest.find(['{', '}']) {
+        rendered.push_str(&rest[..i]);
+
+        if rest[i..].starts_with("{{") || rest[i..].starts_with("}}") {
+            rendered.push_str(&rest[i..i + 1]);
+            rest = &rest[i + 2..];
+        } else if rest[i..].starts_with('}') {
+            return Err(make_error(format!(
+                "'}}' without a '{{' before it in '{}'.",
+                text
+            )));
+        } else {
+            let end = match rest[i..].find('}') {
+                Some(end) => i + end,
+                None => {
+                    return Err(make_error(format!(
+                        "'{{' without a '}}' after it in '{}'.",
+                        text
+                    )))
+                }
+            };
+
+            let expr = parse_expression(&rest[i + 1..end])?;
+            rendered.push_str(&format_number(evaluate(&expr, values)?));
+            rest = &rest[end + 1..];
+        }
+    }
+
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+// how the stores keep the definitions, one "name: definition" per line.
+// names can't contain ':', so the first one on each line ends the name.
+pub(crate) fn definitions_to_column(definitions: &BTreeMap<String, String>) -> Option<String> {
+    if definitions.is_empty() {
+        return None;
+    }
+
+    Some(
+        definitions
+            .iter()
+            .map(|(name, source)| format!("{}: {}", name, source))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
+pub(crate) fn definitions_from_column(column: Option<String>) -> BTreeMap<String, String> {
+    column
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let i = line.find(':')?;
+            Some((line[..i].to_string(), line[i + 1..].trim().to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn definitions(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, source)| (name.to_string(), source.to_string()))
+            .collect()
+    }
+
+    fn values(pairs: &[(&str, f64)]) -> HashMap<String, f64> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect()
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let values = values(&[("a", 6.0), ("b", 4.0)]);
+        let eval = |s| evaluate(&parse_expression(s).unwrap(), &values).unwrap();
+
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("a / b"), 1.5);
+        assert_eq!(eval("a % b - -1"), 3.0);
+        assert_eq!(eval("2 ^ 3 ^ 2"), 512.0);
+        assert_eq!(eval("-2 ^ 2"), -4.0);
+        assert_eq!(eval("max(a, b, 10) + min(a, b) + abs(-1)"), 15.0);
+        assert_eq!(
+            eval("sqrt(a * 6) + round(2.5) + floor(1.5) + ceil(1.2)"),
+            12.0
+        );
+
+        let error = |s| {
+            format!(
+                "{}",
+                evaluate(&parse_expression(s).unwrap(), &values).unwrap_err()
+            )
+        };
+        assert_eq!(error("a / (b - 4)"), "Division by zero.");
+        assert_eq!(error("c + 1"), "Unknown variable 'c'.");
+        assert_eq!(error("foo(1)"), "Unknown function 'foo'.");
+        assert_eq!(error("abs(1, 2)"), "abs() takes one argument.");
+        assert_eq!(
+            format!("{}", parse_expression("(1 + 2").unwrap_err()),
+            "'(1 + 2' ends too early."
+        );
+        assert_eq!(
+            format!("{}", parse_expression("1 + * 2").unwrap_err()),
+            "Unexpected '*' in '1 + * 2'."
+        );
+    }
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!(format_number(42.0), "42");
+        assert_eq!(format_number(-3.0), "-3");
+        assert_eq!(format_number(-0.0), "0");
+        assert_eq!(format_number(1.5), "1.5");
+        assert_eq!(format_number(2.0 / 3.0), "0.6667");
+        assert_eq!(format_number(0.1 + 0.2), "0.3");
+    }
+
+    #[test]
+    fn test_instantiate() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let drill = definitions(&[
+            ("product", "a * b"),
+            ("a", "2..12"),
+            ("b", "a..a + 2"),
+            ("c", "5"),
+        ]);
+
+        for _ in 0..100 {
+            let values = instantiate(&drill, &mut rng).unwrap();
+            let (a, b) = (values["a"], values["b"]);
+
+            assert!((2.0..=12.0).contains(&a) && a.fract() == 0.0);
+            assert!(b >= a && b <= a + 2.0);
+            assert_eq!(values["product"], a * b);
+            assert_eq!(values["c"], 5.0);
+        }
+
+        let mut error = |pairs: &[(&str, &str)]| {
+            format!(
+                "{}",
+                instantiate(&definitions(pairs), &mut rng).unwrap_err()
+            )
+        };
+        assert_eq!(
+            error(&[("a", "b + 1"), ("b", "a * 2")]),
+            "Variables can't be defined in a circle: a uses b uses a"
+        );
+        assert_eq!(
+            error(&[("a", "5..1")]),
+            "Variable a: the range 5..1 is empty."
+        );
+        assert_eq!(
+            error(&[("a", "1...5")]),
+            "Variable a: Unexpected '.' in '1...5'."
+        );
+        assert_eq!(error(&[("a", "b")]), "Unknown variable 'b'.");
+        assert_eq!(
+            error(&[("2a", "1")]),
+            "'2a' can't be the name of a variable. Names can only contain letters, numbers and '_', and can't start with a number."
+        );
+    }
+
+    #[test]
+    fn test_definitions_column() {
+        let drill = definitions(&[("a", "2..12"), ("b", "max(a, 3)")]);
+
+        let column = definitions_to_column(&drill);
+        assert_eq!(column.as_deref(), Some("a: 2..12\nb: max(a, 3)"));
+        assert_eq!(definitions_from_column(column), drill);
+
+        assert_eq!(definitions_to_column(&BTreeMap::new()), None);
+        assert!(definitions_from_column(None).is_empty());
+    }
+
+    #[test]
+    fn test_render() {
+        let values = values(&[("a", 3.0), ("b", 4.0)]);
+
+        assert_eq!(
+            render("What is {a} * {b}?", &values).unwrap(),
+            "What is 3 * 4?"
+        );
+        assert_eq!(render("{a * b}", &values).unwrap(), "12");
+        assert_eq!(
+            render("{{a}} stays, {a / b} doesn't", &values).unwrap(),
+            "{a} stays, 0.75 doesn't"
+        );
+
+        let error = |s| format!("{}", render(s, &values).unwrap_err());
+        assert_eq!(error("{a"), "'{' without a '}' after it in '{a'.");
+        assert_eq!(error("a}"), "'}' without a '{' before it in 'a}'.");
+        assert_eq!(error("{c}"), "Unknown variable 'c'.");
+    }
+}